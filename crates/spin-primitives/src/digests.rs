@@ -20,11 +20,93 @@
 //! This implements the digests for AuRa, to allow the private
 //! `CompatibleDigestItem` trait to appear in public interfaces.
 
-use crate::AURA_ENGINE_ID;
-use codec::{Codec, Encode};
+use crate::{AuthorityIndex, SessionIndex, AURA_ENGINE_ID};
+use codec::{Codec, Decode, Encode, Input, Output};
 use sp_consensus_slots::Slot;
 use sp_runtime::generic::DigestItem;
 
+const PRE_DIGEST_V1: u8 = 1;
+
+/// The pre-runtime digest of a block.
+///
+/// All versions start with the encoded slot, so decoders only interested in the slot, such as
+/// `pallet_aura`, read any of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreDigest {
+    /// Only the slot, as written by chains launched before the other fields were added.
+    V0(Slot),
+    /// The slot along with the session the block is authored in and the index of its author.
+    V1 {
+        /// The slot of the block.
+        slot: Slot,
+        /// The session the block is authored in.
+        ///
+        /// That is the session of its parent, or with
+        /// [`SessionMode::Slots`](crate::SessionMode::Slots) the session of the slot if later, see
+        /// [`SessionMode::session_index`](crate::SessionMode::session_index).
+        session_index: SessionIndex,
+        /// Index of the author in the authority set.
+        authority_index: AuthorityIndex,
+    },
+}
+
+impl PreDigest {
+    /// The slot of the block.
+    pub fn slot(&self) -> Slot {
+        match self {
+            Self::V0(slot) | Self::V1 { slot, .. } => *slot,
+        }
+    }
+
+    /// The session and author index claimed by the block, `None` for [`PreDigest::V0`].
+    pub fn session_and_author(&self) -> Option<(SessionIndex, AuthorityIndex)> {
+        match self {
+            Self::V0(_) => None,
+            Self::V1 {
+                session_index,
+                authority_index,
+                ..
+            } => Some((*session_index, *authority_index)),
+        }
+    }
+}
+
+impl Encode for PreDigest {
+    fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+        match self {
+            Self::V0(slot) => slot.encode_to(dest),
+            Self::V1 {
+                slot,
+                session_index,
+                authority_index,
+            } => {
+                slot.encode_to(dest);
+                PRE_DIGEST_V1.encode_to(dest);
+                session_index.encode_to(dest);
+                authority_index.encode_to(dest);
+            }
+        }
+    }
+}
+
+impl Decode for PreDigest {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+        let slot = Slot::decode(input)?;
+        if input.remaining_len()? == Some(0) {
+            return Ok(Self::V0(slot));
+        }
+
+        match u8::decode(input)? {
+            PRE_DIGEST_V1 => Ok(Self::V1 {
+                slot,
+                session_index: Decode::decode(input)?,
+                authority_index: Decode::decode(input)?,
+            }),
+            _ => Err("Unknown pre-digest version".into()),
+        }
+    }
+}
+
 /// A digest item which is usable with aura consensus.
 pub trait CompatibleDigestItem<Signature>: Sized {
     /// Construct a digest item which contains a signature on the hash.
@@ -33,11 +115,17 @@ pub trait CompatibleDigestItem<Signature>: Sized {
     /// If this item is an Aura seal, return the signature.
     fn as_aura_seal(&self) -> Option<Signature>;
 
-    /// Construct a digest item which contains the slot number
+    /// Construct a digest item which contains the slot number, i.e. a [`PreDigest::V0`].
     fn aura_pre_digest(slot: Slot) -> Self;
 
-    /// If this item is an AuRa pre-digest, return the slot number
+    /// If this item is an AuRa pre-digest of any version, return the slot number
     fn as_aura_pre_digest(&self) -> Option<Slot>;
+
+    /// Construct a digest item which contains the given pre-digest.
+    fn aura_versioned_pre_digest(pre_digest: PreDigest) -> Self;
+
+    /// If this item is an AuRa pre-digest, return it.
+    fn as_aura_versioned_pre_digest(&self) -> Option<PreDigest>;
 }
 
 impl<Signature> CompatibleDigestItem<Signature> for DigestItem
@@ -57,6 +145,52 @@ where
     }
 
     fn as_aura_pre_digest(&self) -> Option<Slot> {
+        self.as_aura_versioned_pre_digest()
+            .map(|pre_digest| pre_digest.slot())
+    }
+
+    fn aura_versioned_pre_digest(pre_digest: PreDigest) -> Self {
+        DigestItem::PreRuntime(AURA_ENGINE_ID, pre_digest.encode())
+    }
+
+    fn as_aura_versioned_pre_digest(&self) -> Option<PreDigest> {
         self.pre_runtime_try_to(&AURA_ENGINE_ID)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sr25519::AuthoritySignature;
+
+    #[test]
+    fn pre_digests_start_with_the_slot() {
+        let v1 = PreDigest::V1 {
+            slot: 7.into(),
+            session_index: 3,
+            authority_index: 1,
+        };
+
+        for pre_digest in [PreDigest::V0(7.into()), v1] {
+            let item =
+                <DigestItem as CompatibleDigestItem<AuthoritySignature>>::aura_versioned_pre_digest(
+                    pre_digest,
+                );
+            assert_eq!(
+                CompatibleDigestItem::<AuthoritySignature>::as_aura_versioned_pre_digest(&item),
+                Some(pre_digest)
+            );
+            assert_eq!(
+                CompatibleDigestItem::<AuthoritySignature>::as_aura_pre_digest(&item),
+                Some(7.into())
+            );
+
+            let encoded = pre_digest.encode();
+            assert_eq!(Slot::decode(&mut &encoded[..]).ok(), Some(7.into()));
+        }
+
+        let mut unknown = v1.encode();
+        unknown[8] = 2;
+        assert!(PreDigest::decode(&mut &unknown[..]).is_err());
+    }
+}
@@ -0,0 +1,635 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verification of a chain of headers without access to the state.
+//!
+//! Starting from a trusted [`Checkpoint`], headers are verified one after the other against the
+//! session state derived from the [`ConsensusLog`] digests of their ancestors, the same way the
+//! client follows sessions on import. This lets light clients and bridges follow the chain.
+
+use crate::{
+    digests::{CompatibleDigestItem, PreDigest},
+    session_author_index, AuthorityIndex, ConsensusLog, Fallback, SessionIndex, SessionMode, Slot,
+    AURA_ENGINE_ID,
+};
+use alloc::vec::Vec;
+use codec::{Codec, Decode, Encode};
+use scale_info::TypeInfo;
+use sp_application_crypto::RuntimeAppPublic;
+use sp_runtime::traits::{AtLeast32BitUnsigned, Header, One, Saturating};
+
+/// A trusted header along with the session state its children are verified against.
+#[derive(Decode, Encode, Debug, Clone, PartialEq, Eq, TypeInfo)]
+pub struct Checkpoint<Hash, Number, AuthorityId> {
+    /// Hash of the header.
+    pub hash: Hash,
+    /// Number of the header.
+    pub number: Number,
+    /// Slot of the header, `None` for the genesis block.
+    pub slot: Option<Slot>,
+    /// The authority set.
+    pub authorities: Vec<AuthorityId>,
+    /// The current session index.
+    pub session_index: SessionIndex,
+    /// Authorities disabled for the rest of the session.
+    pub disabled: Vec<AuthorityIndex>,
+    /// Number of the block the current session started at.
+    pub session_start: Number,
+    /// Number of blocks in the current session.
+    pub session_length: Number,
+    /// The fallback timeout of the runtime, see `AuraApi::fallback_timeout`.
+    pub fallback_timeout: Option<u64>,
+    /// How sessions move on, see `AuraApi::session_mode`.
+    pub session_mode: SessionMode,
+    /// Session the header was authored in and the fallback offset of its author, see
+    /// `AuraApi::fallback_offset`.
+    pub fallback_offset: (SessionIndex, u64),
+}
+
+/// Errors in header chain verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The header is not a child of the checkpoint.
+    NotAChild,
+    /// The header has no seal digest.
+    Unsealed,
+    /// The header has a malformed seal.
+    BadSeal,
+    /// The header has no slot pre-digest.
+    NoPreDigest,
+    /// The header has more than one slot pre-digest.
+    MultiplePreDigests,
+    /// The slot of the header is not after the slot of its parent.
+    SlotNotIncreasing {
+        /// Slot of the header.
+        slot: Slot,
+        /// Slot of the parent.
+        parent_slot: Slot,
+    },
+    /// The session or author index in the pre-digest are not the expected ones.
+    PreDigestMismatch {
+        /// Session and author index of the slot author.
+        expected: (SessionIndex, AuthorityIndex),
+        /// Session and author index claimed by the pre-digest.
+        claimed: (SessionIndex, AuthorityIndex),
+    },
+    /// No slot author found, i.e. there is no enabled authority.
+    SlotAuthorNotFound,
+    /// The header is not signed by the slot author.
+    BadSignature,
+    /// The header does not start the session it should, or starts one it should not.
+    InvalidSessionChange {
+        /// Index of the session the header should start.
+        expected: Option<SessionIndex>,
+        /// Index of the session the header starts.
+        found: Option<SessionIndex>,
+    },
+    /// The header changes the session length outside of a session boundary, or to zero.
+    InvalidSessionLengthChange(u32),
+    /// The header changes the fallback timeout outside of a session boundary.
+    InvalidFallbackTimeoutChange(Option<u64>),
+    /// The header changes the session mode outside of a session boundary, or to empty sessions.
+    InvalidSessionModeChange(SessionMode),
+}
+
+impl<Hash, Number, AuthorityId> Checkpoint<Hash, Number, AuthorityId> {
+    /// Verify `header` as a child of the checkpoint, and move the checkpoint to it.
+    ///
+    /// The checkpoint is left untouched if the header is invalid.
+    pub fn import<H>(&mut self, header: &H) -> Result<(), Error>
+    where
+        H: Header<Hash = Hash, Number = Number>,
+        Hash: AsRef<[u8]> + PartialEq,
+        Number: AtLeast32BitUnsigned + Copy,
+        AuthorityId: RuntimeAppPublic + Codec + Clone,
+        AuthorityId::Signature: Codec,
+    {
+        if *header.parent_hash() != self.hash || *header.number() != self.number + One::one() {
+            return Err(Error::NotAChild);
+        }
+
+        let mut pre_header = header.clone();
+        let seal = pre_header.digest_mut().pop().ok_or(Error::Unsealed)?;
+        let signature = CompatibleDigestItem::<AuthorityId::Signature>::as_aura_seal(&seal)
+            .ok_or(Error::BadSeal)?;
+        let pre_digest = find_pre_digest::<H, AuthorityId::Signature>(&pre_header)?;
+        let slot = pre_digest.slot();
+
+        if let Some(parent_slot) = self.slot.filter(|parent_slot| slot <= *parent_slot) {
+            return Err(Error::SlotNotIncreasing { slot, parent_slot });
+        }
+
+        // authorship follows the session state of the parent, as on import.
+        let session_index = self.session_mode.session_index(slot, self.session_index);
+        let fallback = self
+            .fallback_timeout
+            .zip(self.slot)
+            .map(|(timeout, parent_slot)| {
+                Fallback::new(parent_slot, timeout, session_index, self.fallback_offset)
+            });
+        let offset = fallback.map_or(0, |fallback| fallback.offset(slot));
+        let author_index = session_author_index(
+            session_index,
+            offset,
+            self.authorities.len(),
+            &self.disabled,
+        )
+        .ok_or(Error::SlotAuthorNotFound)?;
+        let author = self
+            .authorities
+            .get(author_index as usize)
+            .ok_or(Error::SlotAuthorNotFound)?;
+
+        if let Some(claimed) = pre_digest
+            .session_and_author()
+            .filter(|claimed| *claimed != (session_index, author_index))
+        {
+            return Err(Error::PreDigestMismatch {
+                expected: (session_index, author_index),
+                claimed,
+            });
+        }
+
+        let pre_hash = pre_header.hash();
+        if !author.verify(&pre_hash.as_ref(), &signature) {
+            return Err(Error::BadSignature);
+        }
+
+        let mut authorities = None;
+        let mut disabled = self.disabled.clone();
+        let mut new_session = None;
+        let mut session_length = None;
+        let mut fallback_timeout = None;
+        let mut session_mode = None;
+        for log in pre_header.digest().logs() {
+            let Some(log) = log.consensus_try_to::<ConsensusLog<AuthorityId>>(&AURA_ENGINE_ID)
+            else {
+                continue;
+            };
+
+            match log {
+                ConsensusLog::AuthoritiesChange(new_authorities) => {
+                    // indices refer to the previous set.
+                    authorities = Some(new_authorities);
+                    disabled.clear();
+                }
+                ConsensusLog::OnDisabled(index) => {
+                    if !disabled.contains(&index) {
+                        disabled.push(index);
+                    }
+                }
+                ConsensusLog::DisabledAuthorities(new_disabled) => {
+                    disabled = new_disabled;
+                }
+                ConsensusLog::NewSession(session_index) => {
+                    new_session = Some(session_index);
+                    disabled.clear();
+                }
+                ConsensusLog::SessionLengthChange(length) => {
+                    session_length = Some(length);
+                }
+                ConsensusLog::FallbackTimeoutChange(timeout) => {
+                    fallback_timeout = Some(timeout);
+                }
+                ConsensusLog::SessionModeChange(mode) => {
+                    session_mode = Some(mode);
+                }
+            }
+        }
+
+        let number = *header.number();
+        let expected = match self.session_mode {
+            SessionMode::Blocks => (number
+                >= self.session_start.saturating_add(self.session_length))
+            .then(|| self.session_index.saturating_add(1)),
+            SessionMode::Slots(_) => {
+                Some(session_index).filter(|index| *index != self.session_index)
+            }
+        };
+        let rollover = expected.is_some();
+        if new_session != expected {
+            return Err(Error::InvalidSessionChange {
+                expected,
+                found: new_session,
+            });
+        }
+        if let Some(length) = session_length.filter(|length| !rollover || *length == 0) {
+            return Err(Error::InvalidSessionLengthChange(length));
+        }
+        if let Some(timeout) = fallback_timeout.filter(|_| !rollover) {
+            return Err(Error::InvalidFallbackTimeoutChange(timeout));
+        }
+        if let Some(mode) = session_mode.filter(|mode| !rollover || *mode == SessionMode::Slots(0))
+        {
+            return Err(Error::InvalidSessionModeChange(mode));
+        }
+
+        self.hash = header.hash();
+        self.number = number;
+        self.slot = Some(slot);
+        self.fallback_offset = (session_index, offset);
+        if let Some(authorities) = authorities {
+            self.authorities = authorities;
+        }
+        self.disabled = disabled;
+        if let Some(session_index) = new_session {
+            self.session_index = session_index;
+            self.session_start = number;
+        }
+        if let Some(length) = session_length {
+            self.session_length = length.into();
+        }
+        if let Some(timeout) = fallback_timeout {
+            self.fallback_timeout = timeout;
+        }
+        if let Some(mode) = session_mode {
+            self.session_mode = mode;
+        }
+
+        Ok(())
+    }
+
+    /// Verify a contiguous sequence of headers starting at a child of the checkpoint, and move
+    /// the checkpoint to the last one.
+    ///
+    /// Stops at the first invalid header, leaving the checkpoint at its parent.
+    pub fn import_headers<'a, H>(
+        &mut self,
+        headers: impl IntoIterator<Item = &'a H>,
+    ) -> Result<(), Error>
+    where
+        H: Header<Hash = Hash, Number = Number> + 'a,
+        Hash: AsRef<[u8]> + PartialEq,
+        Number: AtLeast32BitUnsigned + Copy,
+        AuthorityId: RuntimeAppPublic + Codec + Clone,
+        AuthorityId::Signature: Codec,
+    {
+        headers
+            .into_iter()
+            .try_for_each(|header| self.import(header))
+    }
+}
+
+fn find_pre_digest<H: Header, Signature: Codec>(header: &H) -> Result<PreDigest, Error> {
+    let mut pre_digests = header
+        .digest()
+        .logs()
+        .iter()
+        .filter_map(CompatibleDigestItem::<Signature>::as_aura_versioned_pre_digest);
+
+    let pre_digest = pre_digests.next().ok_or(Error::NoPreDigest)?;
+    if pre_digests.next().is_some() {
+        return Err(Error::MultiplePreDigests);
+    }
+
+    Ok(pre_digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sr25519::{AuthorityId, AuthoritySignature};
+    use sp_keyring::Sr25519Keyring as Keyring;
+    use sp_runtime::{testing::Header as TestHeader, Digest, DigestItem};
+
+    type TestCheckpoint = Checkpoint<<TestHeader as Header>::Hash, u64, AuthorityId>;
+
+    fn genesis() -> (TestHeader, TestCheckpoint) {
+        let genesis = TestHeader::new(
+            0,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        let checkpoint = Checkpoint {
+            hash: genesis.hash(),
+            number: 0,
+            slot: None,
+            authorities: vec![
+                Keyring::Alice.public().into(),
+                Keyring::Bob.public().into(),
+                Keyring::Charlie.public().into(),
+            ],
+            session_index: 0,
+            disabled: vec![],
+            session_start: 0,
+            session_length: 2,
+            fallback_timeout: Some(2),
+            session_mode: SessionMode::Blocks,
+            fallback_offset: (0, 0),
+        };
+
+        (genesis, checkpoint)
+    }
+
+    fn header(
+        parent: &TestHeader,
+        slot: u64,
+        logs: Vec<ConsensusLog<AuthorityId>>,
+        author: Keyring,
+    ) -> TestHeader {
+        header_with_pre_digest(parent, PreDigest::V0(slot.into()), logs, author)
+    }
+
+    fn header_with_pre_digest(
+        parent: &TestHeader,
+        pre_digest: PreDigest,
+        logs: Vec<ConsensusLog<AuthorityId>>,
+        author: Keyring,
+    ) -> TestHeader {
+        let mut digest = Digest {
+            logs: vec![
+                <DigestItem as CompatibleDigestItem<AuthoritySignature>>::aura_versioned_pre_digest(
+                    pre_digest,
+                ),
+            ],
+        };
+        for log in logs {
+            digest.push(DigestItem::Consensus(AURA_ENGINE_ID, log.encode()));
+        }
+
+        let mut header = TestHeader::new(
+            parent.number + 1,
+            Default::default(),
+            Default::default(),
+            parent.hash(),
+            digest,
+        );
+        let signature: AuthoritySignature = author.sign(header.hash().as_ref()).into();
+        header.digest_mut().push(DigestItem::aura_seal(signature));
+
+        header
+    }
+
+    #[test]
+    fn follows_session_rollovers_and_authority_changes() {
+        let (genesis, mut checkpoint) = genesis();
+
+        let block1 = header_with_pre_digest(
+            &genesis,
+            PreDigest::V1 {
+                slot: 1.into(),
+                session_index: 0,
+                authority_index: 0,
+            },
+            vec![],
+            Keyring::Alice,
+        );
+        // the block starting session 1 is still authored in session 0.
+        let block2 = header(
+            &block1,
+            2,
+            vec![
+                ConsensusLog::AuthoritiesChange(vec![
+                    Keyring::Bob.public().into(),
+                    Keyring::Charlie.public().into(),
+                ]),
+                ConsensusLog::NewSession(1),
+                ConsensusLog::SessionLengthChange(3),
+            ],
+            Keyring::Alice,
+        );
+        let block3 = header(&block2, 3, vec![], Keyring::Charlie);
+        let block4 = header(
+            &block3,
+            4,
+            vec![ConsensusLog::OnDisabled(1)],
+            Keyring::Charlie,
+        );
+        let block5 = header(
+            &block4,
+            5,
+            vec![
+                ConsensusLog::NewSession(2),
+                ConsensusLog::FallbackTimeoutChange(Some(1)),
+            ],
+            Keyring::Bob,
+        );
+        // a single empty slot now hands authorship over to the next authority.
+        let block6 = header(&block5, 7, vec![], Keyring::Charlie);
+
+        checkpoint
+            .import_headers([&block1, &block2, &block3, &block4, &block5, &block6])
+            .unwrap();
+
+        assert_eq!(
+            checkpoint,
+            Checkpoint {
+                hash: block6.hash(),
+                number: 6,
+                slot: Some(7.into()),
+                authorities: vec![
+                    Keyring::Bob.public().into(),
+                    Keyring::Charlie.public().into(),
+                ],
+                session_index: 2,
+                disabled: vec![],
+                session_start: 5,
+                session_length: 3,
+                fallback_timeout: Some(1),
+                session_mode: SessionMode::Blocks,
+                fallback_offset: (2, 1),
+            }
+        );
+    }
+
+    #[test]
+    fn follows_slot_based_sessions() {
+        let (genesis, mut checkpoint) = genesis();
+        checkpoint.session_mode = SessionMode::Slots(4);
+
+        let block1 = header(&genesis, 1, vec![], Keyring::Alice);
+        // the first block of a session is authored in it, skipping the sessions without blocks.
+        let block2 = header_with_pre_digest(
+            &block1,
+            PreDigest::V1 {
+                slot: 9.into(),
+                session_index: 2,
+                authority_index: 2,
+            },
+            vec![ConsensusLog::NewSession(2)],
+            Keyring::Charlie,
+        );
+        // the session doesn't depend on the number of blocks.
+        let block3 = header(&block2, 10, vec![], Keyring::Charlie);
+        let block4 = header(&block3, 11, vec![], Keyring::Charlie);
+
+        checkpoint
+            .import_headers([&block1, &block2, &block3, &block4])
+            .unwrap();
+        assert_eq!(checkpoint.session_index, 2);
+        assert_eq!(checkpoint.session_start, 2);
+
+        // sessions move on with the slot, with or without the fallback.
+        assert_eq!(
+            checkpoint.import(&header(&block4, 12, vec![], Keyring::Alice)),
+            Err(Error::InvalidSessionChange {
+                expected: Some(3),
+                found: None,
+            })
+        );
+        assert_eq!(
+            checkpoint.import(&header(&block4, 15, vec![], Keyring::Alice)),
+            Err(Error::BadSignature)
+        );
+        let block5 = header(
+            &block4,
+            15,
+            vec![
+                ConsensusLog::NewSession(3),
+                ConsensusLog::SessionModeChange(SessionMode::Blocks),
+            ],
+            Keyring::Bob,
+        );
+        checkpoint.import(&block5).unwrap();
+        assert_eq!(checkpoint.session_index, 3);
+        assert_eq!(checkpoint.session_mode, SessionMode::Blocks);
+
+        // back to sessions of blocks, the next slot of sessions no longer starts one and the
+        // fallback author keeps the slots.
+        checkpoint
+            .import(&header(&block5, 16, vec![], Keyring::Bob))
+            .unwrap();
+        assert_eq!(checkpoint.session_index, 3);
+    }
+
+    #[test]
+    fn fallback_authors_keep_the_session() {
+        let (genesis, mut checkpoint) = genesis();
+
+        // three empty slots hand session 0 over to Bob.
+        let block1 = header(&genesis, 4, vec![], Keyring::Bob);
+        checkpoint.import(&block1).unwrap();
+        assert_eq!(checkpoint.fallback_offset, (0, 1));
+
+        // Bob keeps the slots for the rest of the session, even right after his block.
+        assert_eq!(
+            checkpoint.import(&header(&block1, 5, vec![], Keyring::Alice)),
+            Err(Error::BadSignature)
+        );
+        let block2 = header(&block1, 5, vec![ConsensusLog::NewSession(1)], Keyring::Bob);
+        checkpoint.import(&block2).unwrap();
+        assert_eq!(checkpoint.fallback_offset, (0, 1));
+
+        // the next session starts over from its own author.
+        let block3 = header(&block2, 6, vec![], Keyring::Bob);
+        checkpoint.import(&block3).unwrap();
+        assert_eq!(checkpoint.fallback_offset, (1, 0));
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let (genesis, mut checkpoint) = genesis();
+        let initial = checkpoint.clone();
+
+        let cases = [
+            (
+                header(&genesis, 1, vec![], Keyring::Bob),
+                Error::BadSignature,
+            ),
+            (
+                header(
+                    &genesis,
+                    1,
+                    vec![ConsensusLog::NewSession(1)],
+                    Keyring::Alice,
+                ),
+                Error::InvalidSessionChange {
+                    expected: None,
+                    found: Some(1),
+                },
+            ),
+            (
+                header(
+                    &genesis,
+                    1,
+                    vec![ConsensusLog::SessionLengthChange(5)],
+                    Keyring::Alice,
+                ),
+                Error::InvalidSessionLengthChange(5),
+            ),
+            (
+                header(
+                    &genesis,
+                    1,
+                    vec![ConsensusLog::FallbackTimeoutChange(None)],
+                    Keyring::Alice,
+                ),
+                Error::InvalidFallbackTimeoutChange(None),
+            ),
+            (
+                header(
+                    &genesis,
+                    1,
+                    vec![ConsensusLog::SessionModeChange(SessionMode::Slots(4))],
+                    Keyring::Alice,
+                ),
+                Error::InvalidSessionModeChange(SessionMode::Slots(4)),
+            ),
+            (
+                header_with_pre_digest(
+                    &genesis,
+                    PreDigest::V1 {
+                        slot: 1.into(),
+                        session_index: 0,
+                        authority_index: 1,
+                    },
+                    vec![],
+                    Keyring::Alice,
+                ),
+                Error::PreDigestMismatch {
+                    expected: (0, 0),
+                    claimed: (0, 1),
+                },
+            ),
+        ];
+        for (header, error) in cases {
+            assert_eq!(checkpoint.import(&header), Err(error));
+            assert_eq!(checkpoint, initial);
+        }
+
+        let mut unsealed = header(&genesis, 1, vec![], Keyring::Alice);
+        unsealed.digest_mut().pop();
+        // the pre-digest is taken for the seal.
+        assert_eq!(checkpoint.import(&unsealed), Err(Error::BadSeal));
+        unsealed.digest_mut().pop();
+        assert_eq!(checkpoint.import(&unsealed), Err(Error::Unsealed));
+
+        let block1 = header(&genesis, 1, vec![], Keyring::Alice);
+        assert_eq!(
+            checkpoint.import(&header(&block1, 2, vec![], Keyring::Alice)),
+            Err(Error::NotAChild)
+        );
+        checkpoint.import(&block1).unwrap();
+
+        assert_eq!(
+            checkpoint.import(&header(&block1, 1, vec![], Keyring::Alice)),
+            Err(Error::SlotNotIncreasing {
+                slot: 1.into(),
+                parent_slot: 1.into(),
+            })
+        );
+        assert_eq!(
+            checkpoint.import(&header(&block1, 2, vec![], Keyring::Alice)),
+            Err(Error::InvalidSessionChange {
+                expected: Some(1),
+                found: None,
+            })
+        );
+    }
+}
@@ -22,10 +22,16 @@
 extern crate alloc;
 
 use alloc::vec::Vec;
-use codec::{Codec, Decode, Encode};
-use sp_runtime::ConsensusEngineId;
+use codec::{Codec, Decode, Encode, MaxEncodedLen};
+use scale_info::TypeInfo;
+use sp_application_crypto::RuntimeAppPublic;
+use sp_runtime::{
+    traits::{Header, NumberFor},
+    ConsensusEngineId, SaturatedConversion,
+};
 
 pub mod digests;
+pub mod header_chain;
 pub mod inherents;
 
 pub mod sr25519 {
@@ -78,8 +84,14 @@ pub type SessionIndex = u32;
 /// Auxilary data for Aura.
 pub type AuraAuxData<A> = (Vec<A>, SessionIndex);
 
+/// An equivocation proof for multiple block authorships on the same slot (i.e. double vote).
+pub type EquivocationProof<H, AuthorityId> = sp_consensus_slots::EquivocationProof<H, AuthorityId>;
+
 /// An consensus log item for Aura.
-#[derive(Decode, Encode)]
+///
+/// The encoding of the shared variants matches `sp_consensus_aura::ConsensusLog`, so the
+/// authority change logs deposited by `pallet_aura` decode as this type.
+#[derive(Decode, Encode, Debug, Clone, PartialEq, Eq)]
 pub enum ConsensusLog<AuthorityId: Codec> {
     /// The authorities have changed.
     #[codec(index = 1)]
@@ -87,10 +99,221 @@ pub enum ConsensusLog<AuthorityId: Codec> {
     /// Disable the authority with given index.
     #[codec(index = 2)]
     OnDisabled(AuthorityIndex),
+    /// A new session has started.
+    ///
+    /// Authorities disabled during the previous session are enabled again.
+    #[codec(index = 3)]
+    NewSession(SessionIndex),
+    /// The full set of disabled authority indices, replacing the current one.
+    #[codec(index = 4)]
+    DisabledAuthorities(Vec<AuthorityIndex>),
+    /// The session starting at this block has the given number of blocks.
+    ///
+    /// Only deposited when a new session length, scheduled during the previous session, takes
+    /// effect.
+    #[codec(index = 5)]
+    SessionLengthChange(u32),
+    /// The session starting at this block has the given fallback timeout.
+    ///
+    /// Only deposited when the timeout differs from the one of the previous session, see
+    /// `AuraApi::fallback_timeout`.
+    #[codec(index = 6)]
+    FallbackTimeoutChange(Option<u64>),
+    /// The session starting at this block moves on in the given mode.
+    ///
+    /// Only deposited when the mode differs from the one of the previous session, see
+    /// `AuraApi::session_mode`.
+    #[codec(index = 7)]
+    SessionModeChange(SessionMode),
+}
+
+/// How the scheduled author of a session performed.
+#[derive(Decode, Encode, Debug, Clone, PartialEq, Eq, TypeInfo, MaxEncodedLen)]
+pub struct SessionPerformance<AuthorityId> {
+    /// The authority scheduled to author the session.
+    pub author: AuthorityId,
+    /// Number of blocks of the session built by the scheduled author.
+    pub blocks_produced: u32,
+    /// Number of blocks of the session, including those built by fallback authors.
+    pub blocks: u32,
+    /// Number of slots elapsed between the last block of the previous session and the last
+    /// block of this one.
+    pub slots: u64,
+}
+
+/// Index of the authority scheduled to author in the given session.
+///
+/// The session author is `session_index % authorities_len`, moved forward by `offset` further
+/// authorities for the fallback rule. Disabled authorities are skipped, i.e. the next enabled
+/// one in the ring takes their place. Returns `None` if there are no enabled authorities.
+pub fn session_author_index(
+    session_index: SessionIndex,
+    offset: u64,
+    authorities_len: usize,
+    disabled: &[AuthorityIndex],
+) -> Option<AuthorityIndex> {
+    let len = authorities_len as u64;
+    if len == 0 {
+        return None;
+    }
+
+    let is_enabled = |idx: &u64| !disabled.contains(&(*idx as AuthorityIndex));
+    let enabled = (0..len).filter(is_enabled).count() as u64;
+    if enabled == 0 {
+        return None;
+    }
+
+    let start = session_index as u64 % len;
+    (0..len)
+        .map(|i| (start + i) % len)
+        .filter(is_enabled)
+        .nth((offset % enabled) as usize)
+        .map(|idx| idx as AuthorityIndex)
+}
+
+/// How sessions move on.
+#[derive(Decode, Encode, Debug, Clone, Copy, Default, PartialEq, Eq, TypeInfo, MaxEncodedLen)]
+pub enum SessionMode {
+    /// A new session starts every `session_length` blocks, see `AuraApi::session_length`.
+    ///
+    /// Blocks are authored in the session of their parent, so empty slots stretch the session.
+    #[default]
+    Blocks,
+    /// The session of a block is its slot divided by the given number of slots per session.
+    ///
+    /// Sessions move on whether blocks are produced or not, and those without blocks are
+    /// skipped.
+    Slots(u64),
+}
+
+impl SessionMode {
+    /// Index of the session a block at `slot` is authored in, on top of a parent in session
+    /// `parent_session_index`.
+    ///
+    /// Sessions never move backwards, e.g. when a runtime switches to slot based sessions.
+    pub fn session_index(&self, slot: Slot, parent_session_index: SessionIndex) -> SessionIndex {
+        match self {
+            Self::Slots(slots_per_session) if *slots_per_session > 0 => {
+                let session_index: SessionIndex = (*slot / slots_per_session).saturated_into();
+                session_index.max(parent_session_index)
+            }
+            _ => parent_session_index,
+        }
+    }
+}
+
+/// Fallback authorship rule for sessions whose author is not producing blocks.
+///
+/// Once `timeout` consecutive slots after `parent_slot` stay empty, the next authority in the
+/// ring becomes the slot author, and authorship moves on again after every further `timeout`
+/// empty slots. A fallback author keeps the slots for the rest of the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fallback {
+    /// Slot of the parent block.
+    pub parent_slot: Slot,
+    /// Number of empty slots after which the next authority may claim.
+    pub timeout: u64,
+    /// Offset of the parent's author, if the parent was authored in the same session.
+    pub parent_offset: u64,
+}
+
+impl Fallback {
+    /// Fallback rule for a block of session `session_index` on top of a parent at
+    /// `parent_slot`.
+    ///
+    /// `authored` is the session the parent was authored in along with the offset of its
+    /// author, see [`AuraApi::fallback_offset`]. The offset only carries over within that
+    /// session.
+    pub fn new(
+        parent_slot: Slot,
+        timeout: u64,
+        session_index: SessionIndex,
+        authored: (SessionIndex, u64),
+    ) -> Self {
+        let (parent_session_index, parent_offset) = authored;
+        let parent_offset = if parent_session_index == session_index {
+            parent_offset
+        } else {
+            0
+        };
+        Self {
+            parent_slot,
+            timeout,
+            parent_offset,
+        }
+    }
+
+    /// Number of authorities to skip past the session author at the given slot.
+    pub fn offset(&self, slot: Slot) -> u64 {
+        if self.timeout == 0 {
+            return self.parent_offset;
+        }
+
+        let empty_slots = slot.saturating_sub(*self.parent_slot).saturating_sub(1);
+        self.parent_offset
+            .saturating_add(empty_slots / self.timeout)
+    }
+}
+
+/// Verifies the equivocation proof by making sure that: both headers have
+/// different hashes, are targetting the same slot, and have valid signatures by
+/// the same authority.
+///
+/// Whether the offender was allowed to author at that slot is left to the caller.
+pub fn check_equivocation_proof<H, AuthorityId>(proof: EquivocationProof<H, AuthorityId>) -> bool
+where
+    H: Header,
+    AuthorityId: RuntimeAppPublic,
+    AuthorityId::Signature: Codec,
+{
+    use digests::CompatibleDigestItem;
+
+    let find_pre_digest = |header: &H| {
+        header
+            .digest()
+            .logs()
+            .iter()
+            .find_map(CompatibleDigestItem::<AuthorityId::Signature>::as_aura_pre_digest)
+    };
+
+    let verify_seal_signature = |mut header: H, offender: &AuthorityId| {
+        let seal = header.digest_mut().pop()?.as_aura_seal()?;
+        let pre_hash = header.hash();
+
+        if !offender.verify(&pre_hash.as_ref(), &seal) {
+            return None;
+        }
+
+        Some(())
+    };
+
+    let verify_proof = || {
+        // we must have different headers for the equivocation to be valid
+        if proof.first_header.hash() == proof.second_header.hash() {
+            return None;
+        }
+
+        let first_slot = find_pre_digest(&proof.first_header)?;
+        let second_slot = find_pre_digest(&proof.second_header)?;
+
+        // both headers must be targetting the same slot and it must
+        // be the same as the one in the proof.
+        if proof.slot != first_slot || first_slot != second_slot {
+            return None;
+        }
+
+        verify_seal_signature(proof.first_header, &proof.offender)?;
+        verify_seal_signature(proof.second_header, &proof.offender)?;
+
+        Some(())
+    };
+
+    verify_proof().is_some()
 }
 
 sp_api::decl_runtime_apis! {
     /// API necessary for block authorship with aura.
+    #[api_version(9)]
     pub trait AuraApi<AuthorityId: Codec> {
         /// Returns the slot duration for Aura.
         ///
@@ -102,5 +325,86 @@ sp_api::decl_runtime_apis! {
 
         /// List of authorities
         fn authorities() -> Vec<AuthorityId>;
+
+        /// Number of consecutive empty slots after which the next authority in the ring may
+        /// claim a slot of the current session.
+        ///
+        /// `None` disables the fallback and only the session author may claim. Changes take effect
+        /// at session boundaries, see [`ConsensusLog::FallbackTimeoutChange`].
+        #[api_version(2)]
+        fn fallback_timeout() -> Option<u64>;
+
+        /// Submits an unsigned extrinsic to report an equivocation of the session author.
+        ///
+        /// The caller must provide the equivocation proof. The extrinsic will be unsigned and
+        /// should only be accepted for local authorship (not to be broadcast to the network).
+        /// This method returns `None` when creation of the extrinsic fails, e.g. if equivocation
+        /// reporting is disabled for the given runtime (i.e. this method is hardcoded to return
+        /// `None`). Only useful in an offchain context.
+        #[api_version(3)]
+        fn submit_report_equivocation_unsigned_extrinsic(
+            equivocation_proof: EquivocationProof<Block::Header, AuthorityId>,
+        ) -> Option<()>;
+
+        /// Indices of the authorities currently taken out of the rotation.
+        ///
+        /// The slots of a disabled authority go to the next enabled one in the ring.
+        #[api_version(4)]
+        fn disabled_authorities() -> Vec<AuthorityIndex>;
+
+        /// Number of blocks in a session.
+        #[api_version(5)]
+        fn session_length() -> NumberFor<Block>;
+
+        /// Number of the block the current session started at.
+        #[api_version(5)]
+        fn current_session_start() -> NumberFor<Block>;
+
+        /// Number of the block the next session starts at.
+        ///
+        /// Only meaningful with [`SessionMode::Blocks`], see [`Self::session_mode`].
+        #[api_version(5)]
+        fn next_session_start() -> NumberFor<Block>;
+
+        /// The authority scheduled to author the given session.
+        ///
+        /// This is based on the current authority set and disabled authorities, so it may change
+        /// for sessions further ahead. Returns `None` if there is no enabled authority.
+        #[api_version(5)]
+        fn session_author(session_index: SessionIndex) -> Option<AuthorityId>;
+
+        /// Number of blocks in the next session.
+        ///
+        /// Differs from [`Self::session_length`] if a new length is scheduled to take effect at
+        /// the next session boundary.
+        #[api_version(6)]
+        fn next_session_length() -> NumberFor<Block>;
+
+        /// How the scheduled author performed in the given session.
+        ///
+        /// Returns `None` for sessions pruned from the history or not started yet.
+        #[api_version(7)]
+        fn session_performance(
+            session_index: SessionIndex,
+        ) -> Option<SessionPerformance<AuthorityId>>;
+
+        /// Number of consecutive sessions the given authority was scheduled for and built no
+        /// block in.
+        #[api_version(7)]
+        fn missed_sessions(authority: AuthorityId) -> u32;
+
+        /// How sessions move on.
+        ///
+        /// Changes take effect at session boundaries, see [`ConsensusLog::SessionModeChange`].
+        #[api_version(8)]
+        fn session_mode() -> SessionMode;
+
+        /// Session the current block was authored in, along with the number of authorities
+        /// its author was past the session author.
+        ///
+        /// Blocks on top of it in the same session carry on from that offset, see
+        /// [`Fallback::new`].
+        #[api_version(9)]
+        fn fallback_offset() -> (SessionIndex, u64);
     }
 }
@@ -0,0 +1,493 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Schema for the session state tracked in the auxiliary database.
+//!
+//! For every block imported through the verifier we record the state its children are verified
+//! against, derived from the parent's record and the [`ConsensusLog`] digests of the block. The
+//! full state is only written when it changes, other blocks point to the block holding it.
+//!
+//! The weight of the chain ending at a block is recorded as well, for the session-aware fork
+//! choice.
+
+use std::collections::BTreeSet;
+
+use codec::{Codec, Decode, Encode};
+use log::trace;
+use sc_client_api::backend::AuxStore;
+use sp_blockchain::{Error as ClientError, Result as ClientResult};
+use sp_runtime::{traits::Header, SaturatedConversion};
+use spin_primitives::{
+    AuraAuxData, AuthorityIndex, ConsensusLog, Fallback, SessionIndex, SessionMode, Slot,
+    AURA_ENGINE_ID,
+};
+
+use crate::LOG_TARGET;
+
+const SESSION_STATE_KEY: &[u8] = b"spin_session_state";
+const CHAIN_WEIGHT_KEY: &[u8] = b"spin_chain_weight";
+
+fn session_state_key<H: Encode>(hash: H) -> Vec<u8> {
+    (SESSION_STATE_KEY, hash).encode()
+}
+
+fn chain_weight_key<H: Encode>(hash: H) -> Vec<u8> {
+    (CHAIN_WEIGHT_KEY, hash).encode()
+}
+
+/// The session state children of a block are verified against.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SessionState<A> {
+    /// The authority set.
+    pub authorities: Vec<A>,
+    /// The current session index.
+    pub session_index: SessionIndex,
+    /// Number of the block the current session started at.
+    pub session_start: u64,
+    /// Authorities disabled for the rest of the session.
+    pub disabled: Vec<AuthorityIndex>,
+    /// The fallback timeout of the runtime, see `AuraApi::fallback_timeout`.
+    pub fallback_timeout: Option<u64>,
+    /// How sessions move on, see `AuraApi::session_mode`.
+    pub session_mode: SessionMode,
+    /// Session the block was authored in and the fallback offset of its author, see
+    /// `AuraApi::fallback_offset`.
+    pub fallback_offset: (SessionIndex, u64),
+}
+
+impl<A: Codec + Clone> SessionState<A> {
+    /// The auxiliary data used to check the seal of a child block.
+    pub fn aux_data(&self) -> AuraAuxData<A> {
+        (self.authorities.clone(), self.session_index)
+    }
+
+    /// Session a child at `slot` is authored in, along with the offset of its author under the
+    /// given fallback rule.
+    pub fn fallback_offset_at(
+        &self,
+        slot: Slot,
+        fallback: Option<Fallback>,
+    ) -> (SessionIndex, u64) {
+        (
+            self.session_mode.session_index(slot, self.session_index),
+            fallback.map_or(0, |fallback| fallback.offset(slot)),
+        )
+    }
+
+    /// Apply the consensus logs found in the given header.
+    ///
+    /// Returns `true` if the state changed.
+    pub fn apply_digests<H: Header>(&mut self, header: &H) -> bool {
+        let mut changed = false;
+        for log in header.digest().logs() {
+            let Some(log) = log.consensus_try_to::<ConsensusLog<A>>(&AURA_ENGINE_ID) else {
+                continue;
+            };
+
+            trace!(target: LOG_TARGET, "Applying consensus log of block {:?}", header.hash());
+            match log {
+                ConsensusLog::AuthoritiesChange(authorities) => {
+                    // indices refer to the previous set.
+                    self.authorities = authorities;
+                    self.disabled.clear();
+                }
+                ConsensusLog::OnDisabled(index) => {
+                    if !self.disabled.contains(&index) {
+                        self.disabled.push(index);
+                    }
+                }
+                ConsensusLog::DisabledAuthorities(disabled) => {
+                    self.disabled = disabled;
+                }
+                ConsensusLog::NewSession(session_index) => {
+                    self.session_index = session_index;
+                    self.session_start = (*header.number()).saturated_into();
+                    self.disabled.clear();
+                }
+                ConsensusLog::FallbackTimeoutChange(fallback_timeout) => {
+                    self.fallback_timeout = fallback_timeout;
+                }
+                ConsensusLog::SessionModeChange(session_mode) => {
+                    self.session_mode = session_mode;
+                }
+                // session boundaries are followed through `NewSession`.
+                ConsensusLog::SessionLengthChange(_) => continue,
+            }
+            changed = true;
+        }
+
+        changed
+    }
+}
+
+/// A session state record as stored for a block.
+#[derive(Encode, Decode)]
+enum StoredSessionState<A, H> {
+    /// The state changed at this block.
+    Changed(SessionState<A>),
+    /// The state is the one recorded at the given block.
+    Unchanged(H),
+}
+
+/// Load the session state recorded for the block with the given hash.
+///
+/// Also returns the hash of the block holding the full state. Returns `None` if nothing was
+/// recorded for the block, e.g. because it was not imported through the verifier.
+pub fn load_session_state<A, H, C>(
+    backend: &C,
+    hash: H,
+) -> ClientResult<Option<(SessionState<A>, H)>>
+where
+    A: Decode,
+    H: Codec + Copy,
+    C: AuxStore,
+{
+    let Some(anchor) =
+        load_decode::<_, StoredSessionState<A, H>>(backend, &session_state_key(hash))?
+    else {
+        return Ok(None);
+    };
+
+    match anchor {
+        StoredSessionState::Changed(state) => Ok(Some((state, hash))),
+        StoredSessionState::Unchanged(anchor) => {
+            match load_decode::<_, StoredSessionState<A, H>>(backend, &session_state_key(anchor))? {
+                Some(StoredSessionState::Changed(state)) => Ok(Some((state, anchor))),
+                _ => Err(ClientError::Backend(format!(
+                    "Session state anchor {:?} of {:?} is missing",
+                    anchor.encode(),
+                    hash.encode(),
+                ))),
+            }
+        }
+    }
+}
+
+/// Compute the auxiliary entry recording the session state after `header`.
+///
+/// `hash` is the hash of the sealed block, `header` the header holding its consensus logs.
+/// `parent_state` is the state recorded for the parent, along with the block holding it, or
+/// `None` if the parent state was read from the runtime instead. `fallback_offset` is the one of
+/// the block, see [`SessionState::fallback_offset_at`]. Returns the new state and the entry to be
+/// written on import of the block.
+pub fn session_state_entry<A, H>(
+    hash: H::Hash,
+    header: &H,
+    parent_state: SessionState<A>,
+    parent_anchor: Option<H::Hash>,
+    fallback_offset: (SessionIndex, u64),
+) -> (SessionState<A>, (Vec<u8>, Vec<u8>))
+where
+    A: Codec + Clone,
+    H: Header,
+{
+    let mut state = parent_state;
+    let mut changed = state.apply_digests(header);
+    if state.fallback_offset != fallback_offset {
+        state.fallback_offset = fallback_offset;
+        changed = true;
+    }
+
+    let record = match parent_anchor {
+        Some(anchor) if !changed => StoredSessionState::<A, H::Hash>::Unchanged(anchor).encode(),
+        _ => StoredSessionState::<A, H::Hash>::Changed(state.clone()).encode(),
+    };
+
+    (state, (session_state_key(hash), record))
+}
+
+/// Compute the auxiliary operations pruning the session states no longer needed once `finalized`
+/// is finalized.
+///
+/// `retracted` are the blocks nothing will be built on anymore: the previously finalized block,
+/// the ones up to the newly finalized block and those of the forks it made stale. Their records
+/// and the ones they point to are deleted, except for the state of `finalized`, which its
+/// descendants still rely on.
+pub fn prune_session_states<A, H, C>(
+    backend: &C,
+    finalized: H,
+    retracted: impl IntoIterator<Item = H>,
+) -> ClientResult<Vec<(Vec<u8>, Option<Vec<u8>>)>>
+where
+    A: Decode,
+    H: Codec + Copy + Ord,
+    C: AuxStore,
+{
+    let mut pruned = BTreeSet::new();
+    for hash in retracted {
+        if let Some((_, anchor)) = load_session_state::<A, H, C>(backend, hash)? {
+            pruned.insert(anchor);
+        }
+        pruned.insert(hash);
+    }
+
+    pruned.remove(&finalized);
+    if let Some((_, anchor)) = load_session_state::<A, H, C>(backend, finalized)? {
+        pruned.remove(&anchor);
+    }
+
+    Ok(pruned
+        .into_iter()
+        .map(|hash| (session_state_key(hash), None))
+        .collect())
+}
+
+/// Weight of the chain ending at a block, see [`crate::SessionForkChoice`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode)]
+pub struct ChainWeight {
+    /// Sum of the weights of the blocks of the chain.
+    pub weight: u64,
+    /// Slot of the block.
+    pub slot: Slot,
+}
+
+impl ChainWeight {
+    /// Whether this chain is preferred over `other`: the heavier one, or the one ending at the
+    /// lowest slot for equal weights.
+    pub fn is_preferred_over(&self, other: &Self) -> bool {
+        self.weight > other.weight || (self.weight == other.weight && self.slot < other.slot)
+    }
+}
+
+/// Load the chain weight recorded for the block with the given hash.
+pub fn load_chain_weight<H, C>(backend: &C, hash: H) -> ClientResult<Option<ChainWeight>>
+where
+    H: Encode,
+    C: AuxStore,
+{
+    load_decode(backend, &chain_weight_key(hash))
+}
+
+/// The auxiliary entry recording the chain weight of the block with the given hash.
+pub fn chain_weight_entry<H: Encode>(hash: H, weight: ChainWeight) -> (Vec<u8>, Vec<u8>) {
+    (chain_weight_key(hash), weight.encode())
+}
+
+/// Compute the auxiliary operations pruning the chain weights of the given retracted blocks, see
+/// [`prune_session_states`].
+///
+/// The weight of `finalized` is kept, its descendants build on it.
+pub fn prune_chain_weights<H: Encode + PartialEq>(
+    finalized: H,
+    retracted: impl IntoIterator<Item = H>,
+) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+    retracted
+        .into_iter()
+        .filter(|hash| *hash != finalized)
+        .map(|hash| (chain_weight_key(hash), None))
+        .collect()
+}
+
+fn load_decode<B: AuxStore, T: Decode>(backend: &B, key: &[u8]) -> ClientResult<Option<T>> {
+    match backend.get_aux(key)? {
+        None => Ok(None),
+        Some(t) => T::decode(&mut &t[..])
+            .map_err(|e| ClientError::Backend(format!("Spin DB is corrupted. Decode error: {}", e)))
+            .map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp_runtime::{
+        testing::{Header as TestHeader, H256},
+        DigestItem,
+    };
+
+    fn header(number: u64, parent_hash: H256, logs: Vec<ConsensusLog<u64>>) -> TestHeader {
+        let mut header = TestHeader::new_from_number(number);
+        header.parent_hash = parent_hash;
+        for log in logs {
+            header
+                .digest
+                .push(DigestItem::Consensus(AURA_ENGINE_ID, log.encode()));
+        }
+        header
+    }
+
+    #[test]
+    fn digests_update_session_state() {
+        let mut state = SessionState::<u64> {
+            authorities: vec![1, 2, 3],
+            session_index: 4,
+            session_start: 0,
+            disabled: vec![],
+            fallback_timeout: Some(10),
+            session_mode: SessionMode::Blocks,
+            fallback_offset: (4, 0),
+        };
+
+        assert!(!state.apply_digests(&header(1, Default::default(), vec![])));
+
+        assert!(state.apply_digests(&header(
+            2,
+            Default::default(),
+            vec![ConsensusLog::OnDisabled(1), ConsensusLog::OnDisabled(1)],
+        )));
+        assert_eq!(state.disabled, vec![1]);
+
+        assert!(state.apply_digests(&header(
+            3,
+            Default::default(),
+            vec![ConsensusLog::NewSession(5)],
+        )));
+        assert_eq!(state.session_index, 5);
+        assert_eq!(state.session_start, 3);
+        assert!(state.disabled.is_empty());
+
+        // the runtime re-announces authorities disabled beyond the session.
+        assert!(state.apply_digests(&header(
+            3,
+            Default::default(),
+            vec![
+                ConsensusLog::NewSession(6),
+                ConsensusLog::DisabledAuthorities(vec![0, 2]),
+            ],
+        )));
+        assert_eq!(state.session_index, 6);
+        assert_eq!(state.disabled, vec![0, 2]);
+
+        state.disabled.push(0);
+        assert!(state.apply_digests(&header(
+            4,
+            Default::default(),
+            vec![ConsensusLog::AuthoritiesChange(vec![7, 8])],
+        )));
+        assert_eq!(state.authorities, vec![7, 8]);
+        assert!(state.disabled.is_empty());
+        assert_eq!(state.fallback_timeout, Some(10));
+
+        assert!(state.apply_digests(&header(
+            5,
+            Default::default(),
+            vec![
+                ConsensusLog::NewSession(7),
+                ConsensusLog::FallbackTimeoutChange(None),
+                ConsensusLog::SessionModeChange(SessionMode::Slots(4)),
+            ],
+        )));
+        assert_eq!(state.fallback_timeout, None);
+        assert_eq!(state.session_mode, SessionMode::Slots(4));
+    }
+
+    #[test]
+    fn unchanged_state_points_to_anchor() {
+        let store = substrate_test_runtime_client::new();
+        let genesis_state = SessionState::<u64> {
+            authorities: vec![1, 2, 3],
+            session_index: 0,
+            session_start: 0,
+            disabled: vec![],
+            fallback_timeout: None,
+            session_mode: SessionMode::Blocks,
+            fallback_offset: (0, 0),
+        };
+
+        // the first record after a runtime lookup always holds the full state.
+        let first = header(1, Default::default(), vec![]);
+        let (state, entry) =
+            session_state_entry(first.hash(), &first, genesis_state.clone(), None, (0, 0));
+        store
+            .insert_aux(&[(&entry.0[..], &entry.1[..])], &[])
+            .unwrap();
+        assert_eq!(
+            load_session_state::<u64, _, _>(&store, first.hash()).unwrap(),
+            Some((genesis_state.clone(), first.hash())),
+        );
+
+        // without logs, children point to it.
+        let second = header(2, first.hash(), vec![]);
+        let (state, entry) =
+            session_state_entry(second.hash(), &second, state, Some(first.hash()), (0, 0));
+        store
+            .insert_aux(&[(&entry.0[..], &entry.1[..])], &[])
+            .unwrap();
+        assert_eq!(
+            load_session_state::<u64, _, _>(&store, second.hash()).unwrap(),
+            Some((genesis_state, first.hash())),
+        );
+
+        // a log starts a new anchor.
+        let third = header(3, second.hash(), vec![ConsensusLog::NewSession(1)]);
+        let (state, entry) =
+            session_state_entry(third.hash(), &third, state, Some(first.hash()), (0, 0));
+        store
+            .insert_aux(&[(&entry.0[..], &entry.1[..])], &[])
+            .unwrap();
+        assert_eq!(
+            load_session_state::<u64, _, _>(&store, third.hash()).unwrap(),
+            Some((state.clone(), third.hash())),
+        );
+
+        // so does a fallback author.
+        let fourth = header(4, third.hash(), vec![]);
+        let (state, entry) =
+            session_state_entry(fourth.hash(), &fourth, state, Some(third.hash()), (1, 1));
+        store
+            .insert_aux(&[(&entry.0[..], &entry.1[..])], &[])
+            .unwrap();
+        assert_eq!(state.fallback_offset, (1, 1));
+        assert_eq!(
+            load_session_state::<u64, _, _>(&store, fourth.hash()).unwrap(),
+            Some((state, fourth.hash())),
+        );
+    }
+
+    #[test]
+    fn pruning_keeps_what_the_finalized_block_relies_on() {
+        let store = substrate_test_runtime_client::new();
+        let state = SessionState::<u64> {
+            authorities: vec![1, 2, 3],
+            session_index: 0,
+            session_start: 0,
+            disabled: vec![],
+            fallback_timeout: None,
+            session_mode: SessionMode::Blocks,
+            fallback_offset: (0, 0),
+        };
+
+        let import = |header: &TestHeader, state: SessionState<u64>, anchor: Option<H256>| {
+            let (state, entry) = session_state_entry(header.hash(), header, state, anchor, (0, 0));
+            store
+                .insert_aux(&[(&entry.0[..], &entry.1[..])], &[])
+                .unwrap();
+            state
+        };
+
+        // 1 <- 2 <- 3 with the state changing at 1, and a fork 1 <- 2' changing it again.
+        let first = header(1, Default::default(), vec![]);
+        let second = header(2, first.hash(), vec![]);
+        let third = header(3, second.hash(), vec![]);
+        let fork = header(2, first.hash(), vec![ConsensusLog::NewSession(1)]);
+        let state = import(&first, state, None);
+        import(&fork, state.clone(), Some(first.hash()));
+        let state = import(&second, state, Some(first.hash()));
+        import(&third, state, Some(first.hash()));
+
+        // finalizing 2 drops the fork, but not the state at 1 that 2 and 3 point to.
+        let pruned =
+            prune_session_states::<u64, _, _>(&store, second.hash(), [first.hash(), fork.hash()])
+                .unwrap();
+        assert_eq!(pruned, vec![(session_state_key(fork.hash()), None)]);
+        assert_eq!(
+            prune_chain_weights(second.hash(), [first.hash(), second.hash()]),
+            vec![(chain_weight_key(first.hash()), None)],
+        );
+    }
+}
@@ -0,0 +1,386 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Import queue holding back blocks whose slot has not started yet.
+//!
+//! Blocks of peers with a clock running slightly fast would otherwise fail verification and be
+//! requested again later. Instead, they are kept in a bounded buffer and handed to the inner
+//! queue once the local clock reaches their slot.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use futures::{future, FutureExt};
+use futures_timer::Delay;
+use log::debug;
+use parking_lot::Mutex;
+use sc_consensus::import_queue::{
+    BasicQueue, ImportQueue, ImportQueueService, IncomingBlock, Link, RuntimeOrigin,
+};
+use sp_consensus::BlockOrigin;
+use sp_consensus_slots::{Slot, SlotDuration};
+use sp_runtime::{
+    traits::{Block as BlockT, Header, NumberFor},
+    Justifications,
+};
+use sp_timestamp::Timestamp;
+
+use crate::LOG_TARGET;
+
+/// Maximum number of blocks held back at once.
+pub const MAX_DEFERRED_BLOCKS: usize = 256;
+
+/// Maximum number of slots a block may be ahead of the local clock to be held back.
+///
+/// Blocks further in the future are passed on and rejected by the verifier.
+pub const MAX_DEFERRED_SLOTS: u64 = 16;
+
+/// Blocks held back until their slot.
+struct DeferredBlocks<B: BlockT> {
+    slot_duration: SlotDuration,
+    /// Number of slots a block may be ahead of the local clock and still be imported.
+    drift: u64,
+    slot_of: fn(&B::Header) -> Option<Slot>,
+    /// Blocks along with their origin and the slot they are due at, in submission order.
+    blocks: Vec<(Slot, BlockOrigin, IncomingBlock<B>)>,
+    /// Slots the held back blocks are due at.
+    due: HashMap<B::Hash, Slot>,
+}
+
+impl<B: BlockT> DeferredBlocks<B> {
+    fn new(
+        slot_duration: SlotDuration,
+        drift: u64,
+        slot_of: fn(&B::Header) -> Option<Slot>,
+    ) -> Self {
+        Self {
+            slot_duration,
+            drift,
+            slot_of,
+            blocks: Vec::new(),
+            due: HashMap::new(),
+        }
+    }
+
+    fn slot_now(&self) -> Slot {
+        Slot::from_timestamp(Timestamp::current(), self.slot_duration)
+    }
+
+    fn time_until_next_slot(&self) -> Duration {
+        let now = Timestamp::current().as_millis();
+        let slot_duration = self.slot_duration.as_millis().max(1);
+        let next_slot = (now / slot_duration + 1) * slot_duration;
+        Duration::from_millis(next_slot - now)
+    }
+
+    /// Hold back the blocks which can't be imported at `slot_now` yet.
+    ///
+    /// Blocks ahead of the clock are held back along with the blocks building on them, which are
+    /// due no earlier than their parent. Returns the other blocks, to be imported now.
+    fn defer(
+        &mut self,
+        slot_now: Slot,
+        origin: BlockOrigin,
+        blocks: Vec<IncomingBlock<B>>,
+    ) -> Vec<IncomingBlock<B>> {
+        let max_due = *slot_now + self.drift + MAX_DEFERRED_SLOTS;
+        let mut now = Vec::with_capacity(blocks.len());
+        let mut deferred = 0;
+
+        for block in blocks {
+            let future_slot = block
+                .header
+                .as_ref()
+                .and_then(self.slot_of)
+                .filter(|slot| **slot > *slot_now + self.drift);
+            let parent_due = block
+                .header
+                .as_ref()
+                .and_then(|header| self.due.get(header.parent_hash()).copied());
+            let Some(due) = future_slot.max(parent_due) else {
+                now.push(block);
+                continue;
+            };
+
+            if *due > max_due || self.blocks.len() >= MAX_DEFERRED_BLOCKS {
+                debug!(
+                    target: LOG_TARGET,
+                    "Not deferring block {:?} due at slot {} (now {}, {} deferred)",
+                    block.hash,
+                    due,
+                    slot_now,
+                    self.blocks.len(),
+                );
+                now.push(block);
+                continue;
+            }
+
+            self.due.insert(block.hash, due);
+            self.blocks.push((due, origin, block));
+            deferred += 1;
+        }
+
+        if deferred > 0 {
+            debug!(
+                target: LOG_TARGET,
+                "Deferring {} blocks until their slot (now {})", deferred, slot_now,
+            );
+        }
+
+        now
+    }
+
+    /// Take the blocks which can be imported at `slot_now`, in submission order.
+    ///
+    /// Consecutive blocks of the same origin are batched together.
+    fn take_due(&mut self, slot_now: Slot) -> Vec<(BlockOrigin, Vec<IncomingBlock<B>>)> {
+        let drift = self.drift;
+        let (due, pending) = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(slot, _, _)| **slot <= *slot_now + drift);
+        self.blocks = pending;
+
+        let mut batches = Vec::<(BlockOrigin, Vec<IncomingBlock<B>>)>::new();
+        for (_, origin, block) in due {
+            self.due.remove(&block.hash);
+            match batches.last_mut() {
+                Some((batch_origin, batch)) if *batch_origin == origin => batch.push(block),
+                _ => batches.push((origin, vec![block])),
+            }
+        }
+
+        batches
+    }
+}
+
+/// The [`ImportQueueService`] of a [`DeferredImportQueue`].
+struct DeferredImportQueueService<B: BlockT> {
+    inner: Box<dyn ImportQueueService<B>>,
+    deferred: Arc<Mutex<DeferredBlocks<B>>>,
+}
+
+impl<B: BlockT> DeferredImportQueueService<B> {
+    /// Hand the blocks whose slot has started to the inner queue.
+    fn release_due(&mut self) {
+        let due = {
+            let mut deferred = self.deferred.lock();
+            let slot_now = deferred.slot_now();
+            deferred.take_due(slot_now)
+        };
+
+        for (origin, blocks) in due {
+            self.inner.import_blocks(origin, blocks);
+        }
+    }
+}
+
+impl<B: BlockT> ImportQueueService<B> for DeferredImportQueueService<B> {
+    fn import_blocks(&mut self, origin: BlockOrigin, blocks: Vec<IncomingBlock<B>>) {
+        let blocks = {
+            let mut deferred = self.deferred.lock();
+            let slot_now = deferred.slot_now();
+            deferred.defer(slot_now, origin, blocks)
+        };
+
+        if !blocks.is_empty() {
+            self.inner.import_blocks(origin, blocks);
+        }
+    }
+
+    fn import_justifications(
+        &mut self,
+        who: RuntimeOrigin,
+        hash: B::Hash,
+        number: NumberFor<B>,
+        justifications: Justifications,
+    ) {
+        self.inner
+            .import_justifications(who, hash, number, justifications)
+    }
+}
+
+/// Import queue deferring blocks from the future until their slot.
+///
+/// Wraps a [`BasicQueue`], which does the actual verification and import.
+pub struct DeferredImportQueue<B: BlockT> {
+    inner: BasicQueue<B>,
+    service: DeferredImportQueueService<B>,
+}
+
+impl<B: BlockT> DeferredImportQueue<B> {
+    /// Wrap the given queue.
+    ///
+    /// Blocks more than `drift` slots ahead of the local clock are held back, as long as they
+    /// are at most [`MAX_DEFERRED_SLOTS`] further ahead and the buffer has room for them.
+    pub fn new(
+        inner: BasicQueue<B>,
+        slot_duration: SlotDuration,
+        drift: u64,
+        slot_of: fn(&B::Header) -> Option<Slot>,
+    ) -> Self {
+        let deferred = Arc::new(Mutex::new(DeferredBlocks::new(
+            slot_duration,
+            drift,
+            slot_of,
+        )));
+        let service = DeferredImportQueueService {
+            inner: inner.service(),
+            deferred,
+        };
+
+        Self { inner, service }
+    }
+}
+
+#[async_trait::async_trait]
+impl<B: BlockT> ImportQueue<B> for DeferredImportQueue<B> {
+    fn service(&self) -> Box<dyn ImportQueueService<B>> {
+        Box::new(DeferredImportQueueService {
+            inner: self.inner.service(),
+            deferred: self.service.deferred.clone(),
+        })
+    }
+
+    fn service_ref(&mut self) -> &mut dyn ImportQueueService<B> {
+        &mut self.service
+    }
+
+    fn poll_actions(&mut self, cx: &mut futures::task::Context, link: &dyn Link<B>) {
+        self.service.release_due();
+        self.inner.poll_actions(cx, link)
+    }
+
+    async fn run(self, link: &dyn Link<B>) {
+        let Self { inner, mut service } = self;
+
+        let release = async move {
+            loop {
+                let wait = service.deferred.lock().time_until_next_slot();
+                Delay::new(wait).await;
+                service.release_due();
+            }
+        };
+
+        future::select(inner.run(link).boxed(), release.boxed()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc_network_test::{Block as TestBlock, Header as TestHeader};
+    use sp_core::H256;
+    use spin_primitives::sr25519::{AuthorityPair, AuthoritySignature};
+
+    fn slot_of(header: &TestHeader) -> Option<Slot> {
+        crate::standalone::find_pre_digest::<TestBlock, AuthoritySignature>(header).ok()
+    }
+
+    fn block(number: u64, parent_hash: H256, slot: u64) -> IncomingBlock<TestBlock> {
+        let mut header = TestHeader::new(
+            number,
+            Default::default(),
+            Default::default(),
+            parent_hash,
+            Default::default(),
+        );
+        header
+            .digest_mut()
+            .push(crate::standalone::pre_digest::<AuthorityPair>(
+                crate::PreDigest::V0(slot.into()),
+            ));
+
+        IncomingBlock {
+            hash: header.hash(),
+            header: Some(header),
+            body: None,
+            indexed_body: None,
+            justifications: None,
+            origin: None,
+            allow_missing_state: false,
+            skip_execution: false,
+            import_existing: false,
+            state: None,
+        }
+    }
+
+    fn hashes(blocks: &[IncomingBlock<TestBlock>]) -> Vec<H256> {
+        blocks.iter().map(|b| b.hash).collect()
+    }
+
+    #[test]
+    fn future_blocks_are_held_until_their_slot() {
+        let mut deferred =
+            DeferredBlocks::<TestBlock>::new(SlotDuration::from_millis(1000), 1, slot_of);
+        let b1 = block(1, Default::default(), 10);
+        let b2 = block(2, b1.hash, 12);
+        let b3 = block(3, b2.hash, 13);
+        let c2 = block(2, b1.hash, 11);
+
+        // b2 is two slots ahead of the clock, so it is held back along with its child, while the
+        // rest of the batch goes on.
+        let now = deferred.defer(
+            10.into(),
+            BlockOrigin::NetworkBroadcast,
+            vec![b1.clone(), b2.clone(), b3.clone(), c2.clone()],
+        );
+        assert_eq!(hashes(&now), hashes(&[b1, c2]));
+
+        // children of held back blocks wait for them, even when their own slot has started.
+        let b4 = block(4, b3.hash, 11);
+        assert!(deferred
+            .defer(10.into(), BlockOrigin::NetworkInitialSync, vec![b4.clone()])
+            .is_empty());
+
+        assert!(deferred.take_due(11.into()).is_empty());
+        let due = deferred.take_due(12.into());
+        assert_eq!(due.len(), 1);
+        assert_eq!(hashes(&due[0].1), hashes(&[b2]));
+
+        let due = deferred.take_due(13.into());
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].0, BlockOrigin::NetworkBroadcast);
+        assert_eq!(hashes(&due[0].1), hashes(&[b3]));
+        assert_eq!(due[1].0, BlockOrigin::NetworkInitialSync);
+        assert_eq!(hashes(&due[1].1), hashes(&[b4]));
+        assert!(deferred.due.is_empty());
+    }
+
+    #[test]
+    fn buffer_is_bounded() {
+        let mut deferred =
+            DeferredBlocks::<TestBlock>::new(SlotDuration::from_millis(1000), 1, slot_of);
+
+        // too far in the future.
+        let far = block(1, Default::default(), 10 + 2 + MAX_DEFERRED_SLOTS);
+        let now = deferred.defer(10.into(), BlockOrigin::NetworkBroadcast, vec![far.clone()]);
+        assert_eq!(hashes(&now), hashes(&[far]));
+
+        // blocks beyond the capacity of the buffer are passed on.
+        let mut parent_hash = Default::default();
+        let blocks = (1..=MAX_DEFERRED_BLOCKS as u64 + 1)
+            .map(|n| {
+                let block = block(n, parent_hash, 12);
+                parent_hash = block.hash;
+                block
+            })
+            .collect::<Vec<_>>();
+        let now = deferred.defer(10.into(), BlockOrigin::NetworkInitialSync, blocks.clone());
+        assert_eq!(hashes(&now), hashes(&blocks[MAX_DEFERRED_BLOCKS..]));
+        assert_eq!(deferred.blocks.len(), MAX_DEFERRED_BLOCKS);
+    }
+}
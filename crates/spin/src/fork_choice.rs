@@ -0,0 +1,326 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Fork choice rules for the blocks authored by the worker and checked by the verifier.
+
+use std::sync::Arc;
+
+use sc_client_api::backend::AuxStore;
+use sc_consensus::{BlockImportParams, ForkChoiceStrategy};
+use sp_blockchain::HeaderBackend;
+use sp_consensus::Error as ConsensusError;
+use sp_consensus_slots::Slot;
+use sp_runtime::traits::{Block as BlockT, Header};
+
+use crate::{
+    aux_schema::{self, ChainWeight},
+    PreDigest, AURA_ENGINE_ID,
+};
+
+/// Weight of a block sealed by the author scheduled for its session.
+const SESSION_AUTHOR_WEIGHT: u64 = 2;
+
+/// Weight of a block sealed by a fallback author.
+const FALLBACK_AUTHOR_WEIGHT: u64 = 1;
+
+/// Decides whether blocks become the new best block on import.
+pub trait ForkChoice<B: BlockT>: Send + Sync {
+    /// Set the fork choice strategy of a checked block, along with any auxiliary data the rule
+    /// keeps for it.
+    ///
+    /// `slot` is the slot of the block, and `session_author` tells whether it was sealed by the
+    /// author scheduled for its session rather than by a fallback author.
+    fn fork_choice(
+        &self,
+        block: &mut BlockImportParams<B>,
+        slot: Slot,
+        session_author: bool,
+    ) -> Result<(), ConsensusError>;
+}
+
+/// The longest chain wins, whoever sealed its blocks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LongestChainForkChoice;
+
+impl<B: BlockT> ForkChoice<B> for LongestChainForkChoice {
+    fn fork_choice(
+        &self,
+        block: &mut BlockImportParams<B>,
+        _slot: Slot,
+        _session_author: bool,
+    ) -> Result<(), ConsensusError> {
+        block.fork_choice = Some(ForkChoiceStrategy::LongestChain);
+        Ok(())
+    }
+}
+
+/// Prefers chains whose blocks were sealed by the author scheduled for their session.
+///
+/// Blocks sealed by their session author weigh twice as much as blocks of fallback authors and
+/// the heaviest chain wins, so a stale author can't outrun the legitimate chain. Ties go to the
+/// chain ending at the lowest slot.
+///
+/// Weights only matter between chains building on the last finalized block, so they count from
+/// there for blocks without a recorded weight, e.g. after a warp sync. Blocks imported before the
+/// rule was enabled weigh as much as those of fallback authors.
+pub struct SessionForkChoice<C> {
+    client: Arc<C>,
+}
+
+impl<C> SessionForkChoice<C> {
+    /// Create a new fork choice reading and recording the chain weights in `client`.
+    pub fn new(client: Arc<C>) -> Self {
+        Self { client }
+    }
+}
+
+impl<C> Clone for SessionForkChoice<C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+        }
+    }
+}
+
+impl<B, C> ForkChoice<B> for SessionForkChoice<C>
+where
+    B: BlockT,
+    C: HeaderBackend<B> + AuxStore + Send + Sync,
+{
+    fn fork_choice(
+        &self,
+        block: &mut BlockImportParams<B>,
+        slot: Slot,
+        session_author: bool,
+    ) -> Result<(), ConsensusError> {
+        let client = self.client.as_ref();
+        let parent_hash = *block.header.parent_hash();
+
+        let parent_weight = chain_weight::<B, C>(client, parent_hash)?.weight;
+        let block_weight = if session_author {
+            SESSION_AUTHOR_WEIGHT
+        } else {
+            FALLBACK_AUTHOR_WEIGHT
+        };
+        let weight = ChainWeight {
+            weight: parent_weight.saturating_add(block_weight),
+            slot,
+        };
+
+        let best_hash = client.info().best_hash;
+        let strategy = if best_hash == parent_hash {
+            ForkChoiceStrategy::Custom(true)
+        } else {
+            let best = chain_weight::<B, C>(client, best_hash)?;
+            ForkChoiceStrategy::Custom(weight.is_preferred_over(&best))
+        };
+
+        let (key, value) = aux_schema::chain_weight_entry(block.post_hash(), weight);
+        block.auxiliary.push((key, Some(value)));
+        block.fork_choice = Some(strategy);
+
+        Ok(())
+    }
+}
+
+/// Weight of the chain ending at the given block.
+///
+/// Walks back to the latest block with a recorded weight, or to the last finalized block, which
+/// weighs nothing if none is recorded for it.
+fn chain_weight<B, C>(client: &C, hash: B::Hash) -> Result<ChainWeight, ConsensusError>
+where
+    B: BlockT,
+    C: HeaderBackend<B> + AuxStore,
+{
+    let client_error = |e: sp_blockchain::Error| ConsensusError::ClientImport(e.to_string());
+    let finalized_number = client.info().finalized_number;
+
+    let mut hash = hash;
+    let mut unrecorded = 0u64;
+    let mut slot = None;
+    loop {
+        if let Some(recorded) = aux_schema::load_chain_weight(client, hash).map_err(client_error)? {
+            return Ok(ChainWeight {
+                weight: recorded
+                    .weight
+                    .saturating_add(unrecorded.saturating_mul(FALLBACK_AUTHOR_WEIGHT)),
+                slot: slot.unwrap_or(recorded.slot),
+            });
+        }
+
+        let header = client
+            .header(hash)
+            .map_err(client_error)?
+            .ok_or_else(|| ConsensusError::ChainLookup(format!("Unknown block {hash:?}")))?;
+        let slot = *slot.get_or_insert_with(|| slot_of(&header));
+        if *header.number() <= finalized_number {
+            return Ok(ChainWeight {
+                weight: unrecorded.saturating_mul(FALLBACK_AUTHOR_WEIGHT),
+                slot,
+            });
+        }
+
+        unrecorded += 1;
+        hash = *header.parent_hash();
+    }
+}
+
+/// Slot of the given header, zero for the genesis block, which carries none.
+fn slot_of<H: Header>(header: &H) -> Slot {
+    header
+        .digest()
+        .logs()
+        .iter()
+        .find_map(|log| log.pre_runtime_try_to::<PreDigest>(&AURA_ENGINE_ID))
+        .map_or_else(Default::default, |pre_digest| pre_digest.slot())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::Encode;
+    use sc_block_builder::BlockBuilderBuilder;
+    use sc_client_api::Finalizer;
+    use sp_consensus::BlockOrigin;
+    use sp_core::H256;
+    use substrate_test_runtime_client::{
+        runtime::{Block, Header as TestHeader},
+        ClientBlockImportExt,
+    };
+
+    fn import_params(parent_hash: H256) -> BlockImportParams<Block> {
+        BlockImportParams::new(
+            BlockOrigin::Own,
+            TestHeader::new(
+                1,
+                Default::default(),
+                Default::default(),
+                parent_hash,
+                Default::default(),
+            ),
+        )
+    }
+
+    #[test]
+    fn heavier_chains_are_preferred_then_the_lowest_slot() {
+        let weight = |weight, slot: u64| ChainWeight {
+            weight,
+            slot: slot.into(),
+        };
+
+        assert!(weight(3, 9).is_preferred_over(&weight(2, 5)));
+        assert!(!weight(2, 5).is_preferred_over(&weight(3, 9)));
+        assert!(weight(3, 5).is_preferred_over(&weight(3, 6)));
+        assert!(!weight(3, 6).is_preferred_over(&weight(3, 5)));
+        assert!(!weight(3, 5).is_preferred_over(&weight(3, 5)));
+    }
+
+    #[test]
+    fn session_authors_weigh_more_than_fallback_authors() {
+        let client = Arc::new(substrate_test_runtime_client::new());
+        let genesis_hash = client.info().genesis_hash;
+        let fork_choice = SessionForkChoice::new(client.clone());
+
+        // children of the best block always become the best block.
+        let mut block = import_params(genesis_hash);
+        fork_choice
+            .fork_choice(&mut block, 1.into(), false)
+            .unwrap();
+        assert!(matches!(
+            block.fork_choice,
+            Some(ForkChoiceStrategy::Custom(true))
+        ));
+        let hash = block.post_hash();
+        let (key, value) = &block.auxiliary[0];
+        client
+            .insert_aux(&[(&key[..], &value.as_deref().unwrap()[..])], &[])
+            .unwrap();
+        assert_eq!(
+            aux_schema::load_chain_weight(&*client, hash).unwrap(),
+            Some(ChainWeight {
+                weight: 1,
+                slot: 1.into(),
+            })
+        );
+
+        // blocks sealed by their session author weigh two more than their parent.
+        let mut block = import_params(hash);
+        fork_choice.fork_choice(&mut block, 2.into(), true).unwrap();
+        assert!(matches!(
+            block.fork_choice,
+            Some(ForkChoiceStrategy::Custom(true))
+        ));
+        let (_, value) = &block.auxiliary[0];
+        assert_eq!(
+            value.as_deref(),
+            Some(
+                &ChainWeight {
+                    weight: 3,
+                    slot: 2.into(),
+                }
+                .encode()[..]
+            )
+        );
+    }
+
+    #[test]
+    fn unrecorded_blocks_are_weighed_from_the_last_finalized_block() {
+        let mut client = Arc::new(substrate_test_runtime_client::new());
+        let fork_choice = SessionForkChoice::new(client.clone());
+
+        // blocks imported before the rule was enabled, without a weight.
+        let mut parent_hash = client.info().genesis_hash;
+        let mut hashes = Vec::new();
+        for _ in 0..3 {
+            let block = BlockBuilderBuilder::new(&*client)
+                .on_parent_block(parent_hash)
+                .fetch_parent_block_number(&*client)
+                .unwrap()
+                .build()
+                .unwrap()
+                .build()
+                .unwrap()
+                .block;
+            parent_hash = block.hash();
+            hashes.push(parent_hash);
+            futures::executor::block_on(client.import(BlockOrigin::Own, block)).unwrap();
+        }
+
+        let weight_of_child = |parent_hash| {
+            let mut block = import_params(parent_hash);
+            fork_choice.fork_choice(&mut block, 4.into(), true).unwrap();
+            block.auxiliary[0].1.clone()
+        };
+
+        // they weigh as much as blocks of fallback authors.
+        let expected = |weight| {
+            Some(
+                ChainWeight {
+                    weight,
+                    slot: 4.into(),
+                }
+                .encode(),
+            )
+        };
+        assert_eq!(weight_of_child(hashes[2]), expected(5));
+
+        // and only count from the last finalized block.
+        client.finalize_block(hashes[1], None).unwrap();
+        assert_eq!(weight_of_child(hashes[2]), expected(3));
+    }
+}
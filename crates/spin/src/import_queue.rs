@@ -19,36 +19,74 @@
 //! Module implementing the logic for verifying and importing AuRa blocks.
 
 use crate::{
-    aux_data, standalone::SealVerificationError, AuraAuxData, AuthorityId, CompatibilityMode,
-    Error, LOG_TARGET,
+    aux_schema,
+    deferred::DeferredImportQueue,
+    find_pre_digest, find_versioned_pre_digest,
+    metrics::VerifierMetrics,
+    standalone::{Fallback, SealVerificationError, SessionMode},
+    AuraAuxData, AuthorityId, AuthorityIndex, CompatibilityMode, Error, ForkChoice, LOG_TARGET,
 };
-use codec::Codec;
-use log::{debug, info, trace};
+use codec::{Codec, Decode};
+use log::{debug, info, trace, warn};
 use prometheus_endpoint::Registry;
-use sc_client_api::{backend::AuxStore, BlockOf, UsageProvider};
+use sc_client_api::{
+    backend::AuxStore, AuxDataOperations, BlockOf, FinalityNotification, PreCommitActions,
+    UsageProvider,
+};
 use sc_consensus::{
     block_import::{BlockImport, BlockImportParams, ForkChoiceStrategy},
-    import_queue::{BasicQueue, BoxJustificationImport, DefaultImportQueue, Verifier},
+    import_queue::{BasicQueue, BoxJustificationImport, Verifier},
 };
 use sc_consensus_slots::{check_equivocation, CheckedHeader, InherentDataProviderExt};
 use sc_telemetry::{telemetry, TelemetryHandle, CONSENSUS_DEBUG, CONSENSUS_TRACE};
+use sc_transaction_pool_api::OffchainTransactionPoolFactory;
 use sp_api::{ApiExt, ProvideRuntimeApi};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
 use sp_blockchain::HeaderBackend;
 use sp_consensus::Error as ConsensusError;
-use sp_consensus_slots::Slot;
+use sp_consensus_slots::{Slot, SlotDuration};
 use sp_core::crypto::Pair;
 use sp_inherents::{CreateInherentDataProviders, InherentDataProvider as _};
 use sp_runtime::{
     traits::{Block as BlockT, Header, NumberFor},
     DigestItem,
 };
-use spin_primitives::{inherents::AuraInherentData, AuraApi};
-use std::{fmt::Debug, marker::PhantomData, sync::Arc};
+use spin_primitives::{inherents::AuraInherentData, AuraApi, EquivocationProof};
+use std::{fmt::Debug, marker::PhantomData, sync::Arc, time::Duration};
+
+/// How far ahead of the local clock the slot of an imported header may be.
+///
+/// Headers further ahead are held back until their slot, see [`DeferredImportQueue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftTolerance {
+    /// A fixed number of slots.
+    Slots(u64),
+    /// A wall-clock duration, rounded up to whole slots.
+    Duration(Duration),
+}
+
+impl Default for DriftTolerance {
+    fn default() -> Self {
+        Self::Slots(1)
+    }
+}
+
+impl DriftTolerance {
+    /// The tolerance in slots of the given duration.
+    pub fn slots(&self, slot_duration: SlotDuration) -> u64 {
+        match self {
+            Self::Slots(slots) => *slots,
+            Self::Duration(duration) => {
+                let slot_duration = slot_duration.as_millis().max(1);
+                (duration.as_millis() as u64).div_ceil(slot_duration)
+            }
+        }
+    }
+}
 
 /// check a header has been signed by the right key. If the slot is too far in the future, an error
-/// will be returned. If it's successful, returns the pre-header and the digest item
-/// containing the seal.
+/// will be returned. If it's successful, returns the pre-header, the digest item
+/// containing the seal and a proof of equivocation if the author is equivocating.
 ///
 /// This digest item will always return `Some` when used with `as_aura_seal`.
 fn check_header<C, B: BlockT, P: Pair>(
@@ -56,23 +94,51 @@ fn check_header<C, B: BlockT, P: Pair>(
     slot_now: Slot,
     header: B::Header,
     hash: B::Hash,
+    parent_slot: Option<Slot>,
     aux_data: &AuraAuxData<AuthorityId<P>>,
+    session_mode: SessionMode,
+    disabled: &[AuthorityIndex],
+    fallback: Option<Fallback>,
     check_for_equivocation: CheckForEquivocation,
-) -> Result<CheckedHeader<B::Header, (Slot, DigestItem)>, Error<B>>
+) -> Result<
+    CheckedHeader<
+        B::Header,
+        (
+            Slot,
+            DigestItem,
+            Option<EquivocationProof<B::Header, P::Public>>,
+        ),
+    >,
+    Error<B>,
+>
 where
     P::Public: Codec,
     P::Signature: Codec,
     C: sc_client_api::backend::AuxStore,
 {
-    let check_result =
-        crate::standalone::check_header_slot_and_seal::<B, P>(slot_now, header, aux_data);
+    let check_result = crate::standalone::check_header_slot_and_seal::<B, P>(
+        slot_now,
+        header,
+        parent_slot,
+        aux_data,
+        session_mode,
+        disabled,
+        fallback,
+    );
 
     match check_result {
         Ok((header, slot, seal)) => {
             let (authorities, session_index) = aux_data;
-            let expected_author =
-                crate::standalone::slot_author::<P>(slot, *session_index, &authorities);
+            let expected_author = crate::standalone::slot_author::<P>(
+                slot,
+                *session_index,
+                session_mode,
+                authorities,
+                disabled,
+                fallback,
+            );
             let should_equiv_check = check_for_equivocation.check_for_equivocation();
+            let mut equivocation = None;
             if let (true, Some(expected)) = (should_equiv_check, expected_author) {
                 if let Some(equivocation_proof) =
                     check_equivocation(client, slot_now, slot, &header, expected)
@@ -85,10 +151,11 @@ where
                         equivocation_proof.first_header.hash(),
                         equivocation_proof.second_header.hash(),
                     );
+                    equivocation = Some(equivocation_proof);
                 }
             }
 
-            Ok(CheckedHeader::Checked(header, (slot, seal)))
+            Ok(CheckedHeader::Checked(header, (slot, seal, equivocation)))
         }
         Err(SealVerificationError::Deferred(header, slot)) => {
             Ok(CheckedHeader::Deferred(header, slot))
@@ -98,26 +165,48 @@ where
         Err(SealVerificationError::BadSignature) => Err(Error::BadSignature(hash)),
         Err(SealVerificationError::SlotAuthorNotFound) => Err(Error::SlotAuthorNotFound),
         Err(SealVerificationError::InvalidPreDigest(e)) => Err(Error::from(e)),
+        Err(SealVerificationError::PreDigestMismatch { expected, claimed }) => {
+            Err(Error::PreDigestMismatch {
+                hash,
+                expected,
+                claimed,
+            })
+        }
+        Err(SealVerificationError::SlotNotIncreasing { slot, parent_slot }) => {
+            Err(Error::SlotNotIncreasing {
+                hash,
+                slot,
+                parent_slot,
+            })
+        }
     }
 }
 
 /// A verifier for Aura blocks.
-pub struct AuraVerifier<C, P, CIDP, N> {
+pub struct AuraVerifier<C, P, CIDP, FC, B: BlockT> {
     client: Arc<C>,
     create_inherent_data_providers: CIDP,
     check_for_equivocation: CheckForEquivocation,
     telemetry: Option<TelemetryHandle>,
-    compatibility_mode: CompatibilityMode<N>,
+    compatibility_mode: CompatibilityMode<NumberFor<B>>,
+    offchain_tx_pool_factory: OffchainTransactionPoolFactory<B>,
+    drift_tolerance: DriftTolerance,
+    metrics: Option<VerifierMetrics>,
+    fork_choice: FC,
     _phantom: PhantomData<fn() -> P>,
 }
 
-impl<C, P, CIDP, N> AuraVerifier<C, P, CIDP, N> {
+impl<C, P, CIDP, FC, B: BlockT> AuraVerifier<C, P, CIDP, FC, B> {
     pub(crate) fn new(
         client: Arc<C>,
         create_inherent_data_providers: CIDP,
         check_for_equivocation: CheckForEquivocation,
         telemetry: Option<TelemetryHandle>,
-        compatibility_mode: CompatibilityMode<N>,
+        compatibility_mode: CompatibilityMode<NumberFor<B>>,
+        offchain_tx_pool_factory: OffchainTransactionPoolFactory<B>,
+        drift_tolerance: DriftTolerance,
+        metrics: Option<VerifierMetrics>,
+        fork_choice: FC,
     ) -> Self {
         Self {
             client,
@@ -125,16 +214,76 @@ impl<C, P, CIDP, N> AuraVerifier<C, P, CIDP, N> {
             check_for_equivocation,
             telemetry,
             compatibility_mode,
+            offchain_tx_pool_factory,
+            drift_tolerance,
+            metrics,
+            fork_choice,
             _phantom: PhantomData,
         }
     }
 }
 
-impl<C, P, CIDP, N> AuraVerifier<C, P, CIDP, N>
+impl<C, P, CIDP, FC, B: BlockT> AuraVerifier<C, P, CIDP, FC, B>
+where
+    C: ProvideRuntimeApi<B> + HeaderBackend<B>,
+    C::Api: AuraApi<B, AuthorityId<P>>,
+    P: Pair,
+    P::Public: Codec,
+{
+    /// Submit an equivocation report to the runtime at the best block.
+    ///
+    /// Runtimes without support for equivocation reports are silently skipped.
+    fn report_equivocation(
+        &self,
+        equivocation_proof: EquivocationProof<B::Header, AuthorityId<P>>,
+    ) -> Result<(), Error<B>> {
+        let best_hash = self.client.info().best_hash;
+        let mut runtime_api = self.client.runtime_api();
+
+        if !runtime_api
+            .has_api_with::<dyn AuraApi<B, AuthorityId<P>>, _>(best_hash, |v| v >= 3)
+            .map_err(|e| Error::Client(e.into()))?
+        {
+            debug!(
+                target: LOG_TARGET,
+                "Runtime at {:?} doesn't support equivocation reports",
+                best_hash,
+            );
+            return Ok(());
+        }
+
+        // Register the offchain tx pool to be able to use it from the runtime.
+        runtime_api.register_extension(
+            self.offchain_tx_pool_factory
+                .offchain_transaction_pool(best_hash),
+        );
+
+        runtime_api
+            .submit_report_equivocation_unsigned_extrinsic(best_hash, equivocation_proof)
+            .map_err(|e| Error::Client(e.into()))?;
+
+        info!(target: LOG_TARGET, "Submitted equivocation report for author");
+
+        Ok(())
+    }
+}
+
+impl<C, P, CIDP, FC, B: BlockT> AuraVerifier<C, P, CIDP, FC, B> {
+    /// Account a failed verification and turn the error into the verifier's error type.
+    fn report_failure(&self, error: Error<B>) -> String {
+        if let Some(metrics) = &self.metrics {
+            metrics.report_failure(&error);
+        }
+
+        error.to_string()
+    }
+}
+
+impl<C, P, CIDP, FC, B: BlockT> AuraVerifier<C, P, CIDP, FC, B>
 where
     CIDP: Send,
 {
-    async fn check_inherents<B: BlockT>(
+    async fn check_inherents(
         &self,
         block: B,
         at_hash: B::Hash,
@@ -169,15 +318,16 @@ where
 }
 
 #[async_trait::async_trait]
-impl<B: BlockT, C, P, CIDP> Verifier<B> for AuraVerifier<C, P, CIDP, NumberFor<B>>
+impl<B: BlockT, C, P, CIDP, FC> Verifier<B> for AuraVerifier<C, P, CIDP, FC, B>
 where
-    C: ProvideRuntimeApi<B> + Send + Sync + sc_client_api::backend::AuxStore,
+    C: ProvideRuntimeApi<B> + HeaderBackend<B> + Send + Sync + sc_client_api::backend::AuxStore,
     C::Api: BlockBuilderApi<B> + AuraApi<B, AuthorityId<P>> + ApiExt<B>,
     P: Pair,
     P::Public: Codec + Debug,
     P::Signature: Codec,
     CIDP: CreateInherentDataProviders<B, ()> + Send + Sync,
     CIDP::InherentDataProviders: InherentDataProviderExt + Send + Sync,
+    FC: ForkChoice<B>,
 {
     async fn verify(
         &self,
@@ -197,13 +347,45 @@ where
 
         let hash = block.header.hash();
         let parent_hash = *block.header.parent_hash();
-        let aux_data = aux_data(
-            self.client.as_ref(),
-            parent_hash,
-            *block.header.number(),
-            &self.compatibility_mode,
+        let parent_header = self
+            .client
+            .header(parent_hash)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Parent header {:?} not found", parent_hash))?;
+
+        // use the session state tracked from the consensus logs, only falling back to the
+        // runtime for parents which were not imported through the verifier. The session and
+        // author index carried by the pre-digest are checked against this state, not trusted, so
+        // headers whose parent has neither can't be verified here, see `header_chain` instead.
+        let (parent_state, parent_anchor) =
+            crate::standalone::fetch_session_state::<AuthorityId<P>, B, C>(
+                self.client.as_ref(),
+                &parent_header,
+                &self.compatibility_mode,
+            )
+            .map_err(|e| format!("Could not fetch session state at {:?}: {}", parent_hash, e))?;
+
+        // chains launched with slot-only pre-digests move on to the session and author index at
+        // the block configured by the compatibility mode.
+        let pre_digest = find_versioned_pre_digest::<B, P::Signature>(&block.header)
+            .map_err(|e| self.report_failure(e.into()))?;
+        if pre_digest.session_and_author().is_none()
+            && !self
+                .compatibility_mode
+                .slot_only_pre_digest(block.header.number())
+        {
+            return Err(self.report_failure(Error::SlotOnlyPreDigest(hash)));
+        }
+
+        let aux_data = parent_state.aux_data();
+        let parent_slot = crate::standalone::parent_slot::<B, P::Signature>(&parent_header)
+            .map_err(|e| e.to_string())?;
+        let fallback = crate::standalone::fallback_at::<B, P::Signature, _>(
+            &parent_header,
+            &parent_state,
+            pre_digest.slot(),
         )
-        .map_err(|e| format!("Could not fetch authorities at {:?}: {}", parent_hash, e))?;
+        .map_err(|e| e.to_string())?;
 
         let create_inherent_data_providers = self
             .create_inherent_data_providers
@@ -218,20 +400,38 @@ where
 
         let slot_now = create_inherent_data_providers.slot();
 
-        // we add one to allow for some small drift.
-        // FIXME #1019 in the future, alter this queue to allow deferring of
-        // headers
+        // we allow for some small drift, headers further in the future are held back by the
+        // `DeferredImportQueue` until their slot.
+        let drift = match self.drift_tolerance {
+            DriftTolerance::Slots(slots) => slots,
+            tolerance => {
+                let slot_duration =
+                    crate::standalone::slot_duration_at(self.client.as_ref(), parent_hash)
+                        .map_err(|e| e.to_string())?;
+                tolerance.slots(slot_duration)
+            }
+        };
         let checked_header = check_header::<C, B, P>(
             &self.client,
-            slot_now + 1,
+            slot_now + drift,
             block.header,
             hash,
+            parent_slot,
             &aux_data,
+            parent_state.session_mode,
+            &parent_state.disabled,
+            fallback,
             self.check_for_equivocation,
         )
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| self.report_failure(e))?;
         match checked_header {
-            CheckedHeader::Checked(pre_header, (slot, seal)) => {
+            CheckedHeader::Checked(pre_header, (slot, seal, equivocation)) => {
+                if let Some(equivocation_proof) = equivocation {
+                    if let Err(err) = self.report_equivocation(equivocation_proof) {
+                        warn!(target: LOG_TARGET, "Error reporting equivocation: {}", err);
+                    }
+                }
+
                 // if the body is passed through, we need to use the runtime
                 // to check that the internally-set timestamp in the inherents
                 // actually matches the slot set in the seal.
@@ -255,7 +455,7 @@ where
                             create_inherent_data_providers,
                         )
                         .await
-                        .map_err(|e| e.to_string())?;
+                        .map_err(|e| self.report_failure(e))?;
                     }
 
                     let (_, inner_body) = new_block.deconstruct();
@@ -270,10 +470,26 @@ where
                     "pre_header" => ?pre_header,
                 );
 
+                let fallback_offset = parent_state.fallback_offset_at(slot, fallback);
+                let (_, (key, value)) = aux_schema::session_state_entry(
+                    hash,
+                    &pre_header,
+                    parent_state,
+                    parent_anchor,
+                    fallback_offset,
+                );
+                block.auxiliary.push((key, Some(value)));
+
                 block.header = pre_header;
                 block.post_digests.push(seal);
-                block.fork_choice = Some(ForkChoiceStrategy::LongestChain);
                 block.post_hash = Some(hash);
+                self.fork_choice
+                    .fork_choice(
+                        &mut block,
+                        slot,
+                        fallback.map_or(true, |fallback| fallback.offset(slot) == 0),
+                    )
+                    .map_err(|e| e.to_string())?;
 
                 Ok(block)
             }
@@ -318,7 +534,7 @@ impl Default for CheckForEquivocation {
 }
 
 /// Parameters of [`import_queue`].
-pub struct ImportQueueParams<'a, Block: BlockT, I, C, S, CIDP> {
+pub struct ImportQueueParams<'a, Block: BlockT, I, C, S, CIDP, FC> {
     /// The block import to use.
     pub block_import: I,
     /// The justification import.
@@ -339,10 +555,24 @@ pub struct ImportQueueParams<'a, Block: BlockT, I, C, S, CIDP> {
     ///
     /// If in doubt, use `Default::default()`.
     pub compatibility_mode: CompatibilityMode<NumberFor<Block>>,
+    /// The offchain transaction pool factory.
+    ///
+    /// Will be used when sending equivocation reports.
+    pub offchain_tx_pool_factory: OffchainTransactionPoolFactory<Block>,
+    /// How far ahead of the local clock imported headers may be.
+    ///
+    /// If in doubt, use `Default::default()`.
+    pub drift_tolerance: DriftTolerance,
+    /// The fork choice rule of the imported blocks.
+    ///
+    /// If in doubt, use [`LongestChainForkChoice`](crate::LongestChainForkChoice).
+    pub fork_choice: FC,
 }
 
 /// Start an import queue for the Aura consensus algorithm.
-pub fn import_queue<P, Block, I, C, S, CIDP>(
+///
+/// The session states and chain weights recorded on import are pruned as blocks get finalized.
+pub fn import_queue<P, Block, I, C, S, CIDP, FC>(
     ImportQueueParams {
         block_import,
         justification_import,
@@ -353,8 +583,11 @@ pub fn import_queue<P, Block, I, C, S, CIDP>(
         check_for_equivocation,
         telemetry,
         compatibility_mode,
-    }: ImportQueueParams<Block, I, C, S, CIDP>,
-) -> Result<DefaultImportQueue<Block>, sp_consensus::Error>
+        offchain_tx_pool_factory,
+        drift_tolerance,
+        fork_choice,
+    }: ImportQueueParams<Block, I, C, S, CIDP, FC>,
+) -> Result<DeferredImportQueue<Block>, sp_consensus::Error>
 where
     Block: BlockT,
     C::Api: BlockBuilderApi<Block> + AuraApi<Block, AuthorityId<P>> + ApiExt<Block>,
@@ -365,7 +598,8 @@ where
         + Sync
         + AuxStore
         + UsageProvider<Block>
-        + HeaderBackend<Block>,
+        + HeaderBackend<Block>
+        + PreCommitActions<Block>,
     I: BlockImport<Block, Error = ConsensusError> + Send + Sync + 'static,
     P: Pair + 'static,
     P::Public: Codec + Debug,
@@ -373,26 +607,100 @@ where
     S: sp_core::traits::SpawnEssentialNamed,
     CIDP: CreateInherentDataProviders<Block, ()> + Sync + Send + 'static,
     CIDP::InherentDataProviders: InherentDataProviderExt + Send + Sync,
+    FC: ForkChoice<Block> + 'static,
 {
-    let verifier = build_verifier::<P, _, _, _>(BuildVerifierParams {
+    let slot_duration =
+        crate::slot_duration(&*client).map_err(|e| ConsensusError::ClientImport(e.to_string()))?;
+
+    let client_weak = Arc::downgrade(&client);
+    client.register_finality_action(Box::new(
+        move |notification: &FinalityNotification<Block>| {
+            client_weak
+                .upgrade()
+                .map_or_else(Default::default, |client| {
+                    aux_storage_cleanup::<AuthorityId<P>, _, _>(client.as_ref(), notification)
+                })
+        },
+    ));
+
+    let verifier = build_verifier::<P, _, _, _, _>(BuildVerifierParams {
         client,
         create_inherent_data_providers,
         check_for_equivocation,
         telemetry,
         compatibility_mode,
+        offchain_tx_pool_factory,
+        drift_tolerance,
+        prometheus_registry: registry.cloned(),
+        fork_choice,
     });
 
-    Ok(BasicQueue::new(
+    let queue = BasicQueue::new(
         verifier,
         Box::new(block_import),
         justification_import,
         spawner,
         registry,
+    );
+
+    Ok(DeferredImportQueue::new(
+        queue,
+        slot_duration,
+        drift_tolerance.slots(slot_duration),
+        |header| find_pre_digest::<Block, P::Signature>(header).ok(),
     ))
 }
 
+/// Prune the session states and chain weights of the blocks retracted by the finalization of a
+/// block.
+fn aux_storage_cleanup<A, B, C>(
+    client: &C,
+    notification: &FinalityNotification<B>,
+) -> AuxDataOperations
+where
+    A: Decode,
+    B: BlockT,
+    C: HeaderBackend<B> + AuxStore,
+{
+    let mut retracted = notification.tree_route.to_vec();
+    let first = notification
+        .tree_route
+        .first()
+        .unwrap_or(&notification.hash);
+    match client.header(*first) {
+        Ok(Some(header)) => retracted.push(*header.parent_hash()),
+        Ok(None) => {}
+        Err(e) => warn!(target: LOG_TARGET, "Failed to look up finalized block {first:?}: {e}"),
+    }
+
+    // walk the stale forks back to the canonical chain.
+    for head in notification.stale_heads.iter() {
+        let mut hash = *head;
+        while let Ok(Some(header)) = client.header(hash) {
+            if client.hash(*header.number()).ok().flatten() == Some(hash) {
+                break;
+            }
+            retracted.push(hash);
+            hash = *header.parent_hash();
+        }
+    }
+
+    let mut operations =
+        aux_schema::prune_session_states::<A, _, _>(client, notification.hash, retracted.clone())
+            .unwrap_or_else(|e| {
+                warn!(target: LOG_TARGET, "Failed to prune session states: {e}");
+                Vec::new()
+            });
+    operations.extend(aux_schema::prune_chain_weights(
+        notification.hash,
+        retracted,
+    ));
+
+    operations
+}
+
 /// Parameters of [`build_verifier`].
-pub struct BuildVerifierParams<C, CIDP, N> {
+pub struct BuildVerifierParams<C, CIDP, FC, B: BlockT> {
     /// The client to interact with the chain.
     pub client: Arc<C>,
     /// Something that can create the inherent data providers.
@@ -404,24 +712,44 @@ pub struct BuildVerifierParams<C, CIDP, N> {
     /// Compatibility mode that should be used.
     ///
     /// If in doubt, use `Default::default()`.
-    pub compatibility_mode: CompatibilityMode<N>,
+    pub compatibility_mode: CompatibilityMode<NumberFor<B>>,
+    /// The offchain transaction pool factory.
+    ///
+    /// Will be used when sending equivocation reports.
+    pub offchain_tx_pool_factory: OffchainTransactionPoolFactory<B>,
+    /// How far ahead of the local clock imported headers may be.
+    ///
+    /// If in doubt, use `Default::default()`.
+    pub drift_tolerance: DriftTolerance,
+    /// The prometheus registry to register the verifier metrics on.
+    pub prometheus_registry: Option<Registry>,
+    /// The fork choice rule of the imported blocks.
+    pub fork_choice: FC,
 }
 
 /// Build the [`AuraVerifier`]
-pub fn build_verifier<P, C, CIDP, N>(
+pub fn build_verifier<P, C, CIDP, FC, B: BlockT>(
     BuildVerifierParams {
         client,
         create_inherent_data_providers,
         check_for_equivocation,
         telemetry,
         compatibility_mode,
-    }: BuildVerifierParams<C, CIDP, N>,
-) -> AuraVerifier<C, P, CIDP, N> {
-    AuraVerifier::<_, P, _, _>::new(
+        offchain_tx_pool_factory,
+        drift_tolerance,
+        prometheus_registry,
+        fork_choice,
+    }: BuildVerifierParams<C, CIDP, FC, B>,
+) -> AuraVerifier<C, P, CIDP, FC, B> {
+    AuraVerifier::<_, P, _, _, _>::new(
         client,
         create_inherent_data_providers,
         check_for_equivocation,
         telemetry,
         compatibility_mode,
+        offchain_tx_pool_factory,
+        drift_tolerance,
+        VerifierMetrics::register_or_warn(prometheus_registry.as_ref()),
+        fork_choice,
     )
 }
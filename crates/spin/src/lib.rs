@@ -30,42 +30,69 @@
 //!
 //! NOTE: Aura itself is designed to be generic over the crypto used.
 #![forbid(missing_docs, unsafe_code)]
-use std::{fmt::Debug, marker::PhantomData, pin::Pin, sync::Arc};
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use codec::Codec;
-use futures::prelude::*;
+use futures::{future::Either, prelude::*};
+use futures_timer::Delay;
 
+use prometheus_endpoint::Registry;
 use sc_client_api::{backend::AuxStore, BlockOf};
-use sc_consensus::{BlockImport, BlockImportParams, ForkChoiceStrategy, StateAction};
+use sc_consensus::{BlockImport, BlockImportParams, StateAction};
 use sc_consensus_slots::{
     BackoffAuthoringBlocksStrategy, InherentDataProviderExt, SimpleSlotWorkerToSlotWorker,
     SlotInfo, StorageChanges,
 };
-use sc_telemetry::TelemetryHandle;
-use sp_api::{Core, ProvideRuntimeApi};
+use sc_telemetry::{telemetry, TelemetryHandle, CONSENSUS_INFO};
+use sp_api::ProvideRuntimeApi;
 use sp_application_crypto::AppPublic;
 use sp_blockchain::HeaderBackend;
-use sp_consensus::{BlockOrigin, Environment, Error as ConsensusError, Proposer, SelectChain};
+use sp_consensus::{
+    BlockOrigin, Environment, Error as ConsensusError, Proposal, Proposer, SelectChain,
+};
 use sp_consensus_slots::Slot;
 use sp_core::crypto::Pair;
-use sp_inherents::CreateInherentDataProviders;
+use sp_inherents::{CreateInherentDataProviders, InherentDataProvider as _};
 use sp_keystore::KeystorePtr;
-use sp_runtime::traits::{Block as BlockT, Header, Member, NumberFor};
+use sp_runtime::{
+    traits::{Block as BlockT, Header, Member, NumberFor, One, Saturating},
+    SaturatedConversion,
+};
 
+mod aux_schema;
+mod deferred;
+mod fork_choice;
 mod import_queue;
+pub mod manual_seal;
+mod metrics;
+mod proposal_budget;
 pub mod standalone;
 
-pub use crate::standalone::{find_pre_digest, slot_duration};
+use crate::{metrics::WorkerMetrics, proposal_budget::CutShort};
+
+pub use crate::standalone::{
+    find_pre_digest, find_versioned_pre_digest, slot_duration, Fallback, SessionMode,
+};
+pub use aux_schema::SessionState;
+pub use deferred::DeferredImportQueue;
+pub use fork_choice::{ForkChoice, LongestChainForkChoice, SessionForkChoice};
 pub use import_queue::{
     build_verifier, import_queue, AuraVerifier, BuildVerifierParams, CheckForEquivocation,
-    ImportQueueParams,
+    DriftTolerance, ImportQueueParams,
 };
-pub use sc_consensus_slots::SlotProportion;
+pub use proposal_budget::{ProposalBudget, TimedBlockImport, BUDGET_HISTORY};
+pub use sc_consensus_slots::{BackoffAuthoringOnFinalizedHeadLagging, SlotProportion};
 pub use sp_consensus::SyncOracle;
 pub use spin_primitives::{
-    digests::CompatibleDigestItem,
+    digests::{CompatibleDigestItem, PreDigest},
     inherents::{InherentDataProvider, InherentType as AuraInherent, INHERENT_IDENTIFIER},
-    AuraApi, AuraAuxData, ConsensusLog, SessionIndex, SlotDuration, AURA_ENGINE_ID,
+    AuraApi, AuraAuxData, AuthorityIndex, ConsensusLog, EquivocationProof, SessionIndex,
+    SlotDuration, AURA_ENGINE_ID,
 };
 
 const LOG_TARGET: &str = "aura";
@@ -101,6 +128,16 @@ pub enum CompatibilityMode<N> {
         /// configuration) and the new nodes.
         until: N,
     },
+    /// Author and accept pre-digests carrying only the slot.
+    ///
+    /// Chains launched before the session and author index were added to the pre-digest keep
+    /// verifying in this mode. Blocks from `until` on are authored with the session and author
+    /// index, and must carry them to be imported. All nodes should have upgraded to a release
+    /// using this mode before `until`.
+    SlotOnlyPreDigest {
+        /// Number of the first block carrying the session and author index.
+        until: N,
+    },
 }
 
 impl<N> Default for CompatibilityMode<N> {
@@ -109,8 +146,35 @@ impl<N> Default for CompatibilityMode<N> {
     }
 }
 
+impl<N: PartialOrd> CompatibilityMode<N> {
+    /// Whether the block with the given number may carry a pre-digest with the slot only.
+    pub fn slot_only_pre_digest(&self, number: &N) -> bool {
+        matches!(self, Self::SlotOnlyPreDigest { until } if number < until)
+    }
+
+    /// The pre-digest of the block with the given number, authored at `slot` by the authority
+    /// with index `authority_index` in session `session_index`.
+    pub fn pre_digest(
+        &self,
+        number: &N,
+        slot: Slot,
+        session_index: SessionIndex,
+        authority_index: AuthorityIndex,
+    ) -> PreDigest {
+        if self.slot_only_pre_digest(number) {
+            PreDigest::V0(slot)
+        } else {
+            PreDigest::V1 {
+                slot,
+                session_index,
+                authority_index,
+            }
+        }
+    }
+}
+
 /// Parameters of [`start_aura`].
-pub struct StartAuraParams<C, SC, I, PF, SO, L, CIDP, BS, N> {
+pub struct StartAuraParams<C, SC, I, PF, SO, L, CIDP, BS, FC, N> {
     /// The duration of a slot.
     pub slot_duration: SlotDuration,
     /// The client to interact with the chain.
@@ -142,16 +206,25 @@ pub struct StartAuraParams<C, SC, I, PF, SO, L, CIDP, BS, N> {
     /// The maximum proportion of the slot dedicated to proposing with any lenience factor applied
     /// due to no blocks being produced.
     pub max_block_proposal_slot_portion: Option<SlotProportion>,
+    /// Budget adapting the proportion of the slot dedicated to proposing to recent import
+    /// durations, starting from `block_proposal_slot_portion`.
+    ///
+    /// The proportion stays fixed if `None`.
+    pub proposal_budget: Option<ProposalBudget>,
     /// Telemetry instance used to report telemetry metrics.
     pub telemetry: Option<TelemetryHandle>,
     /// Compatibility mode that should be used.
     ///
     /// If in doubt, use `Default::default()`.
     pub compatibility_mode: CompatibilityMode<N>,
+    /// The prometheus registry to register the worker metrics on.
+    pub prometheus_registry: Option<Registry>,
+    /// The fork choice rule of the authored blocks, which should match the one of the verifier.
+    pub fork_choice: FC,
 }
 
 /// Start the aura worker. The returned future should be run in a futures executor.
-pub fn start_aura<P, B, C, SC, I, PF, SO, L, CIDP, BS, Error>(
+pub fn start_aura<P, B, C, SC, I, PF, SO, L, CIDP, BS, FC, Error>(
     StartAuraParams {
         slot_duration,
         client,
@@ -166,9 +239,12 @@ pub fn start_aura<P, B, C, SC, I, PF, SO, L, CIDP, BS, Error>(
         keystore,
         block_proposal_slot_portion,
         max_block_proposal_slot_portion,
+        proposal_budget,
         telemetry,
         compatibility_mode,
-    }: StartAuraParams<C, SC, I, PF, SO, L, CIDP, BS, NumberFor<B>>,
+        prometheus_registry,
+        fork_choice,
+    }: StartAuraParams<C, SC, I, PF, SO, L, CIDP, BS, FC, NumberFor<B>>,
 ) -> Result<impl Future<Output = ()>, ConsensusError>
 where
     P: Pair,
@@ -186,9 +262,10 @@ where
     CIDP: CreateInherentDataProviders<B, ()> + Send + 'static,
     CIDP::InherentDataProviders: InherentDataProviderExt + Send,
     BS: BackoffAuthoringBlocksStrategy<NumberFor<B>> + Send + Sync + 'static,
+    FC: ForkChoice<B> + 'static,
     Error: std::error::Error + Send + From<ConsensusError> + 'static,
 {
-    let worker = build_aura_worker::<P, _, _, _, _, _, _, _, _>(BuildAuraWorkerParams {
+    let worker = build_aura_worker::<P, _, _, _, _, _, _, _, _, _>(BuildAuraWorkerParams {
         client,
         block_import,
         proposer_factory,
@@ -200,7 +277,10 @@ where
         telemetry,
         block_proposal_slot_portion,
         max_block_proposal_slot_portion,
+        proposal_budget,
         compatibility_mode,
+        prometheus_registry,
+        fork_choice,
     });
 
     Ok(sc_consensus_slots::start_slot_worker(
@@ -213,7 +293,7 @@ where
 }
 
 /// Parameters of [`build_aura_worker`].
-pub struct BuildAuraWorkerParams<C, I, PF, SO, L, BS, N> {
+pub struct BuildAuraWorkerParams<C, I, PF, SO, L, BS, FC, N> {
     /// The client to interact with the chain.
     pub client: Arc<C>,
     /// The block import.
@@ -239,18 +319,27 @@ pub struct BuildAuraWorkerParams<C, I, PF, SO, L, BS, N> {
     /// The maximum proportion of the slot dedicated to proposing with any lenience factor applied
     /// due to no blocks being produced.
     pub max_block_proposal_slot_portion: Option<SlotProportion>,
+    /// Budget adapting the proportion of the slot dedicated to proposing to recent import
+    /// durations, starting from `block_proposal_slot_portion`.
+    ///
+    /// The proportion stays fixed if `None`.
+    pub proposal_budget: Option<ProposalBudget>,
     /// Telemetry instance used to report telemetry metrics.
     pub telemetry: Option<TelemetryHandle>,
     /// Compatibility mode that should be used.
     ///
     /// If in doubt, use `Default::default()`.
     pub compatibility_mode: CompatibilityMode<N>,
+    /// The prometheus registry to register the worker metrics on.
+    pub prometheus_registry: Option<Registry>,
+    /// The fork choice rule of the authored blocks, which should match the one of the verifier.
+    pub fork_choice: FC,
 }
 
 /// Build the aura worker.
 ///
 /// The caller is responsible for running this worker, otherwise it will do nothing.
-pub fn build_aura_worker<P, B, C, PF, I, SO, L, BS, Error>(
+pub fn build_aura_worker<P, B, C, PF, I, SO, L, BS, FC, Error>(
     BuildAuraWorkerParams {
         client,
         block_import,
@@ -261,18 +350,21 @@ pub fn build_aura_worker<P, B, C, PF, I, SO, L, BS, Error>(
         keystore,
         block_proposal_slot_portion,
         max_block_proposal_slot_portion,
+        proposal_budget,
         telemetry,
         force_authoring,
         compatibility_mode,
-    }: BuildAuraWorkerParams<C, I, PF, SO, L, BS, NumberFor<B>>,
+        prometheus_registry,
+        fork_choice,
+    }: BuildAuraWorkerParams<C, I, PF, SO, L, BS, FC, NumberFor<B>>,
 ) -> impl sc_consensus_slots::SimpleSlotWorker<
     B,
     Proposer = PF::Proposer,
     BlockImport = I,
     SyncOracle = SO,
     JustificationSyncLink = L,
-    Claim = P::Public,
-    AuxData = (Vec<AuthorityId<P>>, SessionIndex),
+    Claim = SlotClaim<P::Public>,
+    AuxData = (SessionState<AuthorityId<P>>, Option<B::Hash>),
 >
 where
     B: BlockT,
@@ -288,6 +380,7 @@ where
     SO: SyncOracle + Send + Sync + Clone,
     L: sc_consensus::JustificationSyncLink<B>,
     BS: BackoffAuthoringBlocksStrategy<NumberFor<B>> + Send + Sync + 'static,
+    FC: ForkChoice<B> + 'static,
 {
     AuraWorker {
         client,
@@ -301,12 +394,27 @@ where
         telemetry,
         block_proposal_slot_portion,
         max_block_proposal_slot_portion,
+        proposal_budget,
         compatibility_mode,
+        metrics: WorkerMetrics::register_or_warn(prometheus_registry.as_ref()),
+        fork_choice,
         _phantom: PhantomData::<fn() -> P>,
     }
 }
 
-struct AuraWorker<C, E, I, P, SO, L, BS, N> {
+/// A slot claimed by a local authority.
+#[derive(Debug, Clone)]
+pub struct SlotClaim<Public> {
+    /// Key of the slot author.
+    pub public: Public,
+    /// Pre-digest of the block to author.
+    pub pre_digest: PreDigest,
+    /// Session the block is authored in and the fallback offset of the slot author, zero for the
+    /// author scheduled for the session.
+    pub fallback_offset: (SessionIndex, u64),
+}
+
+struct AuraWorker<C, E, I, P, SO, L, BS, FC, N> {
     client: Arc<C>,
     block_import: I,
     env: E,
@@ -317,17 +425,41 @@ struct AuraWorker<C, E, I, P, SO, L, BS, N> {
     backoff_authoring_blocks: Option<BS>,
     block_proposal_slot_portion: SlotProportion,
     max_block_proposal_slot_portion: Option<SlotProportion>,
+    proposal_budget: Option<ProposalBudget>,
     telemetry: Option<TelemetryHandle>,
     compatibility_mode: CompatibilityMode<N>,
+    metrics: Option<WorkerMetrics>,
+    fork_choice: FC,
     _phantom: PhantomData<fn() -> P>,
 }
 
+impl<C, E, I, P, SO, L, BS, FC, N> AuraWorker<C, E, I, P, SO, L, BS, FC, N> {
+    fn report_cut_short(&self, slot: Slot, reason: CutShort) {
+        log::debug!(
+            target: LOG_TARGET,
+            "Proposal for slot {} cut short: {}",
+            slot,
+            reason.label(),
+        );
+        telemetry!(
+            self.telemetry;
+            CONSENSUS_INFO;
+            "spin.proposal_cut_short";
+            "slot" => *slot,
+            "reason" => reason.label(),
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.report_cut_short(reason);
+        }
+    }
+}
+
 #[async_trait::async_trait]
-impl<B, C, E, I, P, Error, SO, L, BS> sc_consensus_slots::SimpleSlotWorker<B>
-    for AuraWorker<C, E, I, P, SO, L, BS, NumberFor<B>>
+impl<B, C, E, I, P, Error, SO, L, BS, FC> sc_consensus_slots::SimpleSlotWorker<B>
+    for AuraWorker<C, E, I, P, SO, L, BS, FC, NumberFor<B>>
 where
     B: BlockT,
-    C: ProvideRuntimeApi<B> + BlockOf + HeaderBackend<B> + Sync,
+    C: ProvideRuntimeApi<B> + BlockOf + AuxStore + HeaderBackend<B> + Sync,
     C::Api: AuraApi<B, AuthorityId<P>>,
     E: Environment<B, Error = Error> + Send + Sync,
     E::Proposer: Proposer<B, Error = Error>,
@@ -338,6 +470,7 @@ where
     SO: SyncOracle + Send + Clone + Sync,
     L: sc_consensus::JustificationSyncLink<B>,
     BS: BackoffAuthoringBlocksStrategy<NumberFor<B>> + Send + Sync + 'static,
+    FC: ForkChoice<B>,
     Error: std::error::Error + Send + From<ConsensusError> + 'static,
 {
     type BlockImport = I;
@@ -346,8 +479,8 @@ where
     type CreateProposer =
         Pin<Box<dyn Future<Output = Result<E::Proposer, ConsensusError>> + Send + 'static>>;
     type Proposer = E::Proposer;
-    type Claim = P::Public;
-    type AuxData = AuraAuxData<AuthorityId<P>>;
+    type Claim = SlotClaim<P::Public>;
+    type AuxData = (SessionState<AuthorityId<P>>, Option<B::Hash>);
 
     fn logging_target(&self) -> &'static str {
         "aura"
@@ -358,29 +491,76 @@ where
     }
 
     fn aux_data(&self, header: &B::Header, _slot: Slot) -> Result<Self::AuxData, ConsensusError> {
-        aux_data(
+        crate::standalone::fetch_session_state(
             self.client.as_ref(),
-            header.hash(),
-            *header.number() + 1u32.into(),
+            header,
             &self.compatibility_mode,
         )
     }
 
-    fn authorities_len(&self, aux_data: &Self::AuxData) -> Option<usize> {
-        Some(aux_data.0.len())
+    fn authorities_len(&self, (state, _): &Self::AuxData) -> Option<usize> {
+        Some(state.authorities.len())
     }
 
     async fn claim_slot(
         &mut self,
-        _header: &B::Header,
+        header: &B::Header,
         slot: Slot,
-        aux_data: &Self::AuxData,
+        (state, _): &Self::AuxData,
     ) -> Option<Self::Claim> {
-        crate::standalone::claim_slot::<P>(slot, aux_data, &self.keystore).await
+        let fallback =
+            match crate::standalone::fallback_at::<B, P::Signature, _>(header, state, slot) {
+                Ok(fallback) => fallback,
+                Err(e) => {
+                    log::warn!(
+                        target: LOG_TARGET,
+                        "Unable to build fallback rule at {:?}: {}",
+                        header.hash(),
+                        e,
+                    );
+                    return None;
+                }
+            };
+        let session_mode = state.session_mode;
+        let disabled = &state.disabled;
+
+        let claim = crate::standalone::claim_slot::<P>(
+            slot,
+            &state.aux_data(),
+            session_mode,
+            disabled,
+            fallback,
+            &self.keystore,
+        )
+        .await;
+
+        let session_idx = session_mode.session_index(slot, state.session_index);
+        if let Some(metrics) = &self.metrics {
+            let session_author = spin_primitives::session_author_index(
+                session_idx,
+                0,
+                state.authorities.len(),
+                disabled,
+            );
+            metrics.report_session(session_idx, session_author);
+            metrics.report_claim(claim.is_some());
+        }
+
+        let number = *header.number() + One::one();
+        claim.map(|(public, authority_index)| SlotClaim {
+            public,
+            pre_digest: self.compatibility_mode.pre_digest(
+                &number,
+                slot,
+                session_idx,
+                authority_index,
+            ),
+            fallback_offset: state.fallback_offset_at(slot, fallback),
+        })
     }
 
-    fn pre_digest_data(&self, slot: Slot, _claim: &Self::Claim) -> Vec<sp_runtime::DigestItem> {
-        vec![crate::standalone::pre_digest::<P>(slot)]
+    fn pre_digest_data(&self, _slot: Slot, claim: &Self::Claim) -> Vec<sp_runtime::DigestItem> {
+        vec![crate::standalone::pre_digest::<P>(claim.pre_digest)]
     }
 
     async fn block_import_params(
@@ -389,22 +569,132 @@ where
         header_hash: &B::Hash,
         body: Vec<B::Extrinsic>,
         storage_changes: StorageChanges<B>,
-        public: Self::Claim,
-        _authorities: Self::AuxData,
+        claim: Self::Claim,
+        (state, anchor): Self::AuxData,
     ) -> Result<sc_consensus::BlockImportParams<B>, ConsensusError> {
         let signature_digest_item =
-            crate::standalone::seal::<_, P>(header_hash, &public, &self.keystore)?;
+            crate::standalone::seal::<_, P>(header_hash, &claim.public, &self.keystore)
+                .inspect_err(|_| {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.report_seal_failure();
+                    }
+                })?;
 
         let mut import_block = BlockImportParams::new(BlockOrigin::Own, header);
         import_block.post_digests.push(signature_digest_item);
+        let (_, (key, value)) = aux_schema::session_state_entry(
+            import_block.post_hash(),
+            &import_block.header,
+            state,
+            anchor,
+            claim.fallback_offset,
+        );
+        import_block.auxiliary.push((key, Some(value)));
         import_block.body = Some(body);
         import_block.state_action =
             StateAction::ApplyChanges(sc_consensus::StorageChanges::Changes(storage_changes));
-        import_block.fork_choice = Some(ForkChoiceStrategy::LongestChain);
+        self.fork_choice.fork_choice(
+            &mut import_block,
+            claim.pre_digest.slot(),
+            claim.fallback_offset.1 == 0,
+        )?;
 
         Ok(import_block)
     }
 
+    async fn propose(
+        &mut self,
+        proposer: Self::Proposer,
+        claim: &Self::Claim,
+        slot_info: SlotInfo<B>,
+        end_proposing_at: Instant,
+    ) -> Option<Proposal<B, <Self::Proposer as Proposer<B>>::Proof>> {
+        let slot = slot_info.slot;
+        let started = Instant::now();
+        let limited_by_imports = self
+            .proposal_budget
+            .as_ref()
+            .is_some_and(|budget| budget.limited_by_imports(slot_info.duration));
+        let time_left = || end_proposing_at.saturating_duration_since(Instant::now());
+
+        let inherent_data = match future::select(
+            slot_info
+                .create_inherent_data
+                .create_inherent_data()
+                .boxed(),
+            Delay::new(time_left()),
+        )
+        .await
+        {
+            Either::Left((Ok(inherent_data), _)) => inherent_data,
+            Either::Left((Err(e), _)) => {
+                log::warn!(
+                    target: LOG_TARGET,
+                    "Unable to create inherent data for slot {}: {}",
+                    slot,
+                    e,
+                );
+                return None;
+            }
+            Either::Right(_) => {
+                log::info!(
+                    target: LOG_TARGET,
+                    "⌛️ Discarding proposal for slot {}; creating inherent data took too long",
+                    slot,
+                );
+                self.report_cut_short(slot, CutShort::InherentData);
+                return None;
+            }
+        };
+
+        // The proposer gets 98% of the time left, so that the block is returned before the end.
+        let time_left = time_left();
+        let proposing = proposer.propose(
+            inherent_data,
+            sp_runtime::Digest {
+                logs: self.pre_digest_data(slot, claim),
+            },
+            time_left.mul_f32(0.98),
+            slot_info.block_size_limit,
+        );
+
+        let proposal = match future::select(proposing, Delay::new(time_left)).await {
+            Either::Left((Ok(proposal), _)) => proposal,
+            Either::Left((Err(e), _)) => {
+                log::warn!(target: LOG_TARGET, "Proposing failed: {}", e);
+                return None;
+            }
+            Either::Right(_) => {
+                log::info!(
+                    target: LOG_TARGET,
+                    "⌛️ Discarding proposal for slot {}; block production took too long",
+                    slot,
+                );
+                telemetry!(
+                    self.telemetry;
+                    CONSENSUS_INFO;
+                    "slots.discarding_proposal_took_too_long";
+                    "slot" => *slot,
+                );
+                self.report_cut_short(slot, CutShort::Discarded);
+                return None;
+            }
+        };
+
+        let elapsed = started.elapsed();
+        let allowed = end_proposing_at.saturating_duration_since(started);
+        if elapsed >= allowed.mul_f32(proposal_budget::DEADLINE_HIT_RATIO) {
+            let reason = if limited_by_imports {
+                CutShort::ImportHeadroom
+            } else {
+                CutShort::SlotPortion
+            };
+            self.report_cut_short(slot, reason);
+        }
+
+        Some(proposal)
+    }
+
     fn force_authoring(&self) -> bool {
         self.force_authoring
     }
@@ -415,7 +705,7 @@ where
                 return strategy.should_backoff(
                     *chain_head.number(),
                     chain_head_slot,
-                    self.client.info().finalized_number,
+                    backoff_base::<AuthorityId<P>, B, C>(&*self.client, chain_head, slot),
                     slot,
                     self.logging_target(),
                 );
@@ -443,13 +733,18 @@ where
         self.telemetry.clone()
     }
 
-    fn proposing_remaining_duration(&self, slot_info: &SlotInfo<B>) -> std::time::Duration {
+    fn proposing_remaining_duration(&self, slot_info: &SlotInfo<B>) -> Duration {
         let parent_slot = find_pre_digest::<B, P::Signature>(&slot_info.chain_head).ok();
+        let adapted_slot_portion = self.proposal_budget.as_ref().map(|budget| {
+            budget.slot_portion(&self.block_proposal_slot_portion, slot_info.duration)
+        });
 
         sc_consensus_slots::proposing_remaining_duration(
             parent_slot,
             slot_info,
-            &self.block_proposal_slot_portion,
+            adapted_slot_portion
+                .as_ref()
+                .unwrap_or(&self.block_proposal_slot_portion),
             self.max_block_proposal_slot_portion.as_ref(),
             sc_consensus_slots::SlotLenienceType::Exponential,
             self.logging_target(),
@@ -478,6 +773,29 @@ pub enum Error<B: BlockT> {
     /// Bad signature
     #[error("Bad signature on {0:?}")]
     BadSignature(B::Hash),
+    /// Pre-digest claiming another session or author than expected
+    #[error("Pre-digest of {hash:?} claims session and author {claimed:?}, expected {expected:?}")]
+    PreDigestMismatch {
+        /// Hash of the header.
+        hash: B::Hash,
+        /// Session and author index of the slot author.
+        expected: (SessionIndex, AuthorityIndex),
+        /// Session and author index claimed by the pre-digest.
+        claimed: (SessionIndex, AuthorityIndex),
+    },
+    /// Pre-digest without session and author index, which is no longer accepted
+    #[error("Pre-digest of {0:?} carries only the slot")]
+    SlotOnlyPreDigest(B::Hash),
+    /// Slot not after the slot of the parent
+    #[error("Slot {slot:?} of {hash:?} is not after the parent slot {parent_slot:?}")]
+    SlotNotIncreasing {
+        /// Hash of the header.
+        hash: B::Hash,
+        /// Slot of the header.
+        slot: Slot,
+        /// Slot of the parent.
+        parent_slot: Slot,
+    },
     /// Client Error
     #[error(transparent)]
     Client(sp_blockchain::Error),
@@ -504,45 +822,42 @@ impl<B: BlockT> From<crate::standalone::PreDigestLookupError> for Error<B> {
     }
 }
 
-fn aux_data<A, B, C>(
-    client: &C,
-    parent_hash: B::Hash,
-    context_block_number: NumberFor<B>,
-    compatibility_mode: &CompatibilityMode<NumberFor<B>>,
-) -> Result<AuraAuxData<A>, ConsensusError>
+/// Number of the last block the author of a child of `chain_head` at `slot` isn't held
+/// accountable for when backing off.
+///
+/// That is the finalized block, or the last block before the session of the child if more recent,
+/// taken from the tracked session state. A session author then only slows down if its own blocks
+/// are not being finalized, instead of paying for the finality lag of earlier sessions.
+fn backoff_base<A, B, C>(client: &C, chain_head: &B::Header, slot: Slot) -> NumberFor<B>
 where
-    A: Codec + Debug,
+    A: Codec,
     B: BlockT,
-    C: ProvideRuntimeApi<B>,
-    C::Api: AuraApi<B, A>,
+    C: AuxStore + HeaderBackend<B>,
 {
-    let runtime_api = client.runtime_api();
-
-    match compatibility_mode {
-        CompatibilityMode::None => {}
-        // Use `initialize_block` until we hit the block that should disable the mode.
-        CompatibilityMode::UseInitializeBlock { until } => {
-            if *until > context_block_number {
-                runtime_api
-                    .initialize_block(
-                        parent_hash,
-                        &B::Header::new(
-                            context_block_number,
-                            Default::default(),
-                            Default::default(),
-                            parent_hash,
-                            Default::default(),
-                        ),
-                    )
-                    .map_err(|_| ConsensusError::InvalidAuthoritiesSet)?;
-            }
+    let finalized_number = client.info().finalized_number;
+    let state = match aux_schema::load_session_state::<A, _, _>(client, chain_head.hash()) {
+        Ok(Some((state, _))) => state,
+        Ok(None) => return finalized_number,
+        Err(e) => {
+            log::debug!(
+                target: LOG_TARGET,
+                "Unable to load session state at {:?}: {}",
+                chain_head.hash(),
+                e,
+            );
+            return finalized_number;
         }
-    }
+    };
+
+    // a child starting a session with the slot has no blocks of its session to account for.
+    let session_base =
+        if state.session_mode.session_index(slot, state.session_index) != state.session_index {
+            *chain_head.number()
+        } else {
+            NumberFor::<B>::saturated_from(state.session_start).saturating_sub(One::one())
+        };
 
-    runtime_api
-        .aux_data(parent_hash)
-        .ok()
-        .ok_or(ConsensusError::InvalidAuthoritiesSet)
+    finalized_number.max(session_base)
 }
 
 #[cfg(test)]
@@ -552,9 +867,10 @@ mod tests {
     use sc_block_builder::BlockBuilderBuilder;
     use sc_client_api::BlockchainEvents;
     use sc_consensus::BoxJustificationImport;
-    use sc_consensus_slots::{BackoffAuthoringOnFinalizedHeadLagging, SimpleSlotWorker};
+    use sc_consensus_slots::SimpleSlotWorker;
     use sc_keystore::LocalKeystore;
     use sc_network_test::{Block as TestBlock, *};
+    use sc_transaction_pool_api::{OffchainTransactionPoolFactory, RejectAllTxPool};
     use sp_application_crypto::{key_types::AURA, AppCrypto};
     use sp_consensus::{DisableProofRecording, NoNetwork as DummyOracle, Proposal};
     use sp_inherents::InherentData;
@@ -565,7 +881,7 @@ mod tests {
         Digest,
     };
     use sp_timestamp::Timestamp;
-    use spin_primitives::sr25519::AuthorityPair;
+    use spin_primitives::sr25519::{AuthorityPair, AuthoritySignature};
     use std::{
         task::Poll,
         time::{Duration, Instant},
@@ -632,7 +948,8 @@ mod tests {
                 InherentDataProviders = (InherentDataProvider,),
             >,
         >,
-        u64,
+        LongestChainForkChoice,
+        TestBlock,
     >;
     type AuraPeer = Peer<(), PeersClient>;
 
@@ -663,6 +980,10 @@ mod tests {
                 CheckForEquivocation::Yes,
                 None,
                 CompatibilityMode::None,
+                OffchainTransactionPoolFactory::new(RejectAllTxPool::default()),
+                DriftTolerance::default(),
+                None,
+                LongestChainForkChoice,
             )
         }
 
@@ -738,7 +1059,7 @@ mod tests {
             let slot_duration = slot_duration(&*client).expect("slot duration available");
 
             aura_futures.push(
-                start_aura::<AuthorityPair, _, _, _, _, _, _, _, _, _, _>(StartAuraParams {
+                start_aura::<AuthorityPair, _, _, _, _, _, _, _, _, _, _, _>(StartAuraParams {
                     slot_duration,
                     block_import: client.clone(),
                     select_chain,
@@ -761,8 +1082,11 @@ mod tests {
                     keystore,
                     block_proposal_slot_portion: SlotProportion::new(0.5),
                     max_block_proposal_slot_portion: None,
+                    proposal_budget: None,
                     telemetry: None,
                     compatibility_mode: CompatibilityMode::None,
+                    prometheus_registry: None,
+                    fork_choice: LongestChainForkChoice,
                 })
                 .expect("Starts aura"),
             );
@@ -785,7 +1109,7 @@ mod tests {
     async fn current_node_authority_should_claim_slot() {
         let net = AuraTestNet::new(4);
 
-        let mut authorities = vec![
+        let mut authorities: Vec<AuthorityId<AuthorityPair>> = vec![
             Keyring::Alice.public().into(),
             Keyring::Bob.public().into(),
             Keyring::Charlie.public().into(),
@@ -817,7 +1141,10 @@ mod tests {
             telemetry: None,
             block_proposal_slot_portion: SlotProportion::new(0.5),
             max_block_proposal_slot_portion: None,
+            proposal_budget: None,
             compatibility_mode: Default::default(),
+            metrics: None,
+            fork_choice: LongestChainForkChoice,
             _phantom: PhantomData::<fn() -> AuthorityPair>,
         };
 
@@ -828,38 +1155,26 @@ mod tests {
             Default::default(),
             Default::default(),
         );
-        assert!(worker
-            .claim_slot(&head, 0.into(), &authorities)
-            .await
-            .is_none());
-        assert!(worker
-            .claim_slot(&head, 1.into(), &authorities)
-            .await
-            .is_none());
-        assert!(worker
-            .claim_slot(&head, 2.into(), &authorities)
-            .await
-            .is_none());
-        assert!(worker
-            .claim_slot(&head, 3.into(), &authorities)
-            .await
-            .is_some());
-        assert!(worker
-            .claim_slot(&head, 4.into(), &authorities)
-            .await
-            .is_none());
-        assert!(worker
-            .claim_slot(&head, 5.into(), &authorities)
-            .await
-            .is_none());
-        assert!(worker
-            .claim_slot(&head, 6.into(), &authorities)
-            .await
-            .is_none());
-        assert!(worker
-            .claim_slot(&head, 7.into(), &authorities)
-            .await
-            .is_some());
+        let state = (
+            SessionState {
+                authorities,
+                session_index: 0,
+                session_start: 0,
+                disabled: Vec::new(),
+                fallback_timeout: None,
+                session_mode: SessionMode::Blocks,
+                fallback_offset: (0, 0),
+            },
+            None,
+        );
+        assert!(worker.claim_slot(&head, 0.into(), &state).await.is_none());
+        assert!(worker.claim_slot(&head, 1.into(), &state).await.is_none());
+        assert!(worker.claim_slot(&head, 2.into(), &state).await.is_none());
+        assert!(worker.claim_slot(&head, 3.into(), &state).await.is_some());
+        assert!(worker.claim_slot(&head, 4.into(), &state).await.is_none());
+        assert!(worker.claim_slot(&head, 5.into(), &state).await.is_none());
+        assert!(worker.claim_slot(&head, 6.into(), &state).await.is_none());
+        assert!(worker.claim_slot(&head, 7.into(), &state).await.is_some());
     }
 
     #[tokio::test]
@@ -891,7 +1206,10 @@ mod tests {
             telemetry: None,
             block_proposal_slot_portion: SlotProportion::new(0.5),
             max_block_proposal_slot_portion: None,
+            proposal_budget: None,
             compatibility_mode: Default::default(),
+            metrics: None,
+            fork_choice: LongestChainForkChoice,
             _phantom: PhantomData::<fn() -> AuthorityPair>,
         };
 
@@ -912,4 +1230,159 @@ mod tests {
         // The returned block should be imported and we should be able to get its header by now.
         assert!(client.header(res.block.hash()).unwrap().is_some());
     }
+
+    #[test]
+    fn backoff_counts_unfinalized_blocks_of_the_session() {
+        let client = Arc::new(substrate_test_runtime_client::new());
+        let keystore_path = tempfile::tempdir().expect("Creates keystore path");
+        let keystore = LocalKeystore::open(keystore_path.path(), None).expect("Creates keystore.");
+
+        let worker = AuraWorker {
+            client: client.clone(),
+            block_import: client.clone(),
+            env: DummyFactory(client.clone()),
+            keystore: keystore.into(),
+            sync_oracle: DummyOracle,
+            justification_sync_link: (),
+            force_authoring: false,
+            backoff_authoring_blocks: Some(BackoffAuthoringOnFinalizedHeadLagging {
+                max_interval: 10,
+                unfinalized_slack: 5,
+                authoring_bias: 1,
+            }),
+            telemetry: None,
+            block_proposal_slot_portion: SlotProportion::new(0.5),
+            max_block_proposal_slot_portion: None,
+            proposal_budget: None,
+            compatibility_mode: Default::default(),
+            metrics: None,
+            fork_choice: LongestChainForkChoice,
+            _phantom: PhantomData::<fn() -> AuthorityPair>,
+        };
+        let head = |number, seed| {
+            Header::new(
+                number,
+                Default::default(),
+                H256::from_low_u64_be(seed),
+                Default::default(),
+                Digest {
+                    logs: vec![CompatibleDigestItem::<AuthoritySignature>::aura_pre_digest(
+                        100.into(),
+                    )],
+                },
+            )
+        };
+        let record = |head: &Header, session_start, session_mode| {
+            let state = SessionState::<AuthorityId<AuthorityPair>> {
+                authorities: Vec::new(),
+                session_index: 1,
+                session_start,
+                disabled: Vec::new(),
+                fallback_timeout: None,
+                session_mode,
+                fallback_offset: (1, 0),
+            };
+            let (_, (key, value)) =
+                aux_schema::session_state_entry(head.hash(), head, state, None, (1, 0));
+            client.insert_aux(&[(&key[..], &value[..])], &[]).unwrap();
+        };
+
+        // only the genesis block is finalized.
+        assert!(!worker.should_backoff(101.into(), &head(5, 0)));
+        // without a tracked session, 10 unfinalized blocks, 5 past the slack, skip five slots.
+        assert!(worker.should_backoff(101.into(), &head(10, 0)));
+        assert!(worker.should_backoff(105.into(), &head(10, 0)));
+        assert!(!worker.should_backoff(106.into(), &head(10, 0)));
+
+        // the author of a session started at block 9 isn't held back by the earlier blocks.
+        let started_recently = head(10, 1);
+        record(&started_recently, 9, SessionMode::Blocks);
+        assert!(!worker.should_backoff(101.into(), &started_recently));
+
+        // while blocks of the current session count, from the one before it started.
+        let started_early = head(10, 2);
+        record(&started_early, 2, SessionMode::Blocks);
+        assert!(worker.should_backoff(101.into(), &started_early));
+        assert!(worker.should_backoff(104.into(), &started_early));
+        assert!(!worker.should_backoff(105.into(), &started_early));
+
+        // the author of a session starting with the slot has none of its blocks unfinalized.
+        let slot_based = head(10, 3);
+        record(&slot_based, 2, SessionMode::Slots(51));
+        assert!(worker.should_backoff(101.into(), &slot_based));
+        assert!(!worker.should_backoff(102.into(), &slot_based));
+    }
+
+    #[test]
+    fn proposals_leave_time_to_import_the_heaviest_blocks() {
+        let client = Arc::new(substrate_test_runtime_client::new());
+        let keystore_path = tempfile::tempdir().expect("Creates keystore path");
+        let keystore = LocalKeystore::open(keystore_path.path(), None).expect("Creates keystore.");
+        let budget = ProposalBudget::new(SlotProportion::new(0.1), SlotProportion::new(0.9));
+
+        let worker = AuraWorker {
+            client: client.clone(),
+            block_import: client.clone(),
+            env: DummyFactory(client.clone()),
+            keystore: keystore.into(),
+            sync_oracle: DummyOracle,
+            justification_sync_link: (),
+            force_authoring: false,
+            backoff_authoring_blocks: Option::<()>::None,
+            telemetry: None,
+            block_proposal_slot_portion: SlotProportion::new(0.5),
+            max_block_proposal_slot_portion: None,
+            proposal_budget: Some(budget.clone()),
+            compatibility_mode: Default::default(),
+            metrics: None,
+            fork_choice: LongestChainForkChoice,
+            _phantom: PhantomData::<fn() -> AuthorityPair>,
+        };
+        let slot_info = || SlotInfo {
+            slot: 1.into(),
+            ends_at: Instant::now() + Duration::from_secs(100),
+            create_inherent_data: Box::new(()),
+            duration: Duration::from_millis(SLOT_DURATION_MS),
+            chain_head: client.expect_header(client.info().genesis_hash).unwrap(),
+            block_size_limit: None,
+        };
+        let remaining = |expected: u64| {
+            let remaining = worker.proposing_remaining_duration(&slot_info());
+            let expected = Duration::from_millis(expected);
+            assert!(
+                remaining.abs_diff(expected) <= Duration::from_millis(1),
+                "{remaining:?} != {expected:?}",
+            );
+        };
+
+        // the initial portion is used until an import is recorded.
+        remaining(500);
+        budget.note_import(Duration::from_millis(600));
+        remaining(400);
+        // a cheaper import doesn't raise the budget while the heaviest one is remembered.
+        budget.note_import(Duration::from_millis(50));
+        remaining(400);
+        budget.note_import(Duration::from_millis(950));
+        remaining(100);
+    }
+
+    #[test]
+    fn drift_tolerance_rounds_up_to_slots() {
+        let slot_duration = SlotDuration::from_millis(2000);
+
+        assert_eq!(DriftTolerance::default().slots(slot_duration), 1);
+        assert_eq!(DriftTolerance::Slots(3).slots(slot_duration), 3);
+        assert_eq!(
+            DriftTolerance::Duration(Duration::from_millis(0)).slots(slot_duration),
+            0
+        );
+        assert_eq!(
+            DriftTolerance::Duration(Duration::from_millis(2000)).slots(slot_duration),
+            1
+        );
+        assert_eq!(
+            DriftTolerance::Duration(Duration::from_millis(2500)).slots(slot_duration),
+            2
+        );
+    }
 }
@@ -0,0 +1,251 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Spin digests for blocks sealed on demand with `sc_consensus_manual_seal`.
+//!
+//! Sealed blocks carry the same pre-digest and seal as blocks of the slot worker, so the runtime
+//! and the verifier of other nodes accept them.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use codec::Codec;
+use sc_client_api::backend::AuxStore;
+use sc_consensus::BlockImportParams;
+use sc_consensus_manual_seal::{ConsensusDataProvider, Error};
+use sp_api::ProvideRuntimeApi;
+use sp_application_crypto::AppPublic;
+use sp_consensus_slots::Slot;
+use sp_core::crypto::{ByteArray, Pair};
+use sp_inherents::InherentData;
+use sp_keystore::KeystorePtr;
+use sp_runtime::{traits::Block as BlockT, Digest};
+use sp_timestamp::Timestamp;
+
+use crate::{
+    aux_schema::{self, SessionState},
+    standalone::{
+        fallback_at, fetch_session_state, find_pre_digest, pre_digest, seal, slot_author_index,
+    },
+    AuraApi, AuthorityId, AuthorityIndex, CompatibilityMode, Fallback, ForkChoice,
+    InherentDataProvider, PreDigest, SessionIndex, SlotDuration, INHERENT_IDENTIFIER,
+};
+
+/// Slot and timestamp inherent data providers for a block sealed on top of `parent`.
+///
+/// Blocks may be sealed faster than slots pass, in which case the slot is moved just past the
+/// parent's and the timestamp follows it, running ahead of the local clock. This is meant for
+/// development chains, whose blocks no peer checks against its own clock.
+pub fn inherent_data_providers<B: BlockT, S: Codec>(
+    parent: &B::Header,
+    slot_duration: SlotDuration,
+) -> Result<(InherentDataProvider, sp_timestamp::InherentDataProvider), Error> {
+    let parent_slot =
+        find_pre_digest::<B, S>(parent).map_err(|e| Error::StringError(e.to_string()))?;
+    let slot = Slot::from_timestamp(Timestamp::current(), slot_duration).max(parent_slot + 1);
+    let timestamp = slot
+        .timestamp(slot_duration)
+        .ok_or_else(|| Error::StringError(format!("Timestamp of slot {slot:?} overflows")))?;
+
+    Ok((
+        InherentDataProvider::new(slot),
+        sp_timestamp::InherentDataProvider::new(timestamp),
+    ))
+}
+
+/// Provides the spin pre-digest and seal of blocks sealed on demand.
+///
+/// The seal is made with the key of the slot author, which must be in the keystore.
+pub struct SpinConsensusDataProvider<B, C, P, FC, Proof> {
+    client: Arc<C>,
+    keystore: KeystorePtr,
+    fork_choice: FC,
+    _phantom: PhantomData<fn() -> (B, P, Proof)>,
+}
+
+impl<B, C, P, FC, Proof> SpinConsensusDataProvider<B, C, P, FC, Proof> {
+    /// Create a new data provider signing with the keys in `keystore`.
+    ///
+    /// `fork_choice` should match the one of the import queue.
+    pub fn new(client: Arc<C>, keystore: KeystorePtr, fork_choice: FC) -> Self {
+        Self {
+            client,
+            keystore,
+            fork_choice,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<B, C, P, FC, Proof> ConsensusDataProvider<B> for SpinConsensusDataProvider<B, C, P, FC, Proof>
+where
+    B: BlockT,
+    C: ProvideRuntimeApi<B> + AuxStore + Send + Sync,
+    C::Api: AuraApi<B, AuthorityId<P>>,
+    P: Pair,
+    P::Public: AppPublic,
+    P::Signature: TryFrom<Vec<u8>> + Codec,
+    FC: ForkChoice<B>,
+    Proof: Send + Sync,
+{
+    type Proof = Proof;
+
+    fn create_digest(&self, parent: &B::Header, inherents: &InherentData) -> Result<Digest, Error> {
+        let slot = slot_of(inherents)?;
+        let (state, _) = self.session_state(parent)?;
+        let fallback = fallback_at::<B, P::Signature, _>(parent, &state, slot)?;
+        let (_, authority_index, session_index) = self.slot_author(slot, &state, fallback)?;
+
+        Ok(Digest {
+            logs: vec![pre_digest::<P>(PreDigest::V1 {
+                slot,
+                session_index,
+                authority_index,
+            })],
+        })
+    }
+
+    fn append_block_import(
+        &self,
+        parent: &B::Header,
+        params: &mut BlockImportParams<B>,
+        inherents: &InherentData,
+        _proof: Self::Proof,
+    ) -> Result<(), Error> {
+        let slot = slot_of(inherents)?;
+        let (state, anchor) = self.session_state(parent)?;
+        let fallback = fallback_at::<B, P::Signature, _>(parent, &state, slot)?;
+        let (author, _, _) = self.slot_author(slot, &state, fallback)?;
+        if !self
+            .keystore
+            .has_keys(&[(author.to_raw_vec(), sp_application_crypto::key_types::AURA)])
+        {
+            return Err(Error::StringError(format!(
+                "Key of slot author {author:?} is not in the keystore"
+            )));
+        }
+
+        let seal = seal::<_, P>(&params.header.hash(), &author, &self.keystore)?;
+        params.post_digests.push(seal);
+
+        let fallback_offset = state.fallback_offset_at(slot, fallback);
+        let (_, (key, value)) = aux_schema::session_state_entry(
+            params.post_hash(),
+            &params.header,
+            state,
+            anchor,
+            fallback_offset,
+        );
+        params.auxiliary.push((key, Some(value)));
+        self.fork_choice.fork_choice(
+            params,
+            slot,
+            fallback.map_or(true, |fallback| fallback.offset(slot) == 0),
+        )?;
+
+        Ok(())
+    }
+}
+
+impl<B, C, P, FC, Proof> SpinConsensusDataProvider<B, C, P, FC, Proof>
+where
+    B: BlockT,
+    C: ProvideRuntimeApi<B> + AuxStore,
+    C::Api: AuraApi<B, AuthorityId<P>>,
+    P: Pair,
+    P::Public: Codec + std::fmt::Debug,
+    P::Signature: Codec,
+{
+    /// The session state blocks on top of `parent` are authored in, with the block holding it.
+    fn session_state(
+        &self,
+        parent: &B::Header,
+    ) -> Result<(SessionState<AuthorityId<P>>, Option<B::Hash>), Error> {
+        Ok(fetch_session_state::<AuthorityId<P>, B, C>(
+            self.client.as_ref(),
+            parent,
+            &CompatibilityMode::None,
+        )?)
+    }
+
+    /// The author of a block at `slot` in the given session state, with its index and session.
+    fn slot_author(
+        &self,
+        slot: Slot,
+        state: &SessionState<AuthorityId<P>>,
+        fallback: Option<Fallback>,
+    ) -> Result<(AuthorityId<P>, AuthorityIndex, SessionIndex), Error> {
+        let authority_index = slot_author_index(
+            slot,
+            state.session_index,
+            state.session_mode,
+            state.authorities.len(),
+            &state.disabled,
+            fallback,
+        )
+        .ok_or_else(|| Error::StringError(format!("No author for slot {slot:?}")))?;
+        let author = state.authorities[authority_index as usize].clone();
+
+        Ok((
+            author,
+            authority_index,
+            state.session_mode.session_index(slot, state.session_index),
+        ))
+    }
+}
+
+fn slot_of(inherents: &InherentData) -> Result<Slot, Error> {
+    inherents
+        .get_data::<Slot>(&INHERENT_IDENTIFIER)?
+        .ok_or_else(|| Error::StringError("No slot in the inherent data".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp_runtime::traits::Header as _;
+    use spin_primitives::sr25519::{AuthorityPair, AuthoritySignature};
+    use substrate_test_runtime_client::runtime::{Block as TestBlock, Header};
+
+    #[test]
+    fn blocks_are_sealed_back_to_back() {
+        let slot_duration = SlotDuration::from_millis(6000);
+        let mut parent = Header::new_from_number(1);
+        parent
+            .digest
+            .push(pre_digest::<AuthorityPair>(PreDigest::V0(
+                Slot::from_timestamp(Timestamp::current(), slot_duration),
+            )));
+
+        // every block takes the slot after its parent's without waiting for it to pass.
+        let started = std::time::Instant::now();
+        for number in 2..=5 {
+            let parent_slot = find_pre_digest::<TestBlock, AuthoritySignature>(&parent).unwrap();
+            let (slot, timestamp) =
+                inherent_data_providers::<TestBlock, AuthoritySignature>(&parent, slot_duration)
+                    .unwrap();
+            assert_eq!(*slot, parent_slot + 1);
+            assert_eq!(Some(timestamp.timestamp()), slot.timestamp(slot_duration));
+
+            parent = Header::new_from_number(number);
+            parent
+                .digest
+                .push(pre_digest::<AuthorityPair>(PreDigest::V0(*slot)));
+        }
+        assert!(started.elapsed() < slot_duration.as_duration());
+    }
+}
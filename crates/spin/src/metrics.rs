@@ -0,0 +1,187 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Prometheus metrics of the authoring worker and the verifier.
+
+use prometheus_endpoint::{
+    register, Counter, CounterVec, Gauge, Opts, PrometheusError, Registry, U64,
+};
+use sp_runtime::traits::Block as BlockT;
+
+use crate::{proposal_budget::CutShort, AuthorityIndex, Error, SessionIndex, LOG_TARGET};
+
+/// Metrics of the authoring worker.
+#[derive(Clone)]
+pub(crate) struct WorkerMetrics {
+    slots_claimed: Counter<U64>,
+    slots_skipped: Counter<U64>,
+    seal_failures: Counter<U64>,
+    proposals_cut_short: CounterVec<U64>,
+    session_index: Gauge<U64>,
+    session_author: Gauge<U64>,
+}
+
+impl WorkerMetrics {
+    pub(crate) fn register(registry: &Registry) -> Result<Self, PrometheusError> {
+        Ok(Self {
+            slots_claimed: register(
+                Counter::new(
+                    "substrate_spin_slots_claimed_total",
+                    "Number of slots claimed by a local authority",
+                )?,
+                registry,
+            )?,
+            slots_skipped: register(
+                Counter::new(
+                    "substrate_spin_slots_skipped_total",
+                    "Number of slots left to the authority owning the session",
+                )?,
+                registry,
+            )?,
+            seal_failures: register(
+                Counter::new(
+                    "substrate_spin_seal_failures_total",
+                    "Number of authored blocks which could not be sealed",
+                )?,
+                registry,
+            )?,
+            proposals_cut_short: register(
+                CounterVec::new(
+                    Opts::new(
+                        "substrate_spin_proposals_cut_short_total",
+                        "Number of proposals cut short by their deadline, by reason",
+                    ),
+                    &["reason"],
+                )?,
+                registry,
+            )?,
+            session_index: register(
+                Gauge::new(
+                    "substrate_spin_session_index",
+                    "Index of the session at the best block",
+                )?,
+                registry,
+            )?,
+            session_author: register(
+                Gauge::new(
+                    "substrate_spin_session_author",
+                    "Index of the authority owning the session at the best block",
+                )?,
+                registry,
+            )?,
+        })
+    }
+
+    /// Register the metrics, only logging on failure.
+    pub(crate) fn register_or_warn(registry: Option<&Registry>) -> Option<Self> {
+        registry.and_then(|registry| match Self::register(registry) {
+            Ok(metrics) => Some(metrics),
+            Err(e) => {
+                log::warn!(target: LOG_TARGET, "Failed to register worker metrics: {}", e);
+                None
+            }
+        })
+    }
+
+    pub(crate) fn report_session(
+        &self,
+        session_index: SessionIndex,
+        session_author: Option<AuthorityIndex>,
+    ) {
+        self.session_index.set(session_index.into());
+        if let Some(author) = session_author {
+            self.session_author.set(author.into());
+        }
+    }
+
+    pub(crate) fn report_claim(&self, claimed: bool) {
+        if claimed {
+            self.slots_claimed.inc();
+        } else {
+            self.slots_skipped.inc();
+        }
+    }
+
+    pub(crate) fn report_seal_failure(&self) {
+        self.seal_failures.inc();
+    }
+
+    pub(crate) fn report_cut_short(&self, reason: CutShort) {
+        self.proposals_cut_short
+            .with_label_values(&[reason.label()])
+            .inc();
+    }
+}
+
+/// Metrics of the verifier.
+#[derive(Clone)]
+pub(crate) struct VerifierMetrics {
+    verification_failures: CounterVec<U64>,
+}
+
+impl VerifierMetrics {
+    pub(crate) fn register(registry: &Registry) -> Result<Self, PrometheusError> {
+        Ok(Self {
+            verification_failures: register(
+                CounterVec::new(
+                    Opts::new(
+                        "substrate_spin_verification_failures_total",
+                        "Number of imported blocks failing verification, by error",
+                    ),
+                    &["error"],
+                )?,
+                registry,
+            )?,
+        })
+    }
+
+    /// Register the metrics, only logging on failure.
+    pub(crate) fn register_or_warn(registry: Option<&Registry>) -> Option<Self> {
+        registry.and_then(|registry| match Self::register(registry) {
+            Ok(metrics) => Some(metrics),
+            Err(e) => {
+                log::warn!(target: LOG_TARGET, "Failed to register verifier metrics: {}", e);
+                None
+            }
+        })
+    }
+
+    pub(crate) fn report_failure<B: BlockT>(&self, error: &Error<B>) {
+        self.verification_failures
+            .with_label_values(&[error_label(error)])
+            .inc();
+    }
+}
+
+/// Label of the given error, one per variant.
+fn error_label<B: BlockT>(error: &Error<B>) -> &'static str {
+    match error {
+        Error::MultipleHeaders => "multiple_headers",
+        Error::NoDigestFound => "no_digest_found",
+        Error::HeaderUnsealed(_) => "header_unsealed",
+        Error::HeaderBadSeal(_) => "header_bad_seal",
+        Error::SlotAuthorNotFound => "slot_author_not_found",
+        Error::BadSignature(_) => "bad_signature",
+        Error::SlotNotIncreasing { .. } => "slot_not_increasing",
+        Error::PreDigestMismatch { .. } => "pre_digest_mismatch",
+        Error::SlotOnlyPreDigest(_) => "slot_only_pre_digest",
+        Error::Client(_) => "client",
+        Error::UnknownInherentError(_) => "unknown_inherent_error",
+        Error::Inherent(_) => "inherent",
+    }
+}
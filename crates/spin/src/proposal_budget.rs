@@ -0,0 +1,250 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Proposal time budget adapting to recent import durations.
+//!
+//! A block proposed in a portion `p` of the slot has to be imported by the next author within
+//! the rest of it. Proposals are given the part of the slot left once the longest recent import
+//! is set aside, within configured bounds. Durations are measured by the local node only.
+
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use sc_consensus::{BlockCheckParams, BlockImport, BlockImportParams, ImportResult, StateAction};
+use sp_consensus::BlockOrigin;
+use sp_runtime::traits::Block as BlockT;
+
+use crate::SlotProportion;
+
+/// Number of recent imports the budget is derived from.
+pub const BUDGET_HISTORY: usize = 32;
+
+/// Share of its time a proposal must have used to count as having hit its deadline.
+pub(crate) const DEADLINE_HIT_RATIO: f32 = 0.9;
+
+/// Why a proposal was cut short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CutShort {
+    /// Creating the inherent data took all the time left.
+    InherentData,
+    /// The proposer didn't return in time and its block was discarded.
+    Discarded,
+    /// The proposer hit a deadline lowered to leave time for importing the block.
+    ImportHeadroom,
+    /// The proposer hit the deadline of the largest portion of the slot it may use.
+    SlotPortion,
+}
+
+impl CutShort {
+    /// Label of the reason in metrics.
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Self::InherentData => "inherent_data",
+            Self::Discarded => "discarded",
+            Self::ImportHeadroom => "import_headroom",
+            Self::SlotPortion => "slot_portion",
+        }
+    }
+}
+
+/// Durations of the recent imports.
+#[derive(Default)]
+struct Imports(VecDeque<Duration>);
+
+impl Imports {
+    fn push(&mut self, duration: Duration) {
+        if self.0.len() == BUDGET_HISTORY {
+            self.0.pop_front();
+        }
+        self.0.push_back(duration);
+    }
+
+    /// Portion of a slot of the given duration left after importing the heaviest recent block,
+    /// if known.
+    fn portion(&self, slot_duration: Duration) -> Option<f32> {
+        let import = self.0.iter().max()?.as_secs_f32();
+        let slot_duration = slot_duration.as_secs_f32();
+        if slot_duration == 0.0 {
+            return None;
+        }
+
+        Some(1.0 - (import / slot_duration).min(1.0))
+    }
+}
+
+/// Portion of the slot given to proposals, learned from recent import durations.
+///
+/// Clones share the recorded durations, so the worker and the [`TimedBlockImport`] of the import
+/// queue should be built from the same budget.
+#[derive(Clone)]
+pub struct ProposalBudget {
+    min: f32,
+    max: f32,
+    imports: Arc<Mutex<Imports>>,
+}
+
+impl ProposalBudget {
+    /// Create a budget adapting between the `min` and `max` portions of the slot.
+    ///
+    /// A `max` below `min` is raised to it.
+    pub fn new(min: SlotProportion, max: SlotProportion) -> Self {
+        Self {
+            min: min.get(),
+            max: max.get().max(min.get()),
+            imports: Default::default(),
+        }
+    }
+
+    /// Wrap the block import of the import queue to record how long importing blocks takes.
+    pub fn block_import<I>(&self, inner: I) -> TimedBlockImport<I> {
+        TimedBlockImport {
+            inner,
+            budget: self.clone(),
+        }
+    }
+
+    /// Portion of a slot of the given duration to give the next proposal.
+    ///
+    /// Falls back to `initial` until an import has been recorded.
+    pub fn slot_portion(
+        &self,
+        initial: &SlotProportion,
+        slot_duration: Duration,
+    ) -> SlotProportion {
+        let portion = self
+            .imports
+            .lock()
+            .portion(slot_duration)
+            .unwrap_or(initial.get());
+        SlotProportion::new(portion.clamp(self.min, self.max))
+    }
+
+    /// Whether the budget is kept below its upper bound to leave time for imports.
+    pub(crate) fn limited_by_imports(&self, slot_duration: Duration) -> bool {
+        self.imports
+            .lock()
+            .portion(slot_duration)
+            .is_some_and(|portion| portion < self.max)
+    }
+
+    pub(crate) fn note_import(&self, duration: Duration) {
+        self.imports.lock().push(duration);
+    }
+}
+
+/// Block import recording in a [`ProposalBudget`] how long executing imported blocks takes.
+///
+/// Blocks authored locally are not recorded, as their state changes are applied without
+/// executing them again.
+#[derive(Clone)]
+pub struct TimedBlockImport<I> {
+    inner: I,
+    budget: ProposalBudget,
+}
+
+#[async_trait::async_trait]
+impl<B, I> BlockImport<B> for TimedBlockImport<I>
+where
+    B: BlockT,
+    I: BlockImport<B> + Send + Sync,
+{
+    type Error = I::Error;
+
+    async fn check_block(&self, block: BlockCheckParams<B>) -> Result<ImportResult, Self::Error> {
+        self.inner.check_block(block).await
+    }
+
+    async fn import_block(&self, block: BlockImportParams<B>) -> Result<ImportResult, Self::Error> {
+        let executed = block.origin != BlockOrigin::Own
+            && matches!(
+                block.state_action,
+                StateAction::Execute | StateAction::ExecuteIfPossible
+            );
+
+        let started = std::time::Instant::now();
+        let result = self.inner.import_block(block).await;
+        if executed && matches!(result, Ok(ImportResult::Imported(_))) {
+            self.budget.note_import(started.elapsed());
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLOT_DURATION: Duration = Duration::from_millis(6000);
+
+    fn budget(min: f32, max: f32) -> ProposalBudget {
+        ProposalBudget::new(SlotProportion::new(min), SlotProportion::new(max))
+    }
+
+    fn portion(budget: &ProposalBudget, initial: f32) -> f32 {
+        budget
+            .slot_portion(&SlotProportion::new(initial), SLOT_DURATION)
+            .get()
+    }
+
+    #[test]
+    fn starts_from_the_initial_portion_within_bounds() {
+        let budget = budget(0.3, 0.8);
+        assert_eq!(portion(&budget, 0.5), 0.5);
+        assert_eq!(portion(&budget, 0.9), 0.8);
+        assert!(!budget.limited_by_imports(SLOT_DURATION));
+    }
+
+    #[test]
+    fn leaves_time_to_import_the_heaviest_blocks() {
+        let budget = budget(0.2, 0.9);
+        budget.note_import(Duration::from_millis(300));
+        assert_eq!(portion(&budget, 0.5), 0.9);
+        assert!(!budget.limited_by_imports(SLOT_DURATION));
+
+        // cheap blocks don't shrink the budget, only the time taken by the heaviest one counts.
+        budget.note_import(Duration::from_millis(2000));
+        budget.note_import(Duration::from_millis(10));
+        assert!((portion(&budget, 0.5) - 2.0 / 3.0).abs() < 1e-6);
+        assert!(budget.limited_by_imports(SLOT_DURATION));
+
+        // slower imports shrink the budget down to its lower bound.
+        budget.note_import(Duration::from_millis(6000));
+        assert_eq!(portion(&budget, 0.5), 0.2);
+        budget.note_import(Duration::from_millis(9000));
+        assert_eq!(portion(&budget, 0.5), 0.2);
+    }
+
+    #[test]
+    fn forgets_old_durations() {
+        let budget = budget(0.1, 0.9);
+        budget.note_import(Duration::from_millis(5400));
+        assert!((portion(&budget, 0.5) - 0.1).abs() < 1e-6);
+
+        for _ in 0..BUDGET_HISTORY {
+            budget.note_import(Duration::from_millis(3000));
+        }
+        assert_eq!(portion(&budget, 0.9), 0.5);
+    }
+
+    #[test]
+    fn max_is_raised_to_min() {
+        let budget = budget(0.6, 0.4);
+        assert_eq!(portion(&budget, 0.5), 0.6);
+    }
+}
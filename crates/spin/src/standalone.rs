@@ -24,8 +24,8 @@ use log::trace;
 
 use codec::Codec;
 
-use sc_client_api::UsageProvider;
-use sp_api::{Core, ProvideRuntimeApi};
+use sc_client_api::{backend::AuxStore, UsageProvider};
+use sp_api::{ApiExt, Core, ProvideRuntimeApi};
 use sp_application_crypto::{AppCrypto, AppPublic};
 use sp_blockchain::Result as CResult;
 use sp_consensus::Error as ConsensusError;
@@ -33,16 +33,19 @@ use sp_consensus_slots::Slot;
 use sp_core::crypto::{ByteArray, Pair};
 use sp_keystore::KeystorePtr;
 use sp_runtime::{
-    traits::{Block as BlockT, Header, NumberFor, Zero},
-    DigestItem,
+    traits::{Block as BlockT, Header, NumberFor, One, Zero},
+    DigestItem, SaturatedConversion,
 };
 
 pub use sc_consensus_slots::check_equivocation;
+pub use spin_primitives::{Fallback, SessionMode};
 
 use super::{
-    AuraApi, AuraAuxData, AuthorityId, CompatibilityMode, CompatibleDigestItem, SessionIndex,
-    SlotDuration, LOG_TARGET,
+    aux_schema::{self, SessionState},
+    AuraApi, AuraAuxData, AuthorityId, AuthorityIndex, CompatibilityMode, CompatibleDigestItem,
+    PreDigest, SessionIndex, SlotDuration, LOG_TARGET,
 };
+use spin_primitives::session_author_index;
 
 /// Get the slot duration for Aura by reading from a runtime API at the best block's state.
 pub fn slot_duration<A, B, C>(client: &C) -> CResult<SlotDuration>
@@ -71,21 +74,27 @@ where
 
 /// Get the slot author for given block along with authorities.
 ///
-/// Session increment is assumed to be done by the runtime
-pub fn slot_author<P: Pair>(
-    _slot: Slot,
+/// `session_idx` is the session of the parent, and the block is authored in the session given
+/// by the [`SessionMode`] at `slot`. Session increment is otherwise assumed to be done by the
+/// runtime. If a [`Fallback`] is given, the author is moved along the ring according to the
+/// number of empty slots since the parent, on top of the offset of the parent's author.
+/// Disabled authorities are skipped in favour of the next enabled one.
+pub fn slot_author<'a, P: Pair>(
+    slot: Slot,
     session_idx: SessionIndex,
-    authorities: &[AuthorityId<P>],
-) -> Option<&AuthorityId<P>> {
-    if authorities.is_empty() {
-        return None;
-    }
-
-    let idx = session_idx as u64 % (authorities.len() as u64);
-    assert!(
-        idx <= usize::MAX as u64,
-        "It is impossible to have a vector with length beyond the address space; qed",
-    );
+    session_mode: SessionMode,
+    authorities: &'a [AuthorityId<P>],
+    disabled: &[AuthorityIndex],
+    fallback: Option<Fallback>,
+) -> Option<&'a AuthorityId<P>> {
+    let idx = slot_author_index(
+        slot,
+        session_idx,
+        session_mode,
+        authorities.len(),
+        disabled,
+        fallback,
+    )?;
 
     let current_author = authorities.get(idx as usize).expect(
         "authorities not empty; index constrained to list length;this is a valid index; qed",
@@ -94,35 +103,62 @@ pub fn slot_author<P: Pair>(
     Some(current_author)
 }
 
+/// Index of the slot author in the authority set, see [`slot_author`].
+pub fn slot_author_index(
+    slot: Slot,
+    session_idx: SessionIndex,
+    session_mode: SessionMode,
+    authorities_len: usize,
+    disabled: &[AuthorityIndex],
+    fallback: Option<Fallback>,
+) -> Option<AuthorityIndex> {
+    let offset = fallback.map_or(0, |f| f.offset(slot));
+    session_author_index(
+        session_mode.session_index(slot, session_idx),
+        offset,
+        authorities_len,
+        disabled,
+    )
+}
+
 /// Attempt to claim a slot using a keystore.
 ///
 /// This returns `None` if the slot author is not locally controlled, and `Some` if it is,
-/// with the public key of the slot author.
+/// with the public key of the slot author and its index in the authority set.
 pub async fn claim_slot<P: Pair>(
     slot: Slot,
     aux_data: &AuraAuxData<AuthorityId<P>>,
+    session_mode: SessionMode,
+    disabled: &[AuthorityIndex],
+    fallback: Option<Fallback>,
     keystore: &KeystorePtr,
-) -> Option<P::Public> {
-    let (authorities, session_length) = aux_data;
-    let expected_author = slot_author::<P>(slot, *session_length, authorities);
-    expected_author.and_then(|p| {
-        if keystore.has_keys(&[(p.to_raw_vec(), sp_application_crypto::key_types::AURA)]) {
-            Some(p.clone())
-        } else {
-            None
-        }
-    })
+) -> Option<(P::Public, AuthorityIndex)> {
+    let (authorities, session_idx) = aux_data;
+    let idx = slot_author_index(
+        slot,
+        *session_idx,
+        session_mode,
+        authorities.len(),
+        disabled,
+        fallback,
+    )?;
+    let p = authorities.get(idx as usize)?;
+    if keystore.has_keys(&[(p.to_raw_vec(), sp_application_crypto::key_types::AURA)]) {
+        Some((p.clone(), idx))
+    } else {
+        None
+    }
 }
 
 /// Produce the pre-runtime digest containing the slot info.
 ///
 /// This is intended to be put into the block header prior to runtime execution,
 /// so the runtime can read the slot in this way.
-pub fn pre_digest<P: Pair>(slot: Slot) -> sp_runtime::DigestItem
+pub fn pre_digest<P: Pair>(pre_digest: PreDigest) -> sp_runtime::DigestItem
 where
     P::Signature: Codec,
 {
-    <DigestItem as CompatibleDigestItem<P::Signature>>::aura_pre_digest(slot)
+    <DigestItem as CompatibleDigestItem<P::Signature>>::aura_versioned_pre_digest(pre_digest)
 }
 
 /// Produce the seal digest item by signing the hash of a block.
@@ -181,15 +217,24 @@ pub enum PreDigestLookupError {
 pub fn find_pre_digest<B: BlockT, Signature: Codec>(
     header: &B::Header,
 ) -> Result<Slot, PreDigestLookupError> {
+    find_versioned_pre_digest::<B, Signature>(header).map(|pre_digest| pre_digest.slot())
+}
+
+/// Extract the pre-digest of any version from a block header.
+///
+/// The genesis block carries no pre-digest and is reported at slot 0.
+pub fn find_versioned_pre_digest<B: BlockT, Signature: Codec>(
+    header: &B::Header,
+) -> Result<PreDigest, PreDigestLookupError> {
     if header.number().is_zero() {
-        return Ok(0.into());
+        return Ok(PreDigest::V0(0.into()));
     }
 
-    let mut pre_digest: Option<Slot> = None;
+    let mut pre_digest: Option<PreDigest> = None;
     for log in header.digest().logs() {
         trace!(target: LOG_TARGET, "Checking log {:?}", log);
         match (
-            CompatibleDigestItem::<Signature>::as_aura_pre_digest(log),
+            CompatibleDigestItem::<Signature>::as_aura_versioned_pre_digest(log),
             pre_digest.is_some(),
         ) {
             (Some(_), true) => return Err(PreDigestLookupError::MultipleHeaders),
@@ -220,7 +265,7 @@ where
     let runtime_api = client.runtime_api();
 
     match compatibility_mode {
-        CompatibilityMode::None => {}
+        CompatibilityMode::None | CompatibilityMode::SlotOnlyPreDigest { .. } => {}
         // Use `initialize_block` until we hit the block that should disable the mode.
         CompatibilityMode::UseInitializeBlock { until } => {
             if *until > context_block_number {
@@ -264,6 +309,217 @@ where
         .ok_or(ConsensusError::InvalidAuthoritiesSet)
 }
 
+/// Load the fallback timeout from a runtime at a specific block.
+///
+/// Returns `None` if the runtime doesn't support the fallback or has it disabled.
+pub fn fetch_fallback_timeout<A, B, C>(
+    client: &C,
+    parent_hash: B::Hash,
+) -> Result<Option<u64>, ConsensusError>
+where
+    A: Codec,
+    B: BlockT,
+    C: ProvideRuntimeApi<B>,
+    C::Api: AuraApi<B, A>,
+{
+    let runtime_api = client.runtime_api();
+    let has_fallback = runtime_api
+        .has_api_with::<dyn AuraApi<B, A>, _>(parent_hash, |v| v >= 2)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))?;
+    if !has_fallback {
+        return Ok(None);
+    }
+
+    runtime_api
+        .fallback_timeout(parent_hash)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))
+}
+
+/// Load how sessions move on from a runtime at a specific block.
+///
+/// Runtimes without support for slot based sessions move them on by blocks.
+pub fn fetch_session_mode<A, B, C>(
+    client: &C,
+    parent_hash: B::Hash,
+) -> Result<SessionMode, ConsensusError>
+where
+    A: Codec,
+    B: BlockT,
+    C: ProvideRuntimeApi<B>,
+    C::Api: AuraApi<B, A>,
+{
+    let runtime_api = client.runtime_api();
+    let has_session_mode = runtime_api
+        .has_api_with::<dyn AuraApi<B, A>, _>(parent_hash, |v| v >= 8)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))?;
+    if !has_session_mode {
+        return Ok(SessionMode::Blocks);
+    }
+
+    runtime_api
+        .session_mode(parent_hash)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))
+}
+
+/// Load the session a specific block was authored in and the fallback offset of its author from
+/// a runtime at that block.
+///
+/// Runtimes without support for keeping fallback authors report no offset.
+pub fn fetch_fallback_offset<A, B, C>(
+    client: &C,
+    parent_hash: B::Hash,
+) -> Result<(SessionIndex, u64), ConsensusError>
+where
+    A: Codec,
+    B: BlockT,
+    C: ProvideRuntimeApi<B>,
+    C::Api: AuraApi<B, A>,
+{
+    let runtime_api = client.runtime_api();
+    let has_fallback_offset = runtime_api
+        .has_api_with::<dyn AuraApi<B, A>, _>(parent_hash, |v| v >= 9)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))?;
+    if !has_fallback_offset {
+        return Ok(Default::default());
+    }
+
+    runtime_api
+        .fallback_offset(parent_hash)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))
+}
+
+/// Load the number of the block the current session started at from a runtime at a specific
+/// block.
+///
+/// Runtimes not exposing the session schedule report the genesis block.
+pub fn fetch_session_start<A, B, C>(client: &C, parent_hash: B::Hash) -> Result<u64, ConsensusError>
+where
+    A: Codec,
+    B: BlockT,
+    C: ProvideRuntimeApi<B>,
+    C::Api: AuraApi<B, A>,
+{
+    let runtime_api = client.runtime_api();
+    let has_schedule = runtime_api
+        .has_api_with::<dyn AuraApi<B, A>, _>(parent_hash, |v| v >= 5)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))?;
+    if !has_schedule {
+        return Ok(0);
+    }
+
+    runtime_api
+        .current_session_start(parent_hash)
+        .map(|session_start| session_start.saturated_into())
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))
+}
+
+/// Load the indices of the disabled authorities from a runtime at a specific block.
+///
+/// Runtimes without support for disabling authorities have none disabled.
+pub fn fetch_disabled_authorities<A, B, C>(
+    client: &C,
+    parent_hash: B::Hash,
+) -> Result<Vec<AuthorityIndex>, ConsensusError>
+where
+    A: Codec,
+    B: BlockT,
+    C: ProvideRuntimeApi<B>,
+    C::Api: AuraApi<B, A>,
+{
+    let runtime_api = client.runtime_api();
+    let has_disabled = runtime_api
+        .has_api_with::<dyn AuraApi<B, A>, _>(parent_hash, |v| v >= 4)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))?;
+    if !has_disabled {
+        return Ok(Vec::new());
+    }
+
+    runtime_api
+        .disabled_authorities(parent_hash)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))
+}
+
+/// Load the session state a child of `parent` is verified against, along with the block holding
+/// it.
+///
+/// The state tracked from the consensus logs is used if recorded for the parent. Otherwise it is
+/// read from the runtime at the parent, and no block holds it.
+pub fn fetch_session_state<A, B, C>(
+    client: &C,
+    parent: &B::Header,
+    compatibility_mode: &CompatibilityMode<NumberFor<B>>,
+) -> Result<(SessionState<A>, Option<B::Hash>), ConsensusError>
+where
+    A: Codec + Debug,
+    B: BlockT,
+    C: ProvideRuntimeApi<B> + AuxStore,
+    C::Api: AuraApi<B, A>,
+{
+    let parent_hash = parent.hash();
+    if let Some((state, anchor)) = aux_schema::load_session_state(client, parent_hash)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))?
+    {
+        return Ok((state, Some(anchor)));
+    }
+
+    let (authorities, session_index) = fetch_authorities_with_compatibility_mode(
+        client,
+        parent_hash,
+        *parent.number() + One::one(),
+        compatibility_mode,
+    )?;
+    let state = SessionState {
+        authorities,
+        session_index,
+        session_start: fetch_session_start::<A, B, C>(client, parent_hash)?,
+        disabled: fetch_disabled_authorities::<A, B, C>(client, parent_hash)?,
+        fallback_timeout: fetch_fallback_timeout::<A, B, C>(client, parent_hash)?,
+        session_mode: fetch_session_mode::<A, B, C>(client, parent_hash)?,
+        fallback_offset: fetch_fallback_offset::<A, B, C>(client, parent_hash)?,
+    };
+
+    Ok((state, None))
+}
+
+/// Build the [`Fallback`] rule for a block at `slot` built on top of `parent`, from the session
+/// state of the parent.
+///
+/// Returns `None` if there is no timeout, and for children of the genesis block, which carries
+/// no slot.
+pub fn fallback_at<B: BlockT, S: Codec, A>(
+    parent: &B::Header,
+    state: &SessionState<A>,
+    slot: Slot,
+) -> Result<Option<Fallback>, ConsensusError> {
+    let Some(timeout) = state.fallback_timeout else {
+        return Ok(None);
+    };
+    let Some(parent_slot) = parent_slot::<B, S>(parent)? else {
+        return Ok(None);
+    };
+
+    let session_index = state.session_mode.session_index(slot, state.session_index);
+    Ok(Some(Fallback::new(
+        parent_slot,
+        timeout,
+        session_index,
+        state.fallback_offset,
+    )))
+}
+
+/// Slot of the given parent header, `None` for the genesis block, which carries no slot.
+pub fn parent_slot<B: BlockT, S: Codec>(
+    parent: &B::Header,
+) -> Result<Option<Slot>, ConsensusError> {
+    if parent.number().is_zero() {
+        return Ok(None);
+    }
+
+    find_pre_digest::<B, S>(parent)
+        .map(Some)
+        .map_err(|e| ConsensusError::ClientImport(e.to_string()))
+}
+
 /// Errors in slot and seal verification.
 #[derive(Debug, thiserror::Error)]
 pub enum SealVerificationError<Header> {
@@ -290,12 +546,34 @@ pub enum SealVerificationError<Header> {
     /// Header has no valid slot pre-digest.
     #[error("Header has no valid slot pre-digest")]
     InvalidPreDigest(PreDigestLookupError),
+
+    /// The session or author index in the pre-digest are not the expected ones.
+    #[error("Pre-digest claims session and author {claimed:?}, expected {expected:?}")]
+    PreDigestMismatch {
+        /// Session and author index of the slot author.
+        expected: (SessionIndex, AuthorityIndex),
+        /// Session and author index claimed by the pre-digest.
+        claimed: (SessionIndex, AuthorityIndex),
+    },
+
+    /// The slot of the header is not after the slot of its parent.
+    #[error("Header slot {slot:?} is not after the parent slot {parent_slot:?}")]
+    SlotNotIncreasing {
+        /// Slot of the header.
+        slot: Slot,
+        /// Slot of the parent.
+        parent_slot: Slot,
+    },
 }
 
 /// Check a header has been signed by the right key. If the slot is too far in the future, an error
 /// will be returned. If it's successful, returns the pre-header (i.e. without the seal),
 /// the slot, and the digest item containing the seal.
 ///
+/// The slot must be strictly after `parent_slot`, which is `None` for children of the genesis
+/// block, so authors can neither build several blocks on the same slot nor go back in time.
+/// The session of the header follows from `session_mode`, see [`slot_author`].
+///
 /// Note that this does not check for equivocations, and [`check_equivocation`] is recommended
 /// for that purpose.
 ///
@@ -303,7 +581,11 @@ pub enum SealVerificationError<Header> {
 pub fn check_header_slot_and_seal<B: BlockT, P: Pair>(
     slot_now: Slot,
     mut header: B::Header,
+    parent_slot: Option<Slot>,
     aux_data: &AuraAuxData<AuthorityId<P>>,
+    session_mode: SessionMode,
+    disabled: &[AuthorityIndex],
+    fallback: Option<Fallback>,
 ) -> Result<(B::Header, Slot, DigestItem), SealVerificationError<B::Header>>
 where
     P::Signature: Codec,
@@ -317,8 +599,13 @@ where
 
     let sig = seal.as_aura_seal().ok_or(SealVerificationError::BadSeal)?;
 
-    let slot = find_pre_digest::<B, P::Signature>(&header)
+    let pre_digest = find_versioned_pre_digest::<B, P::Signature>(&header)
         .map_err(SealVerificationError::InvalidPreDigest)?;
+    let slot = pre_digest.slot();
+
+    if let Some(parent_slot) = parent_slot.filter(|parent_slot| slot <= *parent_slot) {
+        return Err(SealVerificationError::SlotNotIncreasing { slot, parent_slot });
+    }
 
     if slot > slot_now {
         header.digest_mut().push(seal);
@@ -326,9 +613,31 @@ where
     } else {
         // check the signature is valid under the expected authority and
         // chain state.
-        let expected_author = slot_author::<P>(slot, *session_idx, &authorities)
+        let session_idx = session_mode.session_index(slot, *session_idx);
+        let expected_idx = slot_author_index(
+            slot,
+            session_idx,
+            session_mode,
+            authorities.len(),
+            disabled,
+            fallback,
+        )
+        .ok_or(SealVerificationError::SlotAuthorNotFound)?;
+        let expected_author = authorities
+            .get(expected_idx as usize)
             .ok_or(SealVerificationError::SlotAuthorNotFound)?;
 
+        // the claims of pre-digests carrying them must match the expected author.
+        if let Some(claimed) = pre_digest
+            .session_and_author()
+            .filter(|claimed| *claimed != (session_idx, expected_idx))
+        {
+            return Err(SealVerificationError::PreDigestMismatch {
+                expected: (session_idx, expected_idx),
+                claimed,
+            });
+        }
+
         let pre_hash = header.hash();
 
         if P::verify(&sig, pre_hash.as_ref(), expected_author) {
@@ -343,6 +652,8 @@ where
 mod tests {
     use super::*;
     use sp_keyring::sr25519::Keyring;
+    use spin_primitives::sr25519::AuthorityPair;
+    use substrate_test_runtime_client::runtime::Block as TestBlock;
 
     #[test]
     fn authorities_call_works() {
@@ -373,4 +684,253 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn slot_author_falls_back_after_timeout() {
+        let authorities: Vec<AuthorityId<AuthorityPair>> = vec![
+            Keyring::Alice.public().into(),
+            Keyring::Bob.public().into(),
+            Keyring::Charlie.public().into(),
+        ];
+        let author = |slot: u64, fallback| {
+            slot_author::<AuthorityPair>(
+                slot.into(),
+                1,
+                SessionMode::Blocks,
+                &authorities,
+                &[],
+                fallback,
+            )
+            .cloned()
+        };
+        let fallback = Some(Fallback {
+            parent_slot: 10.into(),
+            timeout: 3,
+            parent_offset: 0,
+        });
+
+        // without fallback the session author is fixed regardless of the slot.
+        assert_eq!(author(11, None), Some(authorities[1].clone()));
+        assert_eq!(author(100, None), Some(authorities[1].clone()));
+
+        // up to `timeout` empty slots the session author keeps its turn.
+        assert_eq!(author(11, fallback), Some(authorities[1].clone()));
+        assert_eq!(author(13, fallback), Some(authorities[1].clone()));
+
+        // then authorship moves along the ring, wrapping around.
+        assert_eq!(author(14, fallback), Some(authorities[2].clone()));
+        assert_eq!(author(16, fallback), Some(authorities[2].clone()));
+        assert_eq!(author(17, fallback), Some(authorities[0].clone()));
+        assert_eq!(author(20, fallback), Some(authorities[1].clone()));
+
+        // a fallback author of the parent keeps the slots, and the timeout applies on top.
+        let fallback = Some(Fallback {
+            parent_slot: 10.into(),
+            timeout: 3,
+            parent_offset: 1,
+        });
+        assert_eq!(author(11, fallback), Some(authorities[2].clone()));
+        assert_eq!(author(14, fallback), Some(authorities[0].clone()));
+    }
+
+    #[test]
+    fn slot_author_skips_disabled_authorities() {
+        let authorities: Vec<AuthorityId<AuthorityPair>> = vec![
+            Keyring::Alice.public().into(),
+            Keyring::Bob.public().into(),
+            Keyring::Charlie.public().into(),
+        ];
+        let author = |session_idx, disabled: &[AuthorityIndex], fallback| {
+            slot_author::<AuthorityPair>(
+                20.into(),
+                session_idx,
+                SessionMode::Blocks,
+                &authorities,
+                disabled,
+                fallback,
+            )
+            .cloned()
+        };
+
+        // the next enabled authority in the ring takes the session.
+        assert_eq!(author(1, &[1], None), Some(authorities[2].clone()));
+        assert_eq!(author(2, &[2], None), Some(authorities[0].clone()));
+        assert_eq!(author(2, &[0, 2], None), Some(authorities[1].clone()));
+        assert_eq!(author(0, &[1], None), Some(authorities[0].clone()));
+
+        // the fallback only moves along enabled authorities.
+        let fallback = Some(Fallback {
+            parent_slot: 10.into(),
+            timeout: 3,
+            parent_offset: 0,
+        });
+        assert_eq!(author(0, &[1], fallback), Some(authorities[2].clone()));
+        assert_eq!(author(1, &[1], fallback), Some(authorities[0].clone()));
+
+        // nobody may author once every authority is disabled.
+        assert_eq!(author(0, &[0, 1, 2], None), None);
+    }
+
+    #[test]
+    fn slot_author_follows_slot_based_sessions() {
+        let authorities: Vec<AuthorityId<AuthorityPair>> = vec![
+            Keyring::Alice.public().into(),
+            Keyring::Bob.public().into(),
+            Keyring::Charlie.public().into(),
+        ];
+        let author = |slot: u64, session_idx, fallback| {
+            slot_author::<AuthorityPair>(
+                slot.into(),
+                session_idx,
+                SessionMode::Slots(10),
+                &authorities,
+                &[],
+                fallback,
+            )
+            .cloned()
+        };
+
+        // the session moves on with the slot, whatever the session of the parent.
+        assert_eq!(author(15, 1, None), Some(authorities[1].clone()));
+        assert_eq!(author(25, 1, None), Some(authorities[2].clone()));
+        assert_eq!(author(45, 1, None), Some(authorities[1].clone()));
+
+        // but never goes back.
+        assert_eq!(author(15, 2, None), Some(authorities[2].clone()));
+
+        // the fallback applies within the session of the slot.
+        let fallback = Some(Fallback {
+            parent_slot: 10.into(),
+            timeout: 3,
+            parent_offset: 0,
+        });
+        assert_eq!(author(25, 1, fallback), Some(authorities[0].clone()));
+    }
+
+    fn check_sealed_by_bob(
+        pre_digest: PreDigest,
+        parent_slot: Option<u64>,
+    ) -> Result<Slot, SealVerificationError<<TestBlock as BlockT>::Header>> {
+        check_sealed_by_bob_in(pre_digest, parent_slot, 1, None)
+    }
+
+    fn check_sealed_by_bob_in(
+        pre_digest: PreDigest,
+        parent_slot: Option<u64>,
+        session_index: SessionIndex,
+        fallback: Option<Fallback>,
+    ) -> Result<Slot, SealVerificationError<<TestBlock as BlockT>::Header>> {
+        let aux_data: AuraAuxData<AuthorityId<AuthorityPair>> = (
+            vec![Keyring::Alice.public().into(), Keyring::Bob.public().into()],
+            session_index,
+        );
+        let mut header = <TestBlock as BlockT>::Header::new(
+            2,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        header
+            .digest_mut()
+            .push(super::pre_digest::<AuthorityPair>(pre_digest));
+        let signature = Keyring::Bob.pair().sign(header.hash().as_ref());
+        header
+            .digest_mut()
+            .push(<DigestItem as CompatibleDigestItem<_>>::aura_seal(
+                spin_primitives::sr25519::AuthoritySignature::from(signature),
+            ));
+
+        check_header_slot_and_seal::<TestBlock, AuthorityPair>(
+            100.into(),
+            header,
+            parent_slot.map(Into::into),
+            &aux_data,
+            SessionMode::Blocks,
+            &[],
+            fallback,
+        )
+        .map(|(_, slot, _)| slot)
+    }
+
+    #[test]
+    fn fallback_authors_pass_verification() {
+        // Alice authors session 0, Bob takes over after two empty slots.
+        let check = |slot: u64, parent_slot: u64, parent_offset| {
+            check_sealed_by_bob_in(
+                PreDigest::V1 {
+                    slot: slot.into(),
+                    session_index: 0,
+                    authority_index: 1,
+                },
+                Some(parent_slot),
+                0,
+                Some(Fallback {
+                    parent_slot: parent_slot.into(),
+                    timeout: 2,
+                    parent_offset,
+                }),
+            )
+        };
+
+        assert!(matches!(
+            check(7, 5, 0),
+            Err(SealVerificationError::PreDigestMismatch {
+                expected: (0, 0),
+                claimed: (0, 1)
+            })
+        ));
+        assert_eq!(check(8, 5, 0).unwrap(), 8.into());
+
+        // and keeps the following slots of the session.
+        assert_eq!(check(9, 8, 1).unwrap(), 9.into());
+    }
+
+    #[test]
+    fn slots_must_increase_from_the_parent() {
+        let check =
+            |slot: u64, parent_slot| check_sealed_by_bob(PreDigest::V0(slot.into()), parent_slot);
+
+        assert_eq!(check(10, None).unwrap(), 10.into());
+        assert_eq!(check(10, Some(9)).unwrap(), 10.into());
+        assert!(matches!(
+            check(10, Some(10)),
+            Err(SealVerificationError::SlotNotIncreasing { slot, parent_slot })
+                if slot == 10.into() && parent_slot == 10.into()
+        ));
+        assert!(matches!(
+            check(10, Some(12)),
+            Err(SealVerificationError::SlotNotIncreasing { .. })
+        ));
+    }
+
+    #[test]
+    fn pre_digest_claims_must_match_the_author() {
+        let check = |session_index, authority_index| {
+            check_sealed_by_bob(
+                PreDigest::V1 {
+                    slot: 10.into(),
+                    session_index,
+                    authority_index,
+                },
+                Some(9),
+            )
+        };
+
+        assert_eq!(check(1, 1).unwrap(), 10.into());
+        assert!(matches!(
+            check(1, 0),
+            Err(SealVerificationError::PreDigestMismatch {
+                expected: (1, 1),
+                claimed: (1, 0)
+            })
+        ));
+        assert!(matches!(
+            check(2, 1),
+            Err(SealVerificationError::PreDigestMismatch {
+                expected: (1, 1),
+                claimed: (2, 1)
+            })
+        ));
+    }
 }
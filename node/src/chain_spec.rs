@@ -1,9 +1,20 @@
-use sc_service::ChainType;
+use sc_service::{ChainType, Properties};
 use solochain_template_runtime::WASM_BINARY;
 
 /// Specialized `ChainSpec`. This is a specialization of the general Substrate ChainSpec type.
 pub type ChainSpec = sc_service::GenericChainSpec;
 
+/// Chain spec property holding the number of the first block whose pre-digest carries the
+/// session and author index, see `--slot-only-pre-digest-until`.
+pub const SLOT_ONLY_PRE_DIGEST_UNTIL: &str = "slotOnlyPreDigestUntil";
+
+/// Properties of chains launched with the session and author index in their pre-digests.
+fn properties() -> Properties {
+    let mut properties = Properties::new();
+    properties.insert(SLOT_ONLY_PRE_DIGEST_UNTIL.into(), 0.into());
+    properties
+}
+
 pub fn development_chain_spec() -> Result<ChainSpec, String> {
     Ok(ChainSpec::builder(
         WASM_BINARY.ok_or_else(|| "Development wasm not available".to_string())?,
@@ -13,6 +24,7 @@ pub fn development_chain_spec() -> Result<ChainSpec, String> {
     .with_id("dev")
     .with_chain_type(ChainType::Development)
     .with_genesis_config_preset_name(sp_genesis_builder::DEV_RUNTIME_PRESET)
+    .with_properties(properties())
     .build())
 }
 
@@ -25,5 +37,6 @@ pub fn local_chain_spec() -> Result<ChainSpec, String> {
     .with_id("local_testnet")
     .with_chain_type(ChainType::Local)
     .with_genesis_config_preset_name(sp_genesis_builder::LOCAL_TESTNET_RUNTIME_PRESET)
+    .with_properties(properties())
     .build())
 }
@@ -1,4 +1,10 @@
+use crate::chain_spec::SLOT_ONLY_PRE_DIGEST_UNTIL;
 use sc_cli::RunCmd;
+use spin_consensus::{
+    BackoffAuthoringOnFinalizedHeadLagging, CompatibilityMode, DriftTolerance, ProposalBudget,
+    SlotProportion,
+};
+use std::time::Duration;
 
 #[derive(Debug, clap::Parser)]
 pub struct Cli {
@@ -7,6 +13,178 @@ pub struct Cli {
 
     #[clap(flatten)]
     pub run: RunCmd,
+
+    #[clap(flatten)]
+    pub spin: SpinParams,
+}
+
+/// Parameters of the spin consensus.
+#[derive(Debug, Clone, clap::Args)]
+pub struct SpinParams {
+    /// How far ahead of the local clock the slot of an imported block may be.
+    ///
+    /// Either a number of slots, or a duration in milliseconds with an `ms` suffix, e.g.
+    /// `1500ms`. Blocks further ahead are held back until their slot.
+    #[arg(
+        long,
+        value_name = "SLOTS|MILLISms",
+        default_value = "1",
+        value_parser = parse_drift_tolerance
+    )]
+    pub max_slot_drift: DriftTolerance,
+
+    /// Keep authoring at full speed while finality is lagging.
+    ///
+    /// By default, session authors skip slots once too many of their blocks past the finalized
+    /// one are built, see the `--backoff-*` parameters.
+    #[arg(long)]
+    pub no_backoff: bool,
+
+    /// Number of unfinalized blocks an author may build on before slowing down.
+    #[arg(long, value_name = "BLOCKS", default_value_t = 5)]
+    pub backoff_unfinalized_slack: u32,
+
+    /// Number of unfinalized blocks beyond the slack adding one slot to the authoring interval.
+    #[arg(
+        long,
+        value_name = "BLOCKS",
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub backoff_authoring_bias: u32,
+
+    /// Maximum number of slots to skip between two blocks when backing off.
+    #[arg(long, value_name = "SLOTS", default_value_t = 10)]
+    pub backoff_max_interval: u32,
+
+    /// Smallest share of the slot given to proposing, in percent.
+    ///
+    /// The share adapts to recent import times, leaving the next author time to import the
+    /// block. Equal minimum and maximum shares keep it fixed.
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 25,
+        value_parser = clap::value_parser!(u8).range(1..=100)
+    )]
+    pub min_proposal_portion: u8,
+
+    /// Largest share of the slot given to proposing, in percent.
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 75,
+        value_parser = clap::value_parser!(u8).range(1..=100)
+    )]
+    pub max_proposal_portion: u8,
+
+    /// Number of the first block whose pre-digest must carry the session and author index.
+    ///
+    /// Blocks below it are authored, and may be imported, with a pre-digest carrying only the
+    /// slot, as written by chains launched before the index was added. Defaults to the
+    /// `slotOnlyPreDigestUntil` property of the chain spec. Chains without it keep to slot-only
+    /// pre-digests until a block is configured here, which all nodes should agree on.
+    #[arg(long, value_name = "BLOCK")]
+    pub slot_only_pre_digest_until: Option<u32>,
+
+    /// Seal blocks on demand instead of authoring them at their slot, for `--dev` chains only.
+    ///
+    /// `instant` seals a block for every transaction, `manual` only on `engine_createBlock`
+    /// calls, and `interval=<ms>` every given number of milliseconds. The `engine_*` RPCs are
+    /// available in every mode. Blocks sealed faster than slots pass take the slot after their
+    /// parent's, with timestamps running ahead of the clock.
+    #[arg(long, value_name = "instant|manual|interval=<ms>", value_parser = parse_sealing)]
+    pub sealing: Option<Sealing>,
+}
+
+/// How blocks are sealed on demand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sealing {
+    /// Seal a block as soon as a transaction enters the pool.
+    Instant,
+    /// Seal blocks on RPC calls only.
+    Manual,
+    /// Seal a block at every interval, empty or not.
+    Interval(Duration),
+}
+
+impl SpinParams {
+    /// The strategy to back off from authoring when finality lags, unless disabled.
+    pub fn backoff_authoring_blocks<N: From<u32>>(
+        &self,
+    ) -> Option<BackoffAuthoringOnFinalizedHeadLagging<N>> {
+        (!self.no_backoff).then(|| BackoffAuthoringOnFinalizedHeadLagging {
+            max_interval: self.backoff_max_interval.into(),
+            unfinalized_slack: self.backoff_unfinalized_slack.into(),
+            authoring_bias: self.backoff_authoring_bias.into(),
+        })
+    }
+
+    /// The proposal time budget, adapting between the configured shares of the slot.
+    pub fn proposal_budget(&self) -> ProposalBudget {
+        ProposalBudget::new(
+            SlotProportion::new(f32::from(self.min_proposal_portion) / 100.0),
+            SlotProportion::new(f32::from(self.max_proposal_portion) / 100.0),
+        )
+    }
+
+    /// The compatibility mode for pre-digests carrying only the slot on the given chain.
+    pub fn compatibility_mode<N: From<u32>>(
+        &self,
+        chain_spec: &dyn sc_service::ChainSpec,
+    ) -> Result<CompatibilityMode<N>, String> {
+        let until = match self.slot_only_pre_digest_until {
+            Some(until) => until,
+            None => match chain_spec.properties().get(SLOT_ONLY_PRE_DIGEST_UNTIL) {
+                Some(value) => value
+                    .as_u64()
+                    .and_then(|until| until.try_into().ok())
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid `{SLOT_ONLY_PRE_DIGEST_UNTIL}` chain spec property: {value}"
+                        )
+                    })?,
+                None => u32::MAX,
+            },
+        };
+
+        Ok(match until {
+            0 => CompatibilityMode::None,
+            until => CompatibilityMode::SlotOnlyPreDigest {
+                until: until.into(),
+            },
+        })
+    }
+}
+
+fn parse_drift_tolerance(s: &str) -> Result<DriftTolerance, String> {
+    match s.strip_suffix("ms") {
+        Some(millis) => millis
+            .parse()
+            .map(|millis| DriftTolerance::Duration(Duration::from_millis(millis)))
+            .map_err(|e| format!("Invalid duration `{s}`: {e}")),
+        None => s
+            .parse()
+            .map(DriftTolerance::Slots)
+            .map_err(|e| format!("Invalid number of slots `{s}`: {e}")),
+    }
+}
+
+fn parse_sealing(s: &str) -> Result<Sealing, String> {
+    match s {
+        "instant" => Ok(Sealing::Instant),
+        "manual" => Ok(Sealing::Manual),
+        _ => match s.strip_prefix("interval=") {
+            Some(millis) => match millis.parse() {
+                Ok(0) => Err("The sealing interval must be positive".into()),
+                Ok(millis) => Ok(Sealing::Interval(Duration::from_millis(millis))),
+                Err(e) => Err(format!("Invalid interval `{millis}`: {e}")),
+            },
+            None => Err(format!(
+                "Unknown sealing mode `{s}`, expected `instant`, `manual` or `interval=<ms>`"
+            )),
+        },
+    }
 }
 
 #[derive(Debug, clap::Subcommand)]
@@ -14,6 +192,14 @@ pub struct Cli {
 pub enum Subcommand {
     /// Key management cli utilities
     #[command(subcommand)]
+    #[cfg_attr(
+        feature = "ed25519-authorities",
+        command(
+            mut_subcommand("generate", ed25519_scheme),
+            mut_subcommand("inspect", ed25519_scheme),
+            mut_subcommand("insert", ed25519_scheme)
+        )
+    )]
     Key(sc_cli::KeySubcommand),
 
     /// Build a chain specification.
@@ -44,3 +230,163 @@ pub enum Subcommand {
     /// Db meta columns information.
     ChainInfo(sc_cli::ChainInfoCmd),
 }
+
+/// Default a key command to Ed25519, the crypto of the block authorities.
+#[cfg(feature = "ed25519-authorities")]
+fn ed25519_scheme(cmd: clap::Command) -> clap::Command {
+    cmd.mut_arg("scheme", |arg| arg.default_value("ed25519"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Debug, clap::Parser)]
+    struct Params {
+        #[clap(flatten)]
+        spin: SpinParams,
+    }
+
+    fn spin_params(args: &[&str]) -> SpinParams {
+        Params::try_parse_from(std::iter::once("node").chain(args.iter().copied()))
+            .unwrap()
+            .spin
+    }
+
+    #[test]
+    fn backoff_is_on_by_default() {
+        assert!(spin_params(&["--no-backoff"])
+            .backoff_authoring_blocks::<u32>()
+            .is_none());
+        assert!(
+            spin_params(&["--no-backoff", "--backoff-max-interval", "3"])
+                .backoff_authoring_blocks::<u32>()
+                .is_none()
+        );
+
+        let strategy = spin_params(&[]).backoff_authoring_blocks::<u32>().unwrap();
+        assert_eq!(strategy.max_interval, 10);
+        assert_eq!(strategy.unfinalized_slack, 5);
+        assert_eq!(strategy.authoring_bias, 2);
+
+        let strategy = spin_params(&[
+            "--backoff-unfinalized-slack",
+            "1",
+            "--backoff-authoring-bias",
+            "4",
+            "--backoff-max-interval",
+            "3",
+        ])
+        .backoff_authoring_blocks::<u32>()
+        .unwrap();
+        assert_eq!(strategy.max_interval, 3);
+        assert_eq!(strategy.unfinalized_slack, 1);
+        assert_eq!(strategy.authoring_bias, 4);
+
+        assert!(Params::try_parse_from(["node", "--backoff-authoring-bias", "0"]).is_err());
+    }
+
+    #[test]
+    fn slot_only_pre_digests_are_accepted_until_the_cutover() {
+        let chain_spec = |until: Option<serde_json::Value>| {
+            let mut properties = sc_service::Properties::new();
+            if let Some(until) = until {
+                properties.insert(SLOT_ONLY_PRE_DIGEST_UNTIL.into(), until);
+            }
+            crate::chain_spec::ChainSpec::builder(&[], None)
+                .with_name("Test")
+                .with_id("test")
+                .with_genesis_config_patch(serde_json::json!({}))
+                .with_properties(properties)
+                .build()
+        };
+        let until =
+            |args: &[&str], chain_spec: &crate::chain_spec::ChainSpec| match spin_params(args)
+                .compatibility_mode::<u32>(chain_spec)
+            {
+                Ok(CompatibilityMode::None) => Some(0),
+                Ok(CompatibilityMode::SlotOnlyPreDigest { until }) => Some(until),
+                _ => None,
+            };
+
+        // chains without a cutover keep to slot-only pre-digests.
+        assert_eq!(until(&[], &chain_spec(None)), Some(u32::MAX));
+        assert_eq!(until(&[], &chain_spec(Some(0.into()))), Some(0));
+        assert_eq!(until(&[], &chain_spec(Some(100.into()))), Some(100));
+        assert_eq!(
+            until(
+                &["--slot-only-pre-digest-until", "200"],
+                &chain_spec(Some(100.into()))
+            ),
+            Some(200)
+        );
+
+        for invalid in [(-1).into(), u64::MAX.into(), "100".into()] {
+            assert_eq!(until(&[], &chain_spec(Some(invalid))), None);
+        }
+    }
+
+    #[test]
+    fn drift_tolerance_is_slots_or_millis() {
+        assert_eq!(parse_drift_tolerance("0"), Ok(DriftTolerance::Slots(0)));
+        assert_eq!(parse_drift_tolerance("3"), Ok(DriftTolerance::Slots(3)));
+        assert_eq!(
+            parse_drift_tolerance("1500ms"),
+            Ok(DriftTolerance::Duration(Duration::from_millis(1500)))
+        );
+
+        for invalid in ["", "ms", "-1", "1.5", "2s", "1500 ms", "slots"] {
+            assert!(parse_drift_tolerance(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn sealing_modes_are_parsed() {
+        assert_eq!(parse_sealing("instant"), Ok(Sealing::Instant));
+        assert_eq!(parse_sealing("manual"), Ok(Sealing::Manual));
+        assert_eq!(
+            parse_sealing("interval=6000"),
+            Ok(Sealing::Interval(Duration::from_millis(6000)))
+        );
+
+        for invalid in [
+            "",
+            "Instant",
+            "interval",
+            "interval=",
+            "interval=0",
+            "interval=-1",
+            "interval=6s",
+        ] {
+            assert!(parse_sealing(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn key_commands_default_to_the_authority_scheme() {
+        use clap::CommandFactory;
+
+        let expected = if cfg!(feature = "ed25519-authorities") {
+            "ed25519"
+        } else {
+            "sr25519"
+        };
+        let cli = Cli::command();
+        let key = cli.find_subcommand("key").unwrap();
+        for name in ["generate", "inspect", "insert"] {
+            let scheme = key
+                .find_subcommand(name)
+                .unwrap()
+                .get_arguments()
+                .find(|arg| arg.get_id() == "scheme")
+                .unwrap();
+            let defaults = scheme
+                .get_default_values()
+                .iter()
+                .map(|value| value.to_str().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(defaults, [expected], "{name}");
+        }
+    }
+}
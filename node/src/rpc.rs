@@ -5,14 +5,21 @@
 
 #![warn(missing_docs)]
 
+pub mod spin;
+
 use std::sync::Arc;
 
+use futures::channel::mpsc;
 use jsonrpsee::RpcModule;
+use sc_consensus_manual_seal::EngineCommand;
 use sc_transaction_pool_api::TransactionPool;
-use solochain_template_runtime::{opaque::Block, AccountId, Balance, Nonce};
+use solochain_template_runtime::{
+    aura_crypto::AuthorityPair as AuraPair, opaque::Block, AccountId, AuraId, Balance, Hash, Nonce,
+};
 use sp_api::ProvideRuntimeApi;
 use sp_block_builder::BlockBuilder;
 use sp_blockchain::{Error as BlockChainError, HeaderBackend, HeaderMetadata};
+use sp_keystore::KeystorePtr;
 
 /// Full client dependencies.
 pub struct FullDeps<C, P> {
@@ -20,6 +27,10 @@ pub struct FullDeps<C, P> {
     pub client: Arc<C>,
     /// Transaction pool instance.
     pub pool: Arc<P>,
+    /// The keystore holding the keys of the node.
+    pub keystore: KeystorePtr,
+    /// Channel to the sealing task, if blocks are sealed on demand.
+    pub command_sink: Option<mpsc::Sender<EngineCommand<Hash>>>,
 }
 
 /// Instantiate all full RPC extensions.
@@ -33,16 +44,30 @@ where
     C::Api: substrate_frame_rpc_system::AccountNonceApi<Block, AccountId, Nonce>,
     C::Api: pallet_transaction_payment_rpc::TransactionPaymentRuntimeApi<Block, Balance>,
     C::Api: BlockBuilder<Block>,
+    C::Api: spin_primitives::AuraApi<Block, AuraId>,
     P: TransactionPool + 'static,
 {
     use pallet_transaction_payment_rpc::{TransactionPayment, TransactionPaymentApiServer};
+    use spin::{Spin, SpinApiServer};
     use substrate_frame_rpc_system::{System, SystemApiServer};
 
     let mut module = RpcModule::new(());
-    let FullDeps { client, pool } = deps;
+    let FullDeps {
+        client,
+        pool,
+        keystore,
+        command_sink,
+    } = deps;
 
     module.merge(System::new(client.clone(), pool).into_rpc())?;
-    module.merge(TransactionPayment::new(client).into_rpc())?;
+    module.merge(TransactionPayment::new(client.clone()).into_rpc())?;
+    module.merge(Spin::<_, Block, AuraPair>::new(client, keystore).into_rpc())?;
+
+    if let Some(command_sink) = command_sink {
+        use sc_consensus_manual_seal::rpc::{ManualSeal, ManualSealApiServer};
+
+        module.merge(ManualSeal::new(command_sink).into_rpc())?;
+    }
 
     // Extend this RPC with a custom API by using the following syntax.
     // `YourRpcStruct` should have a reference to a client, which is needed
@@ -0,0 +1,382 @@
+//! RPC methods of the spin consensus.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use codec::Codec;
+use jsonrpsee::{
+    core::RpcResult,
+    proc_macros::rpc,
+    types::error::{ErrorObject, ErrorObjectOwned},
+};
+use serde::{Deserialize, Serialize};
+use sp_api::{ApiExt, ProvideRuntimeApi};
+use sp_blockchain::HeaderBackend;
+use sp_core::crypto::{key_types::AURA, ByteArray, Pair};
+use sp_keystore::KeystorePtr;
+use sp_runtime::traits::{Block as BlockT, Header, NumberFor, Zero};
+use spin_primitives::{
+    digests::CompatibleDigestItem, AuraApi, AuthorityIndex, SessionIndex, SessionMode,
+};
+
+/// Schedule of the sessions as of a given block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSchedule<BlockNumber> {
+    /// Index of the current session.
+    pub session_index: SessionIndex,
+    /// How sessions move on.
+    pub session_mode: ScheduleMode,
+    /// Number of blocks in a session.
+    pub session_length: BlockNumber,
+    /// Number of the block the current session started at.
+    pub current_session_start: BlockNumber,
+    /// Number of the block the next session starts at.
+    ///
+    /// `None` for sessions following the slots, which start at the first block of their slots.
+    pub next_session_start: Option<BlockNumber>,
+    /// Number of blocks in the next session, which may differ if a change is scheduled.
+    pub next_session_length: BlockNumber,
+}
+
+/// How sessions move on, see [`SessionMode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScheduleMode {
+    /// Sessions move on every `sessionLength` blocks.
+    Blocks,
+    /// Sessions move on with the slots, every given number of them.
+    Slots(u64),
+}
+
+impl From<SessionMode> for ScheduleMode {
+    fn from(mode: SessionMode) -> Self {
+        match mode {
+            SessionMode::Blocks => Self::Blocks,
+            SessionMode::Slots(slots_per_session) => Self::Slots(slots_per_session),
+        }
+    }
+}
+
+/// Spin digests of a block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockDigest<AuthorityId> {
+    /// Slot of the pre-digest.
+    pub slot: u64,
+    /// Index of the session the block was authored in.
+    ///
+    /// Read from the pre-digest if it carries it, otherwise derived from the parent state and
+    /// the slot.
+    pub session_index: SessionIndex,
+    /// Index of the author claimed by the pre-digest, if it carries one.
+    pub author_index: Option<AuthorityIndex>,
+    /// Index of the authority whose key signed the seal, if any.
+    pub seal_signer_index: Option<AuthorityIndex>,
+    /// The authority whose key signed the seal, if any.
+    pub seal_signer: Option<AuthorityId>,
+}
+
+/// Whether the local keystore holds the key of a session author.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionKeys {
+    /// The key of the current session author is in the keystore.
+    pub current: bool,
+    /// The key of the next session author is in the keystore.
+    pub next: bool,
+}
+
+/// Spin RPC methods.
+#[rpc(server)]
+pub trait SpinApi<BlockHash, BlockNumber, AuthorityId> {
+    /// Returns the index of the current session as of the given block, or the best block.
+    #[method(name = "spin_sessionIndex")]
+    fn session_index(&self, at: Option<BlockHash>) -> RpcResult<SessionIndex>;
+
+    /// Returns the authority scheduled to author the current session, as of the given block,
+    /// or the best block.
+    #[method(name = "spin_currentSessionAuthor")]
+    fn current_session_author(&self, at: Option<BlockHash>) -> RpcResult<Option<AuthorityId>>;
+
+    /// Returns the authority set at the given block, or the best block.
+    #[method(name = "spin_authorities")]
+    fn authorities(&self, at: Option<BlockHash>) -> RpcResult<Vec<AuthorityId>>;
+
+    /// Returns the decoded spin digests of the given block, or the best block.
+    ///
+    /// Returns `None` if the block is unknown.
+    #[method(name = "spin_blockDigest")]
+    fn block_digest(&self, at: Option<BlockHash>) -> RpcResult<Option<BlockDigest<AuthorityId>>>;
+
+    /// Returns whether the local keystore holds the key of the current and of the next session
+    /// author, as of the given block, or the best block.
+    #[method(name = "spin_hasSessionKeys")]
+    fn has_session_keys(&self, at: Option<BlockHash>) -> RpcResult<SessionKeys>;
+
+    /// Returns the session schedule as of the given block, or the best block.
+    #[method(name = "spin_sessionSchedule")]
+    fn session_schedule(&self, at: Option<BlockHash>) -> RpcResult<SessionSchedule<BlockNumber>>;
+
+    /// Returns the authority scheduled to author the given session, as of the given block, or
+    /// the best block.
+    #[method(name = "spin_sessionAuthor")]
+    fn session_author(
+        &self,
+        session_index: SessionIndex,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Option<AuthorityId>>;
+}
+
+/// Error type of this RPC api.
+pub enum Error {
+    /// The call to the runtime failed.
+    RuntimeError,
+    /// The runtime doesn't provide the requested data.
+    Unsupported,
+    /// The block has no valid spin pre-digest.
+    InvalidDigest,
+}
+
+impl From<Error> for i32 {
+    fn from(e: Error) -> i32 {
+        match e {
+            Error::RuntimeError => 1,
+            Error::Unsupported => 2,
+            Error::InvalidDigest => 3,
+        }
+    }
+}
+
+/// Provides RPC methods to query the spin sessions and authorities.
+pub struct Spin<C, Block, P> {
+    client: Arc<C>,
+    keystore: KeystorePtr,
+    _marker: PhantomData<(Block, P)>,
+}
+
+impl<C, Block, P> Spin<C, Block, P> {
+    /// Creates a new instance of the Spin RPC handler.
+    pub fn new(client: Arc<C>, keystore: KeystorePtr) -> Self {
+        Self {
+            client,
+            keystore,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<C, Block, P> Spin<C, Block, P>
+where
+    Block: BlockT,
+    C: ProvideRuntimeApi<Block> + HeaderBackend<Block>,
+    C::Api: AuraApi<Block, P::Public>,
+    P: Pair,
+    P::Public: Codec,
+{
+    /// The given block or the best block.
+    fn at(&self, at: Option<Block::Hash>) -> Block::Hash {
+        at.unwrap_or_else(|| self.client.info().best_hash)
+    }
+
+    /// The given block or the best block, provided its runtime supports the schedule.
+    fn schedule_at(&self, at: Option<Block::Hash>) -> RpcResult<Block::Hash> {
+        let at = self.at(at);
+        let supported = self
+            .client
+            .runtime_api()
+            .has_api_with::<dyn AuraApi<Block, P::Public>, _>(at, |v| v >= 5)
+            .map_err(runtime_error)?;
+
+        if !supported {
+            return Err(ErrorObject::owned(
+                Error::Unsupported.into(),
+                "The runtime doesn't provide the session schedule.",
+                None::<()>,
+            ));
+        }
+
+        Ok(at)
+    }
+
+    /// Whether the key of the author of the given session is in the keystore.
+    fn has_author_key(&self, at: Block::Hash, session_index: SessionIndex) -> RpcResult<bool> {
+        let author = self
+            .client
+            .runtime_api()
+            .session_author(at, session_index)
+            .map_err(runtime_error)?;
+
+        Ok(author.is_some_and(|author| self.keystore.has_keys(&[(author.to_raw_vec(), AURA)])))
+    }
+}
+
+fn runtime_error(e: impl std::fmt::Display) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        Error::RuntimeError.into(),
+        "Unable to query the runtime.",
+        Some(e.to_string()),
+    )
+}
+
+impl<C, Block, P> SpinApiServer<Block::Hash, NumberFor<Block>, P::Public> for Spin<C, Block, P>
+where
+    Block: BlockT,
+    C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+    C::Api: AuraApi<Block, P::Public>,
+    P: Pair + 'static,
+    P::Public: Codec + Serialize,
+    P::Signature: Codec,
+{
+    fn session_index(&self, at: Option<Block::Hash>) -> RpcResult<SessionIndex> {
+        let (_, session_index) = self
+            .client
+            .runtime_api()
+            .aux_data(self.at(at))
+            .map_err(runtime_error)?;
+
+        Ok(session_index)
+    }
+
+    fn current_session_author(&self, at: Option<Block::Hash>) -> RpcResult<Option<P::Public>> {
+        let at = self.schedule_at(at)?;
+        let api = self.client.runtime_api();
+
+        let (_, session_index) = api.aux_data(at).map_err(runtime_error)?;
+        api.session_author(at, session_index).map_err(runtime_error)
+    }
+
+    fn authorities(&self, at: Option<Block::Hash>) -> RpcResult<Vec<P::Public>> {
+        self.client
+            .runtime_api()
+            .authorities(self.at(at))
+            .map_err(runtime_error)
+    }
+
+    fn block_digest(&self, at: Option<Block::Hash>) -> RpcResult<Option<BlockDigest<P::Public>>> {
+        let Some(mut header) = self.client.header(self.at(at)).map_err(runtime_error)? else {
+            return Ok(None);
+        };
+
+        let invalid_digest = |e: &dyn std::fmt::Display| {
+            ErrorObject::owned(
+                Error::InvalidDigest.into(),
+                "The block has no valid spin pre-digest.",
+                Some(e.to_string()),
+            )
+        };
+        if header.number().is_zero() {
+            return Err(invalid_digest(&"the genesis block has no digests"));
+        }
+
+        let pre_digest = spin_consensus::find_versioned_pre_digest::<Block, P::Signature>(&header)
+            .map_err(|e| invalid_digest(&e))?;
+        let slot = pre_digest.slot();
+
+        // the seal is made by an authority of the parent state, over the header without it.
+        let parent_hash = *header.parent_hash();
+        let (authorities, parent_session_index) = self
+            .client
+            .runtime_api()
+            .aux_data(parent_hash)
+            .map_err(runtime_error)?;
+        let (session_index, author_index) = match pre_digest.session_and_author() {
+            Some((session_index, author_index)) => (session_index, Some(author_index)),
+            None => {
+                let session_mode = spin_consensus::standalone::fetch_session_mode::<
+                    P::Public,
+                    Block,
+                    C,
+                >(&*self.client, parent_hash)
+                .map_err(runtime_error)?;
+                (session_mode.session_index(slot, parent_session_index), None)
+            }
+        };
+        let signature = header
+            .digest()
+            .logs()
+            .last()
+            .and_then(CompatibleDigestItem::<P::Signature>::as_aura_seal);
+        let seal_signer_index = signature.and_then(|signature| {
+            header.digest_mut().pop();
+            let pre_hash = header.hash();
+            authorities
+                .iter()
+                .position(|authority| P::verify(&signature, pre_hash.as_ref(), authority))
+        });
+
+        Ok(Some(BlockDigest {
+            slot: *slot,
+            session_index,
+            author_index,
+            seal_signer_index: seal_signer_index.map(|index| index as AuthorityIndex),
+            seal_signer: seal_signer_index.map(|index| authorities[index].clone()),
+        }))
+    }
+
+    fn has_session_keys(&self, at: Option<Block::Hash>) -> RpcResult<SessionKeys> {
+        let at = self.schedule_at(at)?;
+        let (_, session_index) = self
+            .client
+            .runtime_api()
+            .aux_data(at)
+            .map_err(runtime_error)?;
+
+        Ok(SessionKeys {
+            current: self.has_author_key(at, session_index)?,
+            next: self.has_author_key(at, session_index + 1)?,
+        })
+    }
+
+    fn session_schedule(
+        &self,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<SessionSchedule<NumberFor<Block>>> {
+        let at = self.schedule_at(at)?;
+        let api = self.client.runtime_api();
+
+        let (_, session_index) = api.aux_data(at).map_err(runtime_error)?;
+        let session_length = api.session_length(at).map_err(runtime_error)?;
+
+        // runtimes before v6 can't change the session length.
+        let next_session_length = if api
+            .has_api_with::<dyn AuraApi<Block, P::Public>, _>(at, |v| v >= 6)
+            .map_err(runtime_error)?
+        {
+            api.next_session_length(at).map_err(runtime_error)?
+        } else {
+            session_length
+        };
+
+        let session_mode = spin_consensus::standalone::fetch_session_mode::<P::Public, Block, C>(
+            &*self.client,
+            at,
+        )
+        .map_err(runtime_error)?;
+        let next_session_start = match session_mode {
+            SessionMode::Blocks => Some(api.next_session_start(at).map_err(runtime_error)?),
+            SessionMode::Slots(_) => None,
+        };
+
+        Ok(SessionSchedule {
+            session_index,
+            session_mode: session_mode.into(),
+            session_length,
+            current_session_start: api.current_session_start(at).map_err(runtime_error)?,
+            next_session_start,
+            next_session_length,
+        })
+    }
+
+    fn session_author(
+        &self,
+        session_index: SessionIndex,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<Option<P::Public>> {
+        let at = self.schedule_at(at)?;
+
+        self.client
+            .runtime_api()
+            .session_author(at, session_index)
+            .map_err(runtime_error)
+    }
+}
@@ -1,15 +1,26 @@
 //! Service and ServiceFactory implementation. Specialized wrapper over substrate service.
 
-use futures::FutureExt;
+use crate::cli::{Sealing, SpinParams};
+use futures::{channel::mpsc, prelude::*, stream};
 use sc_client_api::{Backend, BlockBackend};
 use sc_consensus_grandpa::SharedVoterState;
-use sc_service::{error::Error as ServiceError, Configuration, TaskManager, WarpSyncConfig};
+use sc_consensus_manual_seal::{EngineCommand, ManualSealParams};
+use sc_service::{
+    error::Error as ServiceError, ChainType, Configuration, TaskManager, WarpSyncConfig,
+};
 use sc_telemetry::{Telemetry, TelemetryWorker};
-use sc_transaction_pool_api::OffchainTransactionPoolFactory;
-use solochain_template_runtime::{self, apis::RuntimeApi, opaque::Block};
-use spin_primitives::sr25519::AuthorityPair as AuraPair;
-use spin_consensus::{ImportQueueParams, SlotProportion, StartAuraParams};
-use std::{sync::Arc, time::Duration};
+use sc_transaction_pool_api::{OffchainTransactionPoolFactory, TransactionPool};
+use solochain_template_runtime::aura_crypto::{
+    AuthorityPair as AuraPair, AuthoritySignature as AuraSignature,
+};
+use solochain_template_runtime::{self, apis::RuntimeApi, opaque::Block, Hash};
+use sp_blockchain::HeaderBackend;
+use sp_keystore::{Keystore, KeystorePtr};
+use spin_consensus::{
+    manual_seal::SpinConsensusDataProvider, ImportQueueParams, ProposalBudget, SessionForkChoice,
+    SlotProportion, StartAuraParams,
+};
+use std::{pin::Pin, sync::Arc, time::Duration};
 
 pub(crate) type FullClient = sc_service::TFullClient<
     Block,
@@ -27,16 +38,17 @@ pub type Service = sc_service::PartialComponents<
     FullClient,
     FullBackend,
     FullSelectChain,
-    sc_consensus::DefaultImportQueue<Block>,
+    spin_consensus::DeferredImportQueue<Block>,
     sc_transaction_pool::TransactionPoolHandle<Block, FullClient>,
     (
         sc_consensus_grandpa::GrandpaBlockImport<FullBackend, Block, FullClient, FullSelectChain>,
         sc_consensus_grandpa::LinkHalf<Block, FullClient, FullSelectChain>,
         Option<Telemetry>,
+        ProposalBudget,
     ),
 >;
 
-pub fn new_partial(config: &Configuration) -> Result<Service, ServiceError> {
+pub fn new_partial(config: &Configuration, spin: &SpinParams) -> Result<Service, ServiceError> {
     let telemetry = config
         .telemetry_endpoints
         .clone()
@@ -85,10 +97,14 @@ pub fn new_partial(config: &Configuration) -> Result<Service, ServiceError> {
         telemetry.as_ref().map(|x| x.handle()),
     )?;
 
+    let proposal_budget = spin.proposal_budget();
+    let compatibility_mode = spin
+        .compatibility_mode(&*config.chain_spec)
+        .map_err(ServiceError::Other)?;
     let cidp_client = client.clone();
     let import_queue =
-        spin_consensus::import_queue::<AuraPair, _, _, _, _, _>(ImportQueueParams {
-            block_import: grandpa_block_import.clone(),
+        spin_consensus::import_queue::<AuraPair, _, _, _, _, _, _>(ImportQueueParams {
+            block_import: proposal_budget.block_import(grandpa_block_import.clone()),
             justification_import: Some(Box::new(grandpa_block_import.clone())),
             client: client.clone(),
             create_inherent_data_providers: move |parent_hash, _| {
@@ -111,7 +127,12 @@ pub fn new_partial(config: &Configuration) -> Result<Service, ServiceError> {
             registry: config.prometheus_registry(),
             check_for_equivocation: Default::default(),
             telemetry: telemetry.as_ref().map(|x| x.handle()),
-            compatibility_mode: Default::default(),
+            compatibility_mode,
+            offchain_tx_pool_factory: OffchainTransactionPoolFactory::new(
+                transaction_pool.clone(),
+            ),
+            drift_tolerance: spin.max_slot_drift,
+            fork_choice: SessionForkChoice::new(client.clone()),
         })?;
 
     Ok(sc_service::PartialComponents {
@@ -122,7 +143,12 @@ pub fn new_partial(config: &Configuration) -> Result<Service, ServiceError> {
         keystore_container,
         select_chain,
         transaction_pool,
-        other: (grandpa_block_import, grandpa_link, telemetry),
+        other: (
+            grandpa_block_import,
+            grandpa_link,
+            telemetry,
+            proposal_budget,
+        ),
     })
 }
 
@@ -131,6 +157,7 @@ pub fn new_full<
     N: sc_network::NetworkBackend<Block, <Block as sp_runtime::traits::Block>::Hash>,
 >(
     config: Configuration,
+    spin: SpinParams,
 ) -> Result<TaskManager, ServiceError> {
     let sc_service::PartialComponents {
         client,
@@ -140,8 +167,21 @@ pub fn new_full<
         keystore_container,
         select_chain,
         transaction_pool,
-        other: (block_import, grandpa_link, mut telemetry),
-    } = new_partial(&config)?;
+        other: (block_import, grandpa_link, mut telemetry, proposal_budget),
+    } = new_partial(&config, &spin)?;
+
+    if spin.sealing.is_some() && config.chain_spec.chain_type() != ChainType::Development {
+        return Err(ServiceError::Other(
+            "Sealing on demand is only available for development chains".into(),
+        ));
+    }
+    let (command_sink, sealing) = match spin.sealing {
+        Some(sealing) => {
+            let (sink, commands) = mpsc::channel(1024);
+            (Some(sink), Some((sealing, commands)))
+        }
+        None => (None, None),
+    };
 
     let mut net_config = sc_network::config::FullNetworkConfiguration::<
         Block,
@@ -212,7 +252,11 @@ pub fn new_full<
 
     let role = config.role;
     let force_authoring = config.force_authoring;
-    let backoff_authoring_blocks: Option<()> = None;
+    let backoff_authoring_blocks =
+        spin.backoff_authoring_blocks::<solochain_template_runtime::BlockNumber>();
+    let compatibility_mode = spin
+        .compatibility_mode(&*config.chain_spec)
+        .map_err(ServiceError::Other)?;
     let name = config.network.node_name.clone();
     let enable_grandpa = !config.disable_grandpa;
     let prometheus_registry = config.prometheus_registry().cloned();
@@ -220,11 +264,14 @@ pub fn new_full<
     let rpc_extensions_builder = {
         let client = client.clone();
         let pool = transaction_pool.clone();
+        let keystore = keystore_container.keystore();
 
         Box::new(move |_| {
             let deps = crate::rpc::FullDeps {
                 client: client.clone(),
                 pool: pool.clone(),
+                keystore: keystore.clone(),
+                command_sink: command_sink.clone(),
             };
             crate::rpc::create_full(deps).map_err(Into::into)
         })
@@ -254,43 +301,96 @@ pub fn new_full<
             telemetry.as_ref().map(|x| x.handle()),
         );
 
+        warn_on_foreign_aura_keys(&keystore_container.keystore());
+
         let slot_duration = spin_consensus::slot_duration(&*client)?;
 
-        let aura = spin_consensus::start_aura::<AuraPair, _, _, _, _, _, _, _, _, _, _>(
-            StartAuraParams {
-                slot_duration,
+        if let Some((sealing, rpc_commands)) = sealing {
+            let consensus_data_provider = SpinConsensusDataProvider::<_, _, AuraPair, _, _>::new(
+                client.clone(),
+                keystore_container.keystore(),
+                SessionForkChoice::new(client.clone()),
+            );
+            let cidp_client = client.clone();
+            let commands_stream = stream::select(
+                rpc_commands,
+                sealing_commands(sealing, transaction_pool.as_ref()),
+            );
+
+            let seal = sc_consensus_manual_seal::run_manual_seal(ManualSealParams {
+                block_import,
+                env: proposer_factory,
                 client,
+                pool: transaction_pool.clone(),
+                commands_stream,
                 select_chain,
-                block_import,
-                proposer_factory,
-                create_inherent_data_providers: move |_, ()| async move {
-                    let timestamp = sp_timestamp::InherentDataProvider::from_system_time();
-
-                    let slot =
-						spin_primitives::inherents::InherentDataProvider::from_timestamp_and_slot_duration(
-							*timestamp,
-							slot_duration,
-						);
-
-                    Ok((slot, timestamp))
+                consensus_data_provider: Some(Box::new(consensus_data_provider)),
+                create_inherent_data_providers: move |parent_hash, ()| {
+                    let cidp_client = cidp_client.clone();
+                    async move {
+                        let parent = cidp_client
+                            .header(parent_hash)?
+                            .ok_or_else(|| format!("Unknown parent block {parent_hash:?}"))?;
+
+                        let providers = spin_consensus::manual_seal::inherent_data_providers::<
+                            Block,
+                            AuraSignature,
+                        >(&parent, slot_duration)?;
+
+                        Ok(providers)
+                    }
                 },
-                force_authoring,
-                backoff_authoring_blocks,
-                keystore: keystore_container.keystore(),
-                sync_oracle: sync_service.clone(),
-                justification_sync_link: sync_service.clone(),
-                block_proposal_slot_portion: SlotProportion::new(2f32 / 3f32),
-                max_block_proposal_slot_portion: None,
-                telemetry: telemetry.as_ref().map(|x| x.handle()),
-                compatibility_mode: Default::default(),
-            },
-        )?;
-
-        // the AURA authoring task is considered essential, i.e. if it
-        // fails we take down the service with it.
-        task_manager
-            .spawn_essential_handle()
-            .spawn_blocking("aura", Some("block-authoring"), aura);
+            });
+
+            // sealing replaces the AURA authoring task, and is just as essential.
+            task_manager.spawn_essential_handle().spawn_blocking(
+                "manual-seal",
+                Some("block-authoring"),
+                seal,
+            );
+        } else {
+            let fork_choice = SessionForkChoice::new(client.clone());
+            let aura = spin_consensus::start_aura::<AuraPair, _, _, _, _, _, _, _, _, _, _, _>(
+                StartAuraParams {
+                    slot_duration,
+                    client,
+                    select_chain,
+                    block_import,
+                    proposer_factory,
+                    create_inherent_data_providers: move |_, ()| async move {
+                        let timestamp = sp_timestamp::InherentDataProvider::from_system_time();
+
+                        let slot =
+							spin_primitives::inherents::InherentDataProvider::from_timestamp_and_slot_duration(
+								*timestamp,
+								slot_duration,
+							);
+
+                        Ok((slot, timestamp))
+                    },
+                    force_authoring,
+                    backoff_authoring_blocks,
+                    keystore: keystore_container.keystore(),
+                    sync_oracle: sync_service.clone(),
+                    justification_sync_link: sync_service.clone(),
+                    block_proposal_slot_portion: SlotProportion::new(2f32 / 3f32),
+                    max_block_proposal_slot_portion: None,
+                    proposal_budget: Some(proposal_budget),
+                    telemetry: telemetry.as_ref().map(|x| x.handle()),
+                    compatibility_mode,
+                    prometheus_registry: prometheus_registry.clone(),
+                    fork_choice,
+                },
+            )?;
+
+            // the AURA authoring task is considered essential, i.e. if it
+            // fails we take down the service with it.
+            task_manager.spawn_essential_handle().spawn_blocking(
+                "aura",
+                Some("block-authoring"),
+                aura,
+            );
+        }
     }
 
     if enable_grandpa {
@@ -344,3 +444,50 @@ pub fn new_full<
 
     Ok(task_manager)
 }
+
+/// Commands sealing blocks in the given mode, on top of the best block.
+fn sealing_commands<P: TransactionPool>(
+    sealing: Sealing,
+    pool: &P,
+) -> Pin<Box<dyn Stream<Item = EngineCommand<Hash>> + Send>> {
+    let seal_new_block = |create_empty| EngineCommand::SealNewBlock {
+        create_empty,
+        finalize: false,
+        parent_hash: None,
+        sender: None,
+    };
+
+    match sealing {
+        Sealing::Instant => Box::pin(
+            pool.import_notification_stream()
+                .map(move |_| seal_new_block(false)),
+        ),
+        Sealing::Manual => Box::pin(stream::pending()),
+        Sealing::Interval(interval) => Box::pin(stream::unfold((), move |()| async move {
+            futures_timer::Delay::new(interval).await;
+            Some((seal_new_block(true), ()))
+        })),
+    }
+}
+
+/// Warn about Aura keys in the keystore the authoring worker won't use.
+///
+/// The worker only looks for keys of the crypto the node was built with, Ed25519 with the
+/// `ed25519-authorities` feature and Sr25519 otherwise.
+fn warn_on_foreign_aura_keys(keystore: &KeystorePtr) {
+    use sp_core::crypto::key_types::AURA;
+
+    #[cfg(feature = "ed25519-authorities")]
+    let (foreign, scheme, expected) = (keystore.sr25519_public_keys(AURA), "sr25519", "ed25519");
+    #[cfg(not(feature = "ed25519-authorities"))]
+    let (foreign, scheme, expected) = (keystore.ed25519_public_keys(AURA), "ed25519", "sr25519");
+
+    if !foreign.is_empty() {
+        log::warn!(
+            "Ignoring {} {} aura key(s) in the keystore, this node only uses {} aura keys",
+            foreign.len(),
+            scheme,
+            expected,
+        );
+    }
+}
@@ -27,6 +27,7 @@
 use alloc::vec::Vec;
 use frame_support::{
     genesis_builder_helper::{build_state, get_preset},
+    traits::Get,
     weights::Weight,
 };
 use pallet_grandpa::AuthorityId as GrandpaId;
@@ -38,12 +39,13 @@ use sp_runtime::{
     ApplyExtrinsicResult,
 };
 use sp_version::RuntimeVersion;
-use spin_primitives::{sr25519::AuthorityId as AuraId, AuraAuxData};
+use spin_primitives::AuraAuxData;
 
 // Local module imports
 use super::{
-    aura_session, AccountId, Aura, Balance, Block, Executive, Grandpa, InherentDataExt, Nonce,
-    Runtime, RuntimeCall, RuntimeGenesisConfig, SessionKeys, System, TransactionPayment, VERSION,
+    aura_equivocation, aura_session, session_performance, AccountId, Aura, AuraId, AuthorityKeys,
+    Balance, Block, BlockNumber, Executive, Grandpa, InherentDataExt, Nonce, Runtime, RuntimeCall,
+    RuntimeGenesisConfig, SessionKeys, System, TransactionPayment, VERSION,
 };
 
 impl_runtime_apis! {
@@ -112,6 +114,7 @@ impl_runtime_apis! {
         }
     }
 
+    #[api_version(9)]
     impl spin_primitives::AuraApi<Block, AuraId> for Runtime {
         fn slot_duration() -> spin_primitives::SlotDuration {
             spin_primitives::SlotDuration::from_millis(Aura::slot_duration())
@@ -127,6 +130,63 @@ impl_runtime_apis! {
         fn authorities() -> Vec<AuraId> {
             pallet_aura::Authorities::<Runtime>::get().into_inner()
         }
+
+        fn fallback_timeout() -> Option<u64> {
+            aura_session::Pallet::<Runtime>::fallback_timeout()
+        }
+
+        fn submit_report_equivocation_unsigned_extrinsic(
+            equivocation_proof: spin_primitives::EquivocationProof<
+                <Block as BlockT>::Header,
+                AuraId,
+            >,
+        ) -> Option<()> {
+            aura_equivocation::Pallet::<Runtime>::submit_unsigned_equivocation_report(
+                equivocation_proof,
+            )
+        }
+
+        fn disabled_authorities() -> Vec<spin_primitives::AuthorityIndex> {
+            aura_session::Pallet::<Runtime>::disabled_authorities()
+        }
+
+        fn session_length() -> BlockNumber {
+            aura_session::SessionLength::<Runtime>::get()
+        }
+
+        fn current_session_start() -> BlockNumber {
+            aura_session::Pallet::<Runtime>::current_session_start()
+        }
+
+        fn next_session_start() -> BlockNumber {
+            aura_session::Pallet::<Runtime>::next_session_start()
+        }
+
+        fn session_author(session_index: spin_primitives::SessionIndex) -> Option<AuraId> {
+            aura_session::Pallet::<Runtime>::author_of(session_index)
+        }
+
+        fn next_session_length() -> BlockNumber {
+            aura_session::Pallet::<Runtime>::next_session_length()
+        }
+
+        fn session_performance(
+            session_index: spin_primitives::SessionIndex,
+        ) -> Option<spin_primitives::SessionPerformance<AuraId>> {
+            session_performance::Pallet::<Runtime>::session_performance(session_index)
+        }
+
+        fn missed_sessions(authority: AuraId) -> u32 {
+            session_performance::Pallet::<Runtime>::missed_sessions(&authority)
+        }
+
+        fn session_mode() -> spin_primitives::SessionMode {
+            aura_session::Pallet::<Runtime>::session_mode()
+        }
+
+        fn fallback_offset() -> (spin_primitives::SessionIndex, u64) {
+            aura_session::FallbackOffset::<Runtime>::get()
+        }
     }
 
     impl sp_session::SessionKeys<Block> for Runtime {
@@ -151,23 +211,26 @@ impl_runtime_apis! {
         }
 
         fn submit_report_equivocation_unsigned_extrinsic(
-            _equivocation_proof: sp_consensus_grandpa::EquivocationProof<
+            equivocation_proof: sp_consensus_grandpa::EquivocationProof<
                 <Block as BlockT>::Hash,
                 NumberFor<Block>,
             >,
-            _key_owner_proof: sp_consensus_grandpa::OpaqueKeyOwnershipProof,
+            key_owner_proof: sp_consensus_grandpa::OpaqueKeyOwnershipProof,
         ) -> Option<()> {
-            None
+            let key_owner_proof = key_owner_proof.decode()?;
+
+            Grandpa::submit_unsigned_equivocation_report(equivocation_proof, key_owner_proof)
         }
 
         fn generate_key_ownership_proof(
-            _set_id: sp_consensus_grandpa::SetId,
-            _authority_id: GrandpaId,
+            set_id: sp_consensus_grandpa::SetId,
+            authority_id: GrandpaId,
         ) -> Option<sp_consensus_grandpa::OpaqueKeyOwnershipProof> {
-            // NOTE: this is the only implementation possible since we've
-            // defined our key owner proof type as a bottom type (i.e. a type
-            // with no values).
-            None
+            use codec::Encode;
+
+            AuthorityKeys::key_owner_proof(set_id, &authority_id)
+                .map(|p| p.encode())
+                .map(sp_consensus_grandpa::OpaqueKeyOwnershipProof::new)
         }
     }
 
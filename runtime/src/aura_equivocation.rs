@@ -0,0 +1,590 @@
+pub use pallet::*;
+
+#[frame_support::pallet]
+pub mod pallet {
+    use crate::aura_session;
+    use alloc::{boxed::Box, vec::Vec};
+    use codec::Encode;
+    use frame_support::{pallet_prelude::*, traits::DisabledValidators};
+    use frame_system::{
+        offchain::{CreateInherent, SubmitTransaction},
+        pallet_prelude::*,
+    };
+    use sp_runtime::{traits::Header as _, DigestItem, SaturatedConversion};
+    use spin_primitives::{
+        digests::PreDigest, AuthorityIndex, ConsensusLog, EquivocationProof, SessionIndex,
+        AURA_ENGINE_ID,
+    };
+
+    /// Equivocation proof against the author of a block of this runtime.
+    pub type EquivocationProofOf<T> =
+        EquivocationProof<HeaderFor<T>, <T as pallet_aura::Config>::AuthorityId>;
+
+    #[pallet::pallet]
+    pub struct Pallet<T>(_);
+
+    #[pallet::config]
+    pub trait Config:
+        frame_system::Config
+        + pallet_aura::Config
+        + aura_session::Config
+        + CreateInherent<Call<Self>>
+    {
+        type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
+    }
+
+    /// Authorities disabled for equivocating, along with the session they are disabled in.
+    ///
+    /// Entries recorded for an older session are stale and no longer apply.
+    #[pallet::storage]
+    pub type Disabled<T: Config> = StorageValue<
+        _,
+        (
+            SessionIndex,
+            BoundedVec<AuthorityIndex, <T as pallet_aura::Config>::MaxAuthorities>,
+        ),
+        ValueQuery,
+    >;
+
+    #[pallet::event]
+    #[pallet::generate_deposit(pub(super) fn deposit_event)]
+    pub enum Event<T: Config> {
+        /// An author of the current session equivocated and is disabled for the rest of it.
+        AuthorityDisabled {
+            authority_index: AuthorityIndex,
+            session_index: SessionIndex,
+        },
+    }
+
+    #[pallet::error]
+    pub enum Error<T> {
+        /// The equivocation proof is invalid.
+        InvalidEquivocationProof,
+        /// The offender may not author at the slot of the proof in the current session.
+        NotSessionAuthor,
+        /// The offender is already disabled for the current session.
+        DuplicateOffenceReport,
+    }
+
+    #[pallet::call]
+    impl<T: Config> Pallet<T> {
+        /// Report an equivocation of an author of the current session.
+        ///
+        /// This extrinsic must be called unsigned and it is expected that only block authors
+        /// will call it (validated in `ValidateUnsigned`). The offender is disabled until the
+        /// end of the session.
+        #[pallet::call_index(0)]
+        #[pallet::weight(
+            // two signature verifications on top of the storage access
+            T::DbWeight::get().reads_writes(6, 1).saturating_add(Weight::from_parts(100_000_000, 0))
+        )]
+        pub fn report_equivocation_unsigned(
+            origin: OriginFor<T>,
+            equivocation_proof: Box<EquivocationProofOf<T>>,
+        ) -> DispatchResult {
+            ensure_none(origin)?;
+
+            let authority_index = Self::check_report(&equivocation_proof)?;
+            Self::disable(authority_index);
+
+            Ok(())
+        }
+    }
+
+    #[pallet::validate_unsigned]
+    impl<T: Config> ValidateUnsigned for Pallet<T> {
+        type Call = Call<T>;
+
+        fn validate_unsigned(source: TransactionSource, call: &Self::Call) -> TransactionValidity {
+            let Call::report_equivocation_unsigned { equivocation_proof } = call else {
+                return InvalidTransaction::Call.into();
+            };
+
+            // discard equivocation report not coming from the local node
+            match source {
+                TransactionSource::Local | TransactionSource::InBlock => {}
+                _ => return InvalidTransaction::Call.into(),
+            }
+
+            let authority_index =
+                Self::check_report(equivocation_proof).map_err(|_| InvalidTransaction::BadProof)?;
+
+            ValidTransaction::with_tag_prefix("AuraEquivocation")
+                // We assign the maximum priority for any equivocation report.
+                .priority(TransactionPriority::MAX)
+                // Only one report for the same offender in the same session.
+                .and_provides((authority_index, aura_session::SessionIndex::<T>::get()))
+                // The report is only useful until the end of the session.
+                .longevity(aura_session::SessionLength::<T>::get().saturated_into::<u64>())
+                // We don't propagate this. This can never be included on a remote node.
+                .propagate(false)
+                .build()
+        }
+
+        fn pre_dispatch(call: &Self::Call) -> Result<(), TransactionValidityError> {
+            let Call::report_equivocation_unsigned { equivocation_proof } = call else {
+                return Err(InvalidTransaction::Call.into());
+            };
+
+            Self::check_report(equivocation_proof)
+                .map(|_| ())
+                .map_err(|_| InvalidTransaction::BadProof.into())
+        }
+    }
+
+    impl<T: Config> Pallet<T> {
+        /// Whether the authority with the given index is disabled in the current session.
+        pub fn is_disabled(authority_index: AuthorityIndex) -> bool {
+            let (session_index, disabled) = Disabled::<T>::get();
+            session_index == aura_session::SessionIndex::<T>::get()
+                && disabled.contains(&authority_index)
+        }
+
+        /// Submits an unsigned extrinsic reporting the given equivocation.
+        ///
+        /// Meant to be called from the runtime API, within an offchain context.
+        pub fn submit_unsigned_equivocation_report(
+            equivocation_proof: EquivocationProofOf<T>,
+        ) -> Option<()> {
+            let call = Call::report_equivocation_unsigned {
+                equivocation_proof: Box::new(equivocation_proof),
+            };
+            let xt = T::create_inherent(call.into());
+
+            SubmitTransaction::<T, Call<T>>::submit_transaction(xt).ok()
+        }
+
+        /// Check the proof and return the index of the offending author.
+        fn check_report(
+            equivocation_proof: &EquivocationProofOf<T>,
+        ) -> Result<AuthorityIndex, Error<T>> {
+            let offender_index = pallet_aura::Authorities::<T>::get()
+                .iter()
+                .position(|authority| *authority == equivocation_proof.offender)
+                .ok_or(Error::<T>::NotSessionAuthor)?
+                as AuthorityIndex;
+
+            ensure!(
+                !Self::is_disabled(offender_index),
+                Error::<T>::DuplicateOffenceReport
+            );
+            ensure!(
+                Self::is_slot_author(equivocation_proof, offender_index),
+                Error::<T>::NotSessionAuthor
+            );
+            ensure!(
+                spin_primitives::check_equivocation_proof(equivocation_proof.clone()),
+                Error::<T>::InvalidEquivocationProof
+            );
+
+            Ok(offender_index)
+        }
+
+        /// Whether the offender may author at the slot of the proof, in the current session.
+        ///
+        /// The session follows from the slot and the session claimed by the pre-digest, if any.
+        /// With a fallback timeout, any enabled authority may end up authoring at the slot.
+        fn is_slot_author(
+            equivocation_proof: &EquivocationProofOf<T>,
+            offender_index: AuthorityIndex,
+        ) -> bool {
+            let slot = equivocation_proof.slot;
+            let parent_session_index = equivocation_proof
+                .first_header
+                .digest()
+                .logs()
+                .iter()
+                .find_map(|log| log.pre_runtime_try_to::<PreDigest>(&AURA_ENGINE_ID))
+                .and_then(|pre_digest| pre_digest.session_and_author())
+                .map(|(session_index, _)| session_index);
+            let session_index = match parent_session_index {
+                Some(parent) => {
+                    aura_session::Pallet::<T>::session_mode().session_index(slot, parent)
+                }
+                None => aura_session::Pallet::<T>::session_index_at(slot),
+            };
+            // offenders are disabled for the rest of the current session only.
+            if session_index != aura_session::SessionIndex::<T>::get() {
+                return false;
+            }
+
+            let authorities_len = pallet_aura::Authorities::<T>::decode_len().unwrap_or(0);
+            let offsets = match aura_session::Pallet::<T>::fallback_timeout() {
+                Some(timeout) if timeout > 0 => authorities_len as u64,
+                _ => 1,
+            };
+            let disabled = aura_session::Pallet::<T>::disabled_authorities();
+            (0..offsets).any(|offset| {
+                spin_primitives::session_author_index(
+                    session_index,
+                    offset,
+                    authorities_len,
+                    &disabled,
+                ) == Some(offender_index)
+            })
+        }
+
+        fn disable(authority_index: AuthorityIndex) {
+            let current_session = aura_session::SessionIndex::<T>::get();
+
+            Disabled::<T>::mutate(|(session_index, disabled)| {
+                if *session_index != current_session {
+                    *session_index = current_session;
+                    disabled.clear();
+                }
+                // authority indices are bounded by `MaxAuthorities`, so this never overflows.
+                let _ = disabled.try_push(authority_index);
+            });
+
+            let log = ConsensusLog::<T::AuthorityId>::OnDisabled(authority_index);
+            frame_system::Pallet::<T>::deposit_log(DigestItem::Consensus(
+                AURA_ENGINE_ID,
+                log.encode(),
+            ));
+
+            Self::deposit_event(Event::AuthorityDisabled {
+                authority_index,
+                session_index: current_session,
+            });
+        }
+    }
+
+    impl<T: Config> DisabledValidators for Pallet<T> {
+        fn is_disabled(index: u32) -> bool {
+            Self::is_disabled(index)
+        }
+
+        fn disabled_validators() -> Vec<u32> {
+            let (session_index, disabled) = Disabled::<T>::get();
+            if session_index != aura_session::SessionIndex::<T>::get() {
+                return Vec::new();
+            }
+            disabled.into_inner()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aura_session;
+    use codec::Encode;
+    use frame_support::{assert_noop, assert_ok, derive_impl, parameter_types};
+    use sp_core::{sr25519, ConstBool, ConstU32, ConstU64, Pair, H256};
+    use sp_keyring::Sr25519Keyring;
+    use sp_runtime::{
+        traits::{Header as _, ValidateUnsigned},
+        transaction_validity::TransactionSource,
+        BuildStorage, DigestItem,
+    };
+    use spin_primitives::{
+        digests::{CompatibleDigestItem, PreDigest},
+        sr25519::{AuthorityId, AuthoritySignature},
+        AuthorityIndex, EquivocationProof, SessionIndex, SessionMode,
+    };
+
+    type Block = frame_system::mocking::MockBlock<Test>;
+    type Header = frame_system::pallet_prelude::HeaderFor<Test>;
+
+    frame_support::construct_runtime!(
+        pub struct Test {
+            System: frame_system,
+            Timestamp: pallet_timestamp,
+            Aura: pallet_aura,
+            AuraSession: aura_session,
+            AuraEquivocation: pallet,
+        }
+    );
+
+    #[derive_impl(frame_system::config_preludes::TestDefaultConfig)]
+    impl frame_system::Config for Test {
+        type Block = Block;
+    }
+
+    impl pallet_timestamp::Config for Test {
+        type Moment = u64;
+        type OnTimestampSet = Aura;
+        type MinimumPeriod = ConstU64<1000>;
+        type WeightInfo = ();
+    }
+
+    impl pallet_aura::Config for Test {
+        type AuthorityId = AuthorityId;
+        type DisabledValidators = ();
+        type MaxAuthorities = ConstU32<10>;
+        type AllowMultipleBlocksPerSlot = ConstBool<false>;
+        type SlotDuration = pallet_aura::MinimumPeriodTimesTwo<Test>;
+    }
+
+    parameter_types! {
+        pub const FallbackTimeout: Option<u64> = None;
+    }
+
+    impl aura_session::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type FallbackTimeout = FallbackTimeout;
+        type SessionMode = ();
+        type DisableOrigin = frame_system::EnsureRoot<u64>;
+        type SessionLengthOrigin = frame_system::EnsureRoot<u64>;
+        type DisabledValidators = AuraEquivocation;
+        type OnNewSession = ();
+    }
+
+    impl pallet::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+    }
+
+    impl<C> frame_system::offchain::CreateTransactionBase<C> for Test
+    where
+        RuntimeCall: From<C>,
+    {
+        type Extrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+        type RuntimeCall = RuntimeCall;
+    }
+
+    impl<C> frame_system::offchain::CreateInherent<C> for Test
+    where
+        RuntimeCall: From<C>,
+    {
+        fn create_inherent(call: RuntimeCall) -> Self::Extrinsic {
+            frame_system::mocking::MockUncheckedExtrinsic::<Test>::new_bare(call)
+        }
+    }
+
+    const AUTHORITIES: [Sr25519Keyring; 3] = [
+        Sr25519Keyring::Alice,
+        Sr25519Keyring::Bob,
+        Sr25519Keyring::Charlie,
+    ];
+
+    fn build_ext() -> sp_io::TestExternalities {
+        let mut storage = frame_system::GenesisConfig::<Test>::default()
+            .build_storage()
+            .unwrap();
+        pallet_aura::GenesisConfig::<Test> {
+            authorities: AUTHORITIES.iter().map(|k| k.public().into()).collect(),
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        aura_session::GenesisConfig::<Test> { session_length: 3 }
+            .assimilate_storage(&mut storage)
+            .unwrap();
+
+        let mut ext: sp_io::TestExternalities = storage.into();
+        ext.execute_with(|| System::set_block_number(1));
+        ext
+    }
+
+    fn sealed_header(pair: &sr25519::Pair, slot: u64, state_root: u8) -> Header {
+        sealed_header_with(pair, PreDigest::V0(slot.into()), state_root)
+    }
+
+    fn sealed_header_with(pair: &sr25519::Pair, pre_digest: PreDigest, state_root: u8) -> Header {
+        let mut header = Header::new(
+            1,
+            Default::default(),
+            H256::repeat_byte(state_root),
+            Default::default(),
+            Default::default(),
+        );
+        header.digest_mut().push(
+            <DigestItem as CompatibleDigestItem<AuthoritySignature>>::aura_versioned_pre_digest(
+                pre_digest,
+            ),
+        );
+
+        let signature: AuthoritySignature = pair.sign(header.hash().as_ref()).into();
+        header
+            .digest_mut()
+            .push(<DigestItem as CompatibleDigestItem<AuthoritySignature>>::aura_seal(signature));
+
+        header
+    }
+
+    fn equivocation_proof(offender: Sr25519Keyring, slot: u64) -> EquivocationProofOf<Test> {
+        let pair = offender.pair();
+        EquivocationProof {
+            offender: offender.public().into(),
+            slot: slot.into(),
+            first_header: sealed_header(&pair, slot, 1),
+            second_header: sealed_header(&pair, slot, 2),
+        }
+    }
+
+    /// An equivocation at `slot` in a block claiming the session `session_index` of its parent.
+    fn equivocation_proof_in(
+        offender: Sr25519Keyring,
+        slot: u64,
+        session_index: SessionIndex,
+    ) -> EquivocationProofOf<Test> {
+        let pair = offender.pair();
+        let authority_index = AUTHORITIES.iter().position(|k| *k == offender).unwrap();
+        let header = |state_root| {
+            let pre_digest = PreDigest::V1 {
+                slot: slot.into(),
+                session_index,
+                authority_index: authority_index as AuthorityIndex,
+            };
+            sealed_header_with(&pair, pre_digest, state_root)
+        };
+        EquivocationProof {
+            offender: offender.public().into(),
+            slot: slot.into(),
+            first_header: header(1),
+            second_header: header(2),
+        }
+    }
+
+    #[test]
+    fn report_disables_session_author_for_the_session() {
+        build_ext().execute_with(|| {
+            aura_session::SessionIndex::<Test>::put(1);
+            let proof = equivocation_proof(Sr25519Keyring::Bob, 5);
+
+            assert!(Pallet::<Test>::validate_unsigned(
+                TransactionSource::Local,
+                &Call::report_equivocation_unsigned {
+                    equivocation_proof: Box::new(proof.clone()),
+                },
+            )
+            .is_ok());
+            assert_ok!(AuraEquivocation::report_equivocation_unsigned(
+                RuntimeOrigin::none(),
+                Box::new(proof.clone()),
+            ));
+            System::assert_last_event(
+                Event::AuthorityDisabled {
+                    authority_index: 1,
+                    session_index: 1,
+                }
+                .into(),
+            );
+            assert!(Pallet::<Test>::is_disabled(1));
+            assert_eq!(
+                aura_session::Pallet::<Test>::session_author_index(),
+                Some(2)
+            );
+            assert!(System::digest().logs().contains(&DigestItem::Consensus(
+                spin_primitives::AURA_ENGINE_ID,
+                spin_primitives::ConsensusLog::<AuthorityId>::OnDisabled(1).encode(),
+            )));
+
+            // the same offence can't be reported twice in a session.
+            assert_noop!(
+                AuraEquivocation::report_equivocation_unsigned(
+                    RuntimeOrigin::none(),
+                    Box::new(proof),
+                ),
+                Error::<Test>::DuplicateOffenceReport,
+            );
+
+            // disabling lapses with the session.
+            aura_session::SessionIndex::<Test>::put(2);
+            assert!(!Pallet::<Test>::is_disabled(1));
+        });
+    }
+
+    #[test]
+    fn invalid_reports_are_rejected() {
+        build_ext().execute_with(|| {
+            aura_session::SessionIndex::<Test>::put(1);
+
+            // only the session author can be reported.
+            assert_noop!(
+                AuraEquivocation::report_equivocation_unsigned(
+                    RuntimeOrigin::none(),
+                    Box::new(equivocation_proof(Sr25519Keyring::Alice, 5)),
+                ),
+                Error::<Test>::NotSessionAuthor,
+            );
+
+            // both headers must be for the slot of the proof.
+            let mut proof = equivocation_proof(Sr25519Keyring::Bob, 5);
+            proof.second_header = sealed_header(&Sr25519Keyring::Bob.pair(), 6, 2);
+            assert_noop!(
+                AuraEquivocation::report_equivocation_unsigned(
+                    RuntimeOrigin::none(),
+                    Box::new(proof),
+                ),
+                Error::<Test>::InvalidEquivocationProof,
+            );
+
+            // and must be signed by the offender.
+            let mut proof = equivocation_proof(Sr25519Keyring::Bob, 5);
+            proof.second_header = sealed_header(&Sr25519Keyring::Charlie.pair(), 5, 2);
+            assert_noop!(
+                AuraEquivocation::report_equivocation_unsigned(
+                    RuntimeOrigin::none(),
+                    Box::new(proof),
+                ),
+                Error::<Test>::InvalidEquivocationProof,
+            );
+
+            // nor an equivocation in another session.
+            assert_noop!(
+                AuraEquivocation::report_equivocation_unsigned(
+                    RuntimeOrigin::none(),
+                    Box::new(equivocation_proof_in(Sr25519Keyring::Bob, 5, 0)),
+                ),
+                Error::<Test>::NotSessionAuthor,
+            );
+
+            // reports from the network are not accepted into the pool.
+            assert!(Pallet::<Test>::validate_unsigned(
+                TransactionSource::External,
+                &Call::report_equivocation_unsigned {
+                    equivocation_proof: Box::new(equivocation_proof(Sr25519Keyring::Bob, 5)),
+                },
+            )
+            .is_err());
+        });
+    }
+
+    #[test]
+    fn fallback_authors_can_be_reported() {
+        build_ext().execute_with(|| {
+            aura_session::SessionIndex::<Test>::put(1);
+            let proof = equivocation_proof_in(Sr25519Keyring::Charlie, 5, 1);
+
+            // Charlie only authors in session 1 once Bob times out.
+            assert_noop!(
+                AuraEquivocation::report_equivocation_unsigned(
+                    RuntimeOrigin::none(),
+                    Box::new(proof.clone()),
+                ),
+                Error::<Test>::NotSessionAuthor,
+            );
+
+            aura_session::CurrentFallbackTimeout::<Test>::put(Some(2));
+            assert_ok!(AuraEquivocation::report_equivocation_unsigned(
+                RuntimeOrigin::none(),
+                Box::new(proof),
+            ));
+            assert!(Pallet::<Test>::is_disabled(2));
+        });
+    }
+
+    #[test]
+    fn reports_follow_the_session_of_the_slot() {
+        build_ext().execute_with(|| {
+            aura_session::CurrentSessionMode::<Test>::put(SessionMode::Slots(10));
+            aura_session::SessionIndex::<Test>::put(1);
+
+            // the block at slot 20 starts session 2, authored by Charlie.
+            assert_noop!(
+                AuraEquivocation::report_equivocation_unsigned(
+                    RuntimeOrigin::none(),
+                    Box::new(equivocation_proof_in(Sr25519Keyring::Charlie, 20, 1)),
+                ),
+                Error::<Test>::NotSessionAuthor,
+            );
+
+            aura_session::SessionIndex::<Test>::put(2);
+            assert_ok!(AuraEquivocation::report_equivocation_unsigned(
+                RuntimeOrigin::none(),
+                Box::new(equivocation_proof_in(Sr25519Keyring::Charlie, 20, 1)),
+            ));
+            assert!(Pallet::<Test>::is_disabled(2));
+        });
+    }
+}
@@ -1,31 +1,575 @@
 pub use pallet::*;
 
+use frame_support::{
+    traits::{Get, OnRuntimeUpgrade},
+    weights::Weight,
+};
+use frame_system::pallet_prelude::BlockNumberFor;
+use sp_runtime::traits::{One, Saturating, Zero};
+
+/// Handler for the start of a session.
+pub trait OnNewSession {
+    /// Called when the session with the given index starts, before it is announced to the client.
+    ///
+    /// Changes to the Aura authorities made here apply from the next block on.
+    fn on_new_session(session_index: spin_primitives::SessionIndex) -> Weight;
+}
+
+impl OnNewSession for () {
+    fn on_new_session(_: spin_primitives::SessionIndex) -> Weight {
+        Weight::zero()
+    }
+}
+
+/// Calls both handlers, in order.
+impl<A: OnNewSession, B: OnNewSession> OnNewSession for (A, B) {
+    fn on_new_session(session_index: spin_primitives::SessionIndex) -> Weight {
+        A::on_new_session(session_index).saturating_add(B::on_new_session(session_index))
+    }
+}
+
+/// Seed the session length storage of chains launched with a fixed session length `L`.
+///
+/// Sessions then started at the multiples of `L`, the current one keeps its boundary.
+pub struct SeedSessionLength<T, L>(core::marker::PhantomData<(T, L)>);
+
+impl<T: Config, L: Get<BlockNumberFor<T>>> OnRuntimeUpgrade for SeedSessionLength<T, L> {
+    fn on_runtime_upgrade() -> Weight {
+        if !SessionLength::<T>::get().is_zero() {
+            return T::DbWeight::get().reads(1);
+        }
+
+        // sessions started in `on_initialize`, which is yet to run for the current block.
+        let length = L::get();
+        let parent = frame_system::Pallet::<T>::block_number().saturating_sub(One::one());
+        SessionLength::<T>::put(length);
+        CurrentSessionStart::<T>::put(parent - parent % length);
+
+        T::DbWeight::get().reads_writes(2, 2)
+    }
+}
+
+/// Seed the parent slot of chains upgraded from a runtime that didn't note it.
+pub struct SeedParentSlot<T>(core::marker::PhantomData<T>);
+
+impl<T: Config> OnRuntimeUpgrade for SeedParentSlot<T> {
+    fn on_runtime_upgrade() -> Weight {
+        if ParentSlot::<T>::exists() {
+            return T::DbWeight::get().reads(1);
+        }
+
+        // `pallet_aura` is yet to move on to the slot of the current block.
+        ParentSlot::<T>::put(pallet_aura::CurrentSlot::<T>::get());
+
+        T::DbWeight::get().reads_writes(2, 1)
+    }
+}
+
 #[frame_support::pallet]
 pub mod pallet {
-    use frame_support::pallet_prelude::*;
-    use frame_system::pallet_prelude::BlockNumberFor;
+    use super::OnNewSession;
+    use alloc::vec::Vec;
+    use codec::{Decode, Encode};
+    use frame_support::{
+        pallet_prelude::*,
+        traits::{DisabledValidators, FindAuthor},
+    };
+    use frame_system::pallet_prelude::*;
+    use sp_runtime::{ConsensusEngineId, DigestItem, SaturatedConversion};
+    use spin_primitives::{
+        digests::PreDigest, AuthorityIndex, ConsensusLog, Fallback, SessionMode, Slot,
+        AURA_ENGINE_ID,
+    };
 
     #[pallet::pallet]
     pub struct Pallet<T>(_);
 
     #[pallet::config]
-    pub trait Config: frame_system::Config {
-        type SessionLength: Get<BlockNumberFor<Self>>;
+    pub trait Config: frame_system::Config + pallet_aura::Config {
+        type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
+
+        /// Number of consecutive empty slots after which the next authority in the ring may
+        /// author blocks of the current session. `None` disables the fallback.
+        ///
+        /// Changes take effect at the next session boundary.
+        type FallbackTimeout: Get<Option<u64>>;
+
+        /// Whether sessions move on every [`SessionLength`] blocks, or with the slots whether
+        /// blocks are produced or not.
+        ///
+        /// Changes take effect at the next session boundary.
+        type SessionMode: Get<SessionMode>;
+
+        /// Origin allowed to take authorities out of the rotation and back in.
+        type DisableOrigin: EnsureOrigin<Self::RuntimeOrigin>;
+
+        /// Origin allowed to change the session length.
+        type SessionLengthOrigin: EnsureOrigin<Self::RuntimeOrigin>;
+
+        /// Further source of authorities disabled for the current session, e.g. for misbehaving.
+        type DisabledValidators: DisabledValidators;
+
+        /// Handler for the start of a session, e.g. to change the authorities.
+        type OnNewSession: OnNewSession;
     }
 
     #[pallet::storage]
     pub type SessionIndex<T: Config> = StorageValue<_, spin_primitives::SessionIndex, ValueQuery>;
 
+    /// Number of blocks in the current session.
+    ///
+    /// Only used with [`SessionMode::Blocks`].
+    #[pallet::storage]
+    pub type SessionLength<T: Config> = StorageValue<_, BlockNumberFor<T>, ValueQuery>;
+
+    /// Session length scheduled to take effect at the next session boundary.
+    #[pallet::storage]
+    pub type NextSessionLength<T: Config> = StorageValue<_, BlockNumberFor<T>, OptionQuery>;
+
+    /// Number of the block the current session started at.
+    #[pallet::storage]
+    pub type CurrentSessionStart<T: Config> = StorageValue<_, BlockNumberFor<T>, ValueQuery>;
+
+    /// The fallback timeout of the current session, see [`Pallet::fallback_timeout`].
+    ///
+    /// Taken from [`Config::FallbackTimeout`] at the start of every session, and announced to the
+    /// client when it changes. Unset until the first session boundary after an upgrade.
+    #[pallet::storage]
+    pub type CurrentFallbackTimeout<T: Config> = StorageValue<_, Option<u64>, OptionQuery>;
+
+    /// The session mode of the current session, see [`Pallet::session_mode`].
+    ///
+    /// Taken from [`Config::SessionMode`] at the start of every session, and announced to the
+    /// client when it changes. Unset until the first session boundary after an upgrade.
+    #[pallet::storage]
+    pub type CurrentSessionMode<T: Config> = StorageValue<_, SessionMode, OptionQuery>;
+
+    /// Slot of the parent block, `None` for children of the genesis block.
+    #[pallet::storage]
+    pub type ParentSlot<T: Config> = StorageValue<_, Slot, OptionQuery>;
+
+    /// Session the current block was authored in, along with the fallback offset of its author.
+    ///
+    /// A fallback author keeps the slots for the rest of the session, see [`Fallback::new`].
+    #[pallet::storage]
+    pub type FallbackOffset<T: Config> =
+        StorageValue<_, (spin_primitives::SessionIndex, u64), ValueQuery>;
+
+    /// The author of the current block, as found from its pre-digest, along with the session it
+    /// authored in.
+    ///
+    /// Authorship follows the session state of the parent, so with [`SessionMode::Blocks`] the
+    /// first block of a session is authored in the previous one.
+    #[pallet::storage]
+    pub type Author<T: Config> =
+        StorageValue<_, (spin_primitives::SessionIndex, T::AuthorityId), OptionQuery>;
+
+    #[pallet::genesis_config]
+    pub struct GenesisConfig<T: Config> {
+        /// Number of blocks in a session.
+        pub session_length: BlockNumberFor<T>,
+    }
+
+    impl<T: Config> Default for GenesisConfig<T> {
+        fn default() -> Self {
+            Self {
+                session_length: crate::MINUTES.into(),
+            }
+        }
+    }
+
+    #[pallet::genesis_build]
+    impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
+        fn build(&self) {
+            assert!(
+                !self.session_length.is_zero(),
+                "Session length must be greater than zero"
+            );
+            SessionLength::<T>::put(self.session_length);
+            CurrentFallbackTimeout::<T>::put(T::FallbackTimeout::get());
+            CurrentSessionMode::<T>::put(T::SessionMode::get());
+        }
+    }
+
+    /// Authorities taken out of the rotation until enabled again, sorted.
+    #[pallet::storage]
+    pub type DisabledAuthorities<T: Config> = StorageValue<
+        _,
+        BoundedVec<AuthorityIndex, <T as pallet_aura::Config>::MaxAuthorities>,
+        ValueQuery,
+    >;
+
+    #[pallet::event]
+    #[pallet::generate_deposit(pub(super) fn deposit_event)]
+    pub enum Event<T: Config> {
+        /// An authority was taken out of the rotation.
+        AuthorityDisabled { authority_index: AuthorityIndex },
+        /// An authority was put back into the rotation.
+        AuthorityEnabled { authority_index: AuthorityIndex },
+        /// A new session length was scheduled for the next session boundary.
+        SessionLengthScheduled { session_length: BlockNumberFor<T> },
+        /// A new session length took effect.
+        SessionLengthChanged { session_length: BlockNumberFor<T> },
+    }
+
+    #[pallet::error]
+    pub enum Error<T> {
+        /// There is no authority with the given index.
+        UnknownAuthority,
+        /// The authority is already disabled.
+        AlreadyDisabled,
+        /// The authority is not disabled.
+        NotDisabled,
+        /// Disabling the authority would leave nobody to author blocks.
+        NoEnabledAuthority,
+        /// Sessions must be at least one block long.
+        ZeroSessionLength,
+    }
+
     #[pallet::hooks]
     impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
         fn on_initialize(n: BlockNumberFor<T>) -> Weight {
-            if n % T::SessionLength::get() == Zero::zero() {
-                // increment session index
-                SessionIndex::<T>::mutate(|idx| *idx += 1);
-                return T::DbWeight::get().reads_writes(1, 1);
+            // before the session moves on, as the author followed the state of the parent.
+            let digest = frame_system::Pallet::<T>::digest();
+            let pre_runtime_digests = digest.logs().iter().filter_map(|d| d.as_pre_runtime());
+            let author = <Self as FindAuthor<AuthorityIndex>>::find_author(pre_runtime_digests)
+                .and_then(|index| {
+                    pallet_aura::Authorities::<T>::get()
+                        .get(index as usize)
+                        .cloned()
+                });
+            let slot = pallet_aura::CurrentSlot::<T>::get();
+            let session_index = Self::session_index_at(slot);
+            Author::<T>::set(author.map(|author| (session_index, author)));
+            FallbackOffset::<T>::put((session_index, Self::fallback_offset_at(slot)));
+            // including the parent slot noted in `on_finalize`.
+            let author_weight = T::DbWeight::get().reads_writes(11, 3);
+
+            if let Some(session_index) = Self::new_session_at(n, slot) {
+                CurrentSessionStart::<T>::put(n);
+                let length_change = NextSessionLength::<T>::take();
+                if let Some(session_length) = length_change {
+                    SessionLength::<T>::put(session_length);
+                    Self::deposit_event(Event::SessionLengthChanged { session_length });
+                }
+
+                SessionIndex::<T>::put(session_index);
+
+                // authority changes come first, they reset the client's disabled set.
+                let handler_weight = T::OnNewSession::on_new_session(session_index);
+
+                // let the client follow the session without reading our state
+                let log = ConsensusLog::<T::AuthorityId>::NewSession(session_index);
+                frame_system::Pallet::<T>::deposit_log(DigestItem::Consensus(
+                    AURA_ENGINE_ID,
+                    log.encode(),
+                ));
+                if let Some(session_length) = length_change {
+                    let log = ConsensusLog::<T::AuthorityId>::SessionLengthChange(
+                        session_length.saturated_into(),
+                    );
+                    frame_system::Pallet::<T>::deposit_log(DigestItem::Consensus(
+                        AURA_ENGINE_ID,
+                        log.encode(),
+                    ));
+                }
+                let fallback_timeout = T::FallbackTimeout::get();
+                if CurrentFallbackTimeout::<T>::get() != Some(fallback_timeout) {
+                    CurrentFallbackTimeout::<T>::put(fallback_timeout);
+                    let log =
+                        ConsensusLog::<T::AuthorityId>::FallbackTimeoutChange(fallback_timeout);
+                    frame_system::Pallet::<T>::deposit_log(DigestItem::Consensus(
+                        AURA_ENGINE_ID,
+                        log.encode(),
+                    ));
+                }
+                let session_mode = T::SessionMode::get();
+                if CurrentSessionMode::<T>::get() != Some(session_mode) {
+                    CurrentSessionMode::<T>::put(session_mode);
+                    let log = ConsensusLog::<T::AuthorityId>::SessionModeChange(session_mode);
+                    frame_system::Pallet::<T>::deposit_log(DigestItem::Consensus(
+                        AURA_ENGINE_ID,
+                        log.encode(),
+                    ));
+                }
+
+                // a new session enables everybody on the client side, so announce again who
+                // stays out of the rotation.
+                if !DisabledAuthorities::<T>::get().is_empty() {
+                    Self::deposit_disabled_log();
+                }
+
+                return T::DbWeight::get()
+                    .reads_writes(7, 7)
+                    .saturating_add(author_weight)
+                    .saturating_add(handler_weight);
             }
 
-            Weight::zero()
+            T::DbWeight::get().reads(3).saturating_add(author_weight)
+        }
+
+        fn integrity_test() {
+            assert!(
+                T::SessionMode::get() != SessionMode::Slots(0),
+                "Sessions must be at least one slot long"
+            );
+        }
+
+        fn on_finalize(_: BlockNumberFor<T>) {
+            ParentSlot::<T>::put(pallet_aura::CurrentSlot::<T>::get());
+        }
+    }
+
+    #[pallet::call]
+    impl<T: Config> Pallet<T> {
+        /// Take the authority with the given index out of the rotation.
+        ///
+        /// Its sessions are authored by the next enabled authority in the ring until it is
+        /// enabled again.
+        #[pallet::call_index(0)]
+        #[pallet::weight(T::DbWeight::get().reads_writes(3, 1))]
+        pub fn disable_authority(
+            origin: OriginFor<T>,
+            authority_index: AuthorityIndex,
+        ) -> DispatchResult {
+            T::DisableOrigin::ensure_origin(origin)?;
+
+            Self::disable(authority_index)
+        }
+
+        /// Put the authority with the given index back into the rotation.
+        #[pallet::call_index(1)]
+        #[pallet::weight(T::DbWeight::get().reads_writes(2, 1))]
+        pub fn enable_authority(
+            origin: OriginFor<T>,
+            authority_index: AuthorityIndex,
+        ) -> DispatchResult {
+            T::DisableOrigin::ensure_origin(origin)?;
+
+            DisabledAuthorities::<T>::try_mutate(|disabled| {
+                let position = disabled
+                    .iter()
+                    .position(|idx| *idx == authority_index)
+                    .ok_or(Error::<T>::NotDisabled)?;
+                disabled.remove(position);
+                Ok::<_, Error<T>>(())
+            })?;
+
+            Self::deposit_disabled_log();
+            Self::deposit_event(Event::AuthorityEnabled { authority_index });
+
+            Ok(())
+        }
+
+        /// Schedule a new session length, taking effect at the next session boundary.
+        ///
+        /// The current session keeps its length, so the boundary authors rely on doesn't move.
+        #[pallet::call_index(2)]
+        #[pallet::weight(T::DbWeight::get().writes(1))]
+        pub fn set_session_length(
+            origin: OriginFor<T>,
+            session_length: BlockNumberFor<T>,
+        ) -> DispatchResult {
+            T::SessionLengthOrigin::ensure_origin(origin)?;
+            ensure!(!session_length.is_zero(), Error::<T>::ZeroSessionLength);
+
+            NextSessionLength::<T>::put(session_length);
+            Self::deposit_event(Event::SessionLengthScheduled { session_length });
+
+            Ok(())
+        }
+    }
+
+    impl<T: Config> Pallet<T> {
+        /// Take the authority with the given index out of the rotation until enabled again.
+        ///
+        /// At least one authority stays enabled.
+        pub fn disable(authority_index: AuthorityIndex) -> DispatchResult {
+            let authorities_len = pallet_aura::Authorities::<T>::decode_len().unwrap_or(0);
+            ensure!(
+                (authority_index as usize) < authorities_len,
+                Error::<T>::UnknownAuthority
+            );
+
+            let mut disabled = DisabledAuthorities::<T>::get();
+            let position = disabled
+                .binary_search(&authority_index)
+                .err()
+                .ok_or(Error::<T>::AlreadyDisabled)?;
+
+            let mut all_disabled = Self::disabled_authorities();
+            all_disabled.push(authority_index);
+            all_disabled.sort();
+            all_disabled.dedup();
+            ensure!(
+                all_disabled.len() < authorities_len,
+                Error::<T>::NoEnabledAuthority
+            );
+
+            // indices are bounded by the number of authorities, so this never overflows.
+            disabled
+                .try_insert(position, authority_index)
+                .map_err(|_| Error::<T>::UnknownAuthority)?;
+            DisabledAuthorities::<T>::put(disabled);
+
+            Self::deposit_disabled_log();
+            Self::deposit_event(Event::AuthorityDisabled { authority_index });
+
+            Ok(())
+        }
+
+        /// Indices of all authorities out of the rotation in the current session, sorted.
+        pub fn disabled_authorities() -> Vec<AuthorityIndex> {
+            let mut disabled = DisabledAuthorities::<T>::get().into_inner();
+            disabled.extend(T::DisabledValidators::disabled_validators());
+            disabled.sort();
+            disabled.dedup();
+            disabled
+        }
+
+        /// Index of the authority scheduled to author the current session.
+        pub fn session_author_index() -> Option<AuthorityIndex> {
+            Self::author_index_of(SessionIndex::<T>::get())
+        }
+
+        /// Index of the session a child of the parent block at `slot` is authored in.
+        pub fn session_index_at(slot: Slot) -> spin_primitives::SessionIndex {
+            Self::session_mode().session_index(slot, SessionIndex::<T>::get())
+        }
+
+        /// Index of the session starting at block `n` at `slot`, if any.
+        fn new_session_at(
+            n: BlockNumberFor<T>,
+            slot: Slot,
+        ) -> Option<spin_primitives::SessionIndex> {
+            let session_index = SessionIndex::<T>::get();
+            match Self::session_mode() {
+                SessionMode::Blocks => (n >= Self::next_session_start()).then(|| session_index + 1),
+                mode @ SessionMode::Slots(_) => Some(mode.session_index(slot, session_index))
+                    .filter(|new_session_index| *new_session_index != session_index),
+            }
+        }
+
+        /// The session mode of the current session.
+        pub fn session_mode() -> SessionMode {
+            CurrentSessionMode::<T>::get().unwrap_or_else(T::SessionMode::get)
+        }
+
+        /// The fallback timeout of the current session.
+        pub fn fallback_timeout() -> Option<u64> {
+            CurrentFallbackTimeout::<T>::get().unwrap_or_else(T::FallbackTimeout::get)
+        }
+
+        /// Number of authorities a child of the parent block at `slot` is authored past the
+        /// session author.
+        ///
+        /// The fallback rule moves authorship along the ring for every
+        /// [`Pallet::fallback_timeout`] empty slots since the parent block, on top of the offset
+        /// of the parent's author within the same session.
+        pub fn fallback_offset_at(slot: Slot) -> u64 {
+            match (Self::fallback_timeout(), ParentSlot::<T>::get()) {
+                (Some(timeout), Some(parent_slot)) => Fallback::new(
+                    parent_slot,
+                    timeout,
+                    Self::session_index_at(slot),
+                    FallbackOffset::<T>::get(),
+                )
+                .offset(slot),
+                _ => 0,
+            }
+        }
+
+        /// Index of the authority entitled to author a child of the parent block at `slot`, see
+        /// [`Self::fallback_offset_at`].
+        pub fn author_index_at(slot: Slot) -> Option<AuthorityIndex> {
+            spin_primitives::session_author_index(
+                Self::session_index_at(slot),
+                Self::fallback_offset_at(slot),
+                pallet_aura::Authorities::<T>::decode_len().unwrap_or(0),
+                &Self::disabled_authorities(),
+            )
+        }
+
+        /// Index of the authority scheduled to author the given session.
+        ///
+        /// This is based on the current authority set. Authorities disabled for the current
+        /// session only are expected back for any other session.
+        pub fn author_index_of(
+            session_index: spin_primitives::SessionIndex,
+        ) -> Option<AuthorityIndex> {
+            let disabled = if session_index == SessionIndex::<T>::get() {
+                Self::disabled_authorities()
+            } else {
+                DisabledAuthorities::<T>::get().into_inner()
+            };
+
+            spin_primitives::session_author_index(
+                session_index,
+                0,
+                pallet_aura::Authorities::<T>::decode_len().unwrap_or(0),
+                &disabled,
+            )
+        }
+
+        /// The authority scheduled to author the given session, see [`Self::author_index_of`].
+        pub fn author_of(session_index: spin_primitives::SessionIndex) -> Option<T::AuthorityId> {
+            let index = Self::author_index_of(session_index)?;
+            pallet_aura::Authorities::<T>::get()
+                .get(index as usize)
+                .cloned()
+        }
+
+        /// Number of the block the current session started at.
+        pub fn current_session_start() -> BlockNumberFor<T> {
+            CurrentSessionStart::<T>::get()
+        }
+
+        /// Number of the block the next session starts at.
+        ///
+        /// Only meaningful with [`SessionMode::Blocks`], sessions following the slots start at
+        /// the first block of their slots.
+        pub fn next_session_start() -> BlockNumberFor<T> {
+            Self::current_session_start() + SessionLength::<T>::get()
+        }
+
+        /// Length of the next session, taking a scheduled change into account.
+        pub fn next_session_length() -> BlockNumberFor<T> {
+            NextSessionLength::<T>::get().unwrap_or_else(SessionLength::<T>::get)
+        }
+
+        fn deposit_disabled_log() {
+            let log =
+                ConsensusLog::<T::AuthorityId>::DisabledAuthorities(Self::disabled_authorities());
+            frame_system::Pallet::<T>::deposit_log(DigestItem::Consensus(
+                AURA_ENGINE_ID,
+                log.encode(),
+            ));
+        }
+    }
+
+    /// Finds the index of the authority that authored the block with the spin pre-digest.
+    ///
+    /// Versioned pre-digests carry the index, checked by the client on import. For pre-digests
+    /// with only the slot, it is the authority entitled to author at the slot, following the
+    /// session state of the parent, so in the block starting a session this only holds until the
+    /// session moves on in `on_initialize`.
+    impl<T: Config> FindAuthor<AuthorityIndex> for Pallet<T> {
+        fn find_author<'a, I>(digests: I) -> Option<AuthorityIndex>
+        where
+            I: 'a + IntoIterator<Item = (ConsensusEngineId, &'a [u8])>,
+        {
+            let pre_digest = digests.into_iter().find_map(|(id, mut data)| {
+                (id == AURA_ENGINE_ID)
+                    .then(|| PreDigest::decode(&mut data).ok())
+                    .flatten()
+            })?;
+
+            let authorities_len = pallet_aura::Authorities::<T>::decode_len().unwrap_or(0);
+            pre_digest
+                .session_and_author()
+                .map(|(_, authority_index)| authority_index)
+                .filter(|authority_index| (*authority_index as usize) < authorities_len)
+                .or_else(|| Self::author_index_at(pre_digest.slot()))
         }
     }
 }
@@ -33,10 +577,12 @@ pub mod pallet {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use frame_support::{derive_impl, pallet_prelude::*, parameter_types};
-    use sp_core::ConstU64;
+    use frame_support::{assert_noop, assert_ok, derive_impl, pallet_prelude::*, parameter_types};
+    use sp_core::{ConstBool, ConstU32, ConstU64};
+    use sp_keyring::Sr25519Keyring;
     use sp_runtime::BuildStorage;
-    use sp_runtime::{traits::IdentityLookup, Perbill};
+    use sp_runtime::{traits::IdentityLookup, DispatchError, Perbill};
+    use spin_primitives::SessionMode;
 
     type Block = frame_system::mocking::MockBlock<Test>;
 
@@ -44,6 +590,7 @@ mod tests {
         pub struct Test {
             System: frame_system,
             Timestamp: pallet_timestamp,
+            Aura: pallet_aura,
             AuraSession: pallet,
         }
     );
@@ -57,23 +604,57 @@ mod tests {
 
     impl pallet_timestamp::Config for Test {
         type Moment = u64;
-        type OnTimestampSet = ();
+        type OnTimestampSet = Aura;
         type MinimumPeriod = ConstU64<{ SLOT_DURATION / 2 }>;
         type WeightInfo = ();
     }
 
+    impl pallet_aura::Config for Test {
+        type AuthorityId = spin_primitives::sr25519::AuthorityId;
+        type DisabledValidators = ();
+        type MaxAuthorities = ConstU32<10>;
+        type AllowMultipleBlocksPerSlot = ConstBool<false>;
+        type SlotDuration = pallet_aura::MinimumPeriodTimesTwo<Test>;
+    }
+
     parameter_types! {
-        pub const SessionLength: u64 = 3;
+        pub static FallbackTimeout: Option<u64> = None;
+        pub static Mode: SessionMode = SessionMode::Blocks;
     }
     impl pallet::Config for Test {
-        type SessionLength = SessionLength;
+        type RuntimeEvent = RuntimeEvent;
+        type FallbackTimeout = FallbackTimeout;
+        type SessionMode = Mode;
+        type DisableOrigin = frame_system::EnsureRoot<u64>;
+        type SessionLengthOrigin = frame_system::EnsureRoot<u64>;
+        type DisabledValidators = ();
+        type OnNewSession = ();
     }
 
+    const SESSION_LENGTH: u64 = 3;
+
     fn build_ext() -> sp_io::TestExternalities {
-        let storage = frame_system::GenesisConfig::<Test>::default()
+        let mut storage = frame_system::GenesisConfig::<Test>::default()
             .build_storage()
             .unwrap();
-        storage.into()
+        pallet_aura::GenesisConfig::<Test> {
+            authorities: vec![
+                Sr25519Keyring::Alice.public().into(),
+                Sr25519Keyring::Bob.public().into(),
+                Sr25519Keyring::Charlie.public().into(),
+            ],
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        pallet::GenesisConfig::<Test> {
+            session_length: SESSION_LENGTH,
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+
+        let mut ext: sp_io::TestExternalities = storage.into();
+        ext.execute_with(|| System::set_block_number(1));
+        ext
     }
 
     #[test]
@@ -87,10 +668,480 @@ mod tests {
             Pallet::<Test>::on_initialize(3);
             assert_eq!(SessionIndex::<Test>::get(), 1);
 
-            for i in 0..SessionLength::get() {
-                Pallet::<Test>::on_initialize(i as u64);
+            for n in 4..=SESSION_LENGTH * 2 {
+                Pallet::<Test>::on_initialize(n);
+            }
+            assert_eq!(SessionIndex::<Test>::get(), 2);
+        });
+    }
+
+    #[test]
+    fn new_session_is_logged() {
+        use codec::Encode;
+        use sp_runtime::DigestItem;
+        use spin_primitives::{sr25519::AuthorityId, ConsensusLog, AURA_ENGINE_ID};
+
+        build_ext().execute_with(|| {
+            Pallet::<Test>::on_initialize(2);
+            assert!(System::digest().logs().is_empty());
+
+            Pallet::<Test>::on_initialize(3);
+            let log = ConsensusLog::<AuthorityId>::NewSession(1);
+            assert_eq!(
+                System::digest().logs(),
+                &[DigestItem::Consensus(AURA_ENGINE_ID, log.encode())]
+            );
+        });
+    }
+
+    #[test]
+    fn disabled_authorities_leave_the_rotation() {
+        use codec::Encode;
+        use sp_runtime::DigestItem;
+        use spin_primitives::{sr25519::AuthorityId, ConsensusLog, AURA_ENGINE_ID};
+
+        build_ext().execute_with(|| {
+            SessionIndex::<Test>::put(1);
+            assert_eq!(Pallet::<Test>::session_author_index(), Some(1));
+
+            assert_noop!(
+                AuraSession::disable_authority(RuntimeOrigin::signed(1), 1),
+                DispatchError::BadOrigin,
+            );
+            assert_noop!(
+                AuraSession::disable_authority(RuntimeOrigin::root(), 3),
+                Error::<Test>::UnknownAuthority,
+            );
+
+            assert_ok!(AuraSession::disable_authority(RuntimeOrigin::root(), 1));
+            System::assert_last_event(Event::AuthorityDisabled { authority_index: 1 }.into());
+            assert_eq!(Pallet::<Test>::session_author_index(), Some(2));
+            assert!(System::digest().logs().contains(&DigestItem::Consensus(
+                AURA_ENGINE_ID,
+                ConsensusLog::<AuthorityId>::DisabledAuthorities(vec![1]).encode(),
+            )));
+            assert_noop!(
+                AuraSession::disable_authority(RuntimeOrigin::root(), 1),
+                Error::<Test>::AlreadyDisabled,
+            );
+
+            // somebody has to stay in the rotation.
+            assert_ok!(AuraSession::disable_authority(RuntimeOrigin::root(), 2));
+            assert_noop!(
+                AuraSession::disable_authority(RuntimeOrigin::root(), 0),
+                Error::<Test>::NoEnabledAuthority,
+            );
+            assert_eq!(Pallet::<Test>::session_author_index(), Some(0));
+
+            // the disabled set outlives the session and is announced again.
+            Pallet::<Test>::on_initialize(3);
+            assert_eq!(Pallet::<Test>::disabled_authorities(), vec![1, 2]);
+            assert_eq!(
+                System::digest().logs().last(),
+                Some(&DigestItem::Consensus(
+                    AURA_ENGINE_ID,
+                    ConsensusLog::<AuthorityId>::DisabledAuthorities(vec![1, 2]).encode(),
+                ))
+            );
+
+            assert_ok!(AuraSession::enable_authority(RuntimeOrigin::root(), 1));
+            System::assert_last_event(Event::AuthorityEnabled { authority_index: 1 }.into());
+            assert_eq!(Pallet::<Test>::disabled_authorities(), vec![2]);
+            assert_noop!(
+                AuraSession::enable_authority(RuntimeOrigin::root(), 1),
+                Error::<Test>::NotDisabled,
+            );
+        });
+    }
+
+    #[test]
+    fn schedule_follows_session_boundaries() {
+        build_ext().execute_with(|| {
+            Pallet::<Test>::on_initialize(3);
+            Pallet::<Test>::on_initialize(4);
+            assert_eq!(Pallet::<Test>::current_session_start(), 3);
+            assert_eq!(Pallet::<Test>::next_session_start(), 6);
+
+            Pallet::<Test>::on_initialize(6);
+            assert_eq!(Pallet::<Test>::current_session_start(), 6);
+            assert_eq!(Pallet::<Test>::next_session_start(), 9);
+
+            assert_eq!(
+                Pallet::<Test>::author_of(1),
+                Some(Sr25519Keyring::Bob.public().into())
+            );
+            assert_eq!(
+                Pallet::<Test>::author_of(5),
+                Some(Sr25519Keyring::Charlie.public().into())
+            );
+
+            // disabling only moves the sessions of the disabled authority.
+            assert_ok!(AuraSession::disable_authority(RuntimeOrigin::root(), 2));
+            assert_eq!(
+                Pallet::<Test>::author_of(5),
+                Some(Sr25519Keyring::Alice.public().into())
+            );
+            assert_eq!(
+                Pallet::<Test>::author_of(4),
+                Some(Sr25519Keyring::Bob.public().into())
+            );
+        });
+    }
+
+    #[test]
+    fn author_is_found_by_session_not_slot() {
+        use codec::Encode;
+        use frame_support::traits::FindAuthor;
+        use spin_primitives::{digests::PreDigest, Slot, AURA_ENGINE_ID};
+
+        build_ext().execute_with(|| {
+            SessionIndex::<Test>::put(1);
+            let pre_digest = Slot::from(8).encode();
+
+            // `pallet_aura` would pick `8 % 3`.
+            assert_eq!(
+                Pallet::<Test>::find_author([(AURA_ENGINE_ID, &pre_digest[..])]),
+                Some(1)
+            );
+            assert_eq!(
+                Pallet::<Test>::find_author([(*b"BABE", &pre_digest[..])]),
+                None
+            );
+
+            // the index carried by versioned pre-digests is preferred, if it is one of an
+            // authority.
+            let versioned = |authority_index| {
+                PreDigest::V1 {
+                    slot: 8.into(),
+                    session_index: 1,
+                    authority_index,
+                }
+                .encode()
+            };
+            assert_eq!(
+                Pallet::<Test>::find_author([(AURA_ENGINE_ID, &versioned(2)[..])]),
+                Some(2)
+            );
+            assert_eq!(
+                Pallet::<Test>::find_author([(AURA_ENGINE_ID, &versioned(3)[..])]),
+                Some(1)
+            );
+        });
+    }
+
+    #[test]
+    fn session_length_changes_at_the_next_boundary() {
+        use codec::Encode;
+        use sp_runtime::DigestItem;
+        use spin_primitives::{sr25519::AuthorityId, ConsensusLog, AURA_ENGINE_ID};
+
+        build_ext().execute_with(|| {
+            assert_noop!(
+                AuraSession::set_session_length(RuntimeOrigin::signed(1), 5),
+                DispatchError::BadOrigin,
+            );
+            assert_noop!(
+                AuraSession::set_session_length(RuntimeOrigin::root(), 0),
+                Error::<Test>::ZeroSessionLength,
+            );
+
+            assert_ok!(AuraSession::set_session_length(RuntimeOrigin::root(), 5));
+            System::assert_last_event(Event::SessionLengthScheduled { session_length: 5 }.into());
+            assert_eq!(Pallet::<Test>::next_session_start(), 3);
+            assert_eq!(Pallet::<Test>::next_session_length(), 5);
+
+            // the current session keeps its length.
+            Pallet::<Test>::on_initialize(2);
+            assert_eq!(SessionLength::<Test>::get(), 3);
+
+            Pallet::<Test>::on_initialize(3);
+            System::assert_last_event(Event::SessionLengthChanged { session_length: 5 }.into());
+            assert_eq!(
+                System::digest().logs(),
+                &[
+                    DigestItem::Consensus(
+                        AURA_ENGINE_ID,
+                        ConsensusLog::<AuthorityId>::NewSession(1).encode()
+                    ),
+                    DigestItem::Consensus(
+                        AURA_ENGINE_ID,
+                        ConsensusLog::<AuthorityId>::SessionLengthChange(5).encode()
+                    ),
+                ]
+            );
+            assert_eq!(SessionIndex::<Test>::get(), 1);
+            assert_eq!(Pallet::<Test>::current_session_start(), 3);
+            assert_eq!(Pallet::<Test>::next_session_start(), 8);
+
+            for n in 4..8 {
+                Pallet::<Test>::on_initialize(n);
+            }
+            assert_eq!(SessionIndex::<Test>::get(), 1);
+            Pallet::<Test>::on_initialize(8);
+            assert_eq!(SessionIndex::<Test>::get(), 2);
+            assert_eq!(Pallet::<Test>::next_session_start(), 13);
+        });
+    }
+
+    #[test]
+    fn fallback_timeout_changes_at_the_next_boundary() {
+        use codec::Encode;
+        use sp_runtime::DigestItem;
+        use spin_primitives::{sr25519::AuthorityId, ConsensusLog, AURA_ENGINE_ID};
+
+        let change = |timeout| {
+            DigestItem::Consensus(
+                AURA_ENGINE_ID,
+                ConsensusLog::<AuthorityId>::FallbackTimeoutChange(timeout).encode(),
+            )
+        };
+
+        build_ext().execute_with(|| {
+            FallbackTimeout::set(Some(2));
+            ParentSlot::<Test>::put(Slot::from(10));
+            assert_eq!(Pallet::<Test>::fallback_timeout(), None);
+            assert_eq!(Pallet::<Test>::author_index_at(13.into()), Some(0));
+
+            Pallet::<Test>::on_initialize(2);
+            assert!(!System::digest().logs().contains(&change(Some(2))));
+            Pallet::<Test>::on_initialize(3);
+            assert!(System::digest().logs().contains(&change(Some(2))));
+            assert_eq!(Pallet::<Test>::fallback_timeout(), Some(2));
+            assert_eq!(Pallet::<Test>::author_index_at(13.into()), Some(2));
+
+            // only changes are announced.
+            System::initialize(&6, &Default::default(), &Default::default());
+            Pallet::<Test>::on_initialize(6);
+            assert_eq!(SessionIndex::<Test>::get(), 2);
+            assert!(!System::digest().logs().contains(&change(Some(2))));
+        });
+
+        // chains upgraded from a fixed timeout follow the configured one until the boundary.
+        build_ext().execute_with(|| {
+            CurrentFallbackTimeout::<Test>::kill();
+            FallbackTimeout::set(Some(2));
+            assert_eq!(Pallet::<Test>::fallback_timeout(), Some(2));
+            Pallet::<Test>::on_initialize(3);
+            assert!(System::digest().logs().contains(&change(Some(2))));
+        });
+    }
+
+    #[test]
+    fn fallback_authors_keep_the_session() {
+        use codec::Encode;
+        use sp_runtime::{Digest, DigestItem};
+        use spin_primitives::{Slot, AURA_ENGINE_ID};
+
+        let produce_block = |n, slot: u64| {
+            let digest = Digest {
+                logs: vec![DigestItem::PreRuntime(
+                    AURA_ENGINE_ID,
+                    Slot::from(slot).encode(),
+                )],
+            };
+            System::initialize(&n, &Default::default(), &digest);
+            pallet_aura::CurrentSlot::<Test>::put(Slot::from(slot));
+            Pallet::<Test>::on_initialize(n);
+            Pallet::<Test>::on_finalize(n);
+            Author::<Test>::get()
+        };
+
+        FallbackTimeout::set(Some(2));
+        build_ext().execute_with(|| {
+            assert_eq!(
+                produce_block(1, 1),
+                Some((0, Sr25519Keyring::Alice.public().into()))
+            );
+
+            // three empty slots hand the session over to Bob.
+            assert_eq!(
+                produce_block(2, 5),
+                Some((0, Sr25519Keyring::Bob.public().into()))
+            );
+            assert_eq!(FallbackOffset::<Test>::get(), (0, 1));
+
+            // who keeps it for the following slots, up to the session boundary.
+            assert_eq!(
+                produce_block(3, 6),
+                Some((0, Sr25519Keyring::Bob.public().into()))
+            );
+            assert_eq!(FallbackOffset::<Test>::get(), (0, 1));
+            assert_eq!(SessionIndex::<Test>::get(), 1);
+
+            // the next session starts over from its own author.
+            assert_eq!(Pallet::<Test>::author_index_at(7.into()), Some(1));
+            produce_block(4, 7);
+            assert_eq!(FallbackOffset::<Test>::get(), (1, 0));
+        });
+    }
+
+    #[test]
+    fn session_mode_changes_at_the_next_boundary() {
+        use codec::Encode;
+        use sp_runtime::DigestItem;
+        use spin_primitives::{sr25519::AuthorityId, ConsensusLog, AURA_ENGINE_ID};
+
+        let change = |mode| {
+            DigestItem::Consensus(
+                AURA_ENGINE_ID,
+                ConsensusLog::<AuthorityId>::SessionModeChange(mode).encode(),
+            )
+        };
+
+        build_ext().execute_with(|| {
+            Mode::set(SessionMode::Slots(4));
+            assert_eq!(Pallet::<Test>::session_mode(), SessionMode::Blocks);
+            assert_eq!(Pallet::<Test>::session_index_at(9.into()), 0);
+
+            Pallet::<Test>::on_initialize(2);
+            assert!(!System::digest()
+                .logs()
+                .contains(&change(SessionMode::Slots(4))));
+            Pallet::<Test>::on_initialize(3);
+            assert!(System::digest()
+                .logs()
+                .contains(&change(SessionMode::Slots(4))));
+            assert_eq!(Pallet::<Test>::session_mode(), SessionMode::Slots(4));
+            assert_eq!(Pallet::<Test>::session_index_at(9.into()), 2);
+        });
+
+        // chains upgraded from a fixed mode follow the configured one until the boundary.
+        Mode::set(SessionMode::Blocks);
+        build_ext().execute_with(|| {
+            CurrentSessionMode::<Test>::kill();
+            assert_eq!(Pallet::<Test>::session_mode(), SessionMode::Blocks);
+            Pallet::<Test>::on_initialize(3);
+            assert!(System::digest()
+                .logs()
+                .contains(&change(SessionMode::Blocks)));
+        });
+    }
+
+    #[test]
+    fn session_length_is_seeded_on_existing_chains() {
+        assert_eq!(pallet::GenesisConfig::<Test>::default().session_length, 10);
+
+        build_ext().execute_with(|| {
+            SessionLength::<Test>::kill();
+            SessionIndex::<Test>::put(1);
+            System::set_block_number(5);
+            SeedSessionLength::<Test, ConstU64<SESSION_LENGTH>>::on_runtime_upgrade();
+            assert_eq!(SessionLength::<Test>::get(), SESSION_LENGTH);
+            assert_eq!(CurrentSessionStart::<Test>::get(), 3);
+
+            // the session keeps its boundary and the migration runs once.
+            Pallet::<Test>::on_initialize(5);
+            assert_eq!(SessionIndex::<Test>::get(), 1);
+            SessionLength::<Test>::put(7);
+            SeedSessionLength::<Test, ConstU64<SESSION_LENGTH>>::on_runtime_upgrade();
+            assert_eq!(SessionLength::<Test>::get(), 7);
+            assert_eq!(CurrentSessionStart::<Test>::get(), 3);
+        });
+
+        // at the boundary, the session moves on in the same block.
+        build_ext().execute_with(|| {
+            SessionLength::<Test>::kill();
+            System::set_block_number(6);
+            SeedSessionLength::<Test, ConstU64<SESSION_LENGTH>>::on_runtime_upgrade();
+            assert_eq!(CurrentSessionStart::<Test>::get(), 3);
+            Pallet::<Test>::on_initialize(6);
+            assert_eq!(SessionIndex::<Test>::get(), 1);
+            assert_eq!(CurrentSessionStart::<Test>::get(), 6);
+        });
+    }
+
+    #[test]
+    fn parent_slot_is_seeded_on_existing_chains() {
+        build_ext().execute_with(|| {
+            pallet_aura::CurrentSlot::<Test>::put(spin_primitives::Slot::from(7));
+            SeedParentSlot::<Test>::on_runtime_upgrade();
+            assert_eq!(ParentSlot::<Test>::get(), Some(7.into()));
+
+            // the migration runs once.
+            pallet_aura::CurrentSlot::<Test>::put(spin_primitives::Slot::from(8));
+            SeedParentSlot::<Test>::on_runtime_upgrade();
+            assert_eq!(ParentSlot::<Test>::get(), Some(7.into()));
+        });
+    }
+
+    #[test]
+    fn sessions_follow_the_slots() {
+        use codec::Encode;
+        use sp_runtime::{Digest, DigestItem};
+        use spin_primitives::{
+            digests::PreDigest, sr25519::AuthorityId, ConsensusLog, Slot, AURA_ENGINE_ID,
+        };
+
+        let pre_digest =
+            |slot: u64| DigestItem::PreRuntime(AURA_ENGINE_ID, Slot::from(slot).encode());
+        let produce_block = |n, slot: u64| {
+            let digest = Digest {
+                logs: vec![pre_digest(slot)],
+            };
+            System::initialize(&n, &Default::default(), &digest);
+            pallet_aura::CurrentSlot::<Test>::put(Slot::from(slot));
+            Pallet::<Test>::on_initialize(n);
+            Pallet::<Test>::on_finalize(n);
+        };
+
+        Mode::set(SessionMode::Slots(4));
+        build_ext().execute_with(|| {
+            produce_block(1, 1);
+            assert_eq!(SessionIndex::<Test>::get(), 0);
+            assert_eq!(
+                Author::<Test>::get(),
+                Some((0, Sr25519Keyring::Alice.public().into()))
+            );
+
+            // empty slots don't stretch the session, sessions without blocks are skipped and the
+            // first block of a session is authored in it.
+            produce_block(2, 9);
+            assert_eq!(SessionIndex::<Test>::get(), 2);
+            assert_eq!(Pallet::<Test>::current_session_start(), 2);
+            assert_eq!(
+                Author::<Test>::get(),
+                Some((2, Sr25519Keyring::Charlie.public().into()))
+            );
+            assert_eq!(
+                System::digest().logs(),
+                &[
+                    pre_digest(9),
+                    DigestItem::Consensus(
+                        AURA_ENGINE_ID,
+                        ConsensusLog::<AuthorityId>::NewSession(2).encode()
+                    )
+                ]
+            );
+
+            // neither do blocks move the session on.
+            for (n, slot) in [(3, 10), (4, 11)] {
+                produce_block(n, slot);
             }
             assert_eq!(SessionIndex::<Test>::get(), 2);
+
+            produce_block(5, 12);
+            assert_eq!(SessionIndex::<Test>::get(), 3);
+            assert_eq!(
+                Author::<Test>::get(),
+                Some((3, Sr25519Keyring::Alice.public().into()))
+            );
+
+            // the author carried by versioned pre-digests is taken as is, e.g. a fallback one.
+            let pre_digest = PreDigest::V1 {
+                slot: 13.into(),
+                session_index: 3,
+                authority_index: 1,
+            };
+            let digest = Digest {
+                logs: vec![DigestItem::PreRuntime(AURA_ENGINE_ID, pre_digest.encode())],
+            };
+            System::initialize(&6, &Default::default(), &digest);
+            pallet_aura::CurrentSlot::<Test>::put(Slot::from(13));
+            Pallet::<Test>::on_initialize(6);
+            assert_eq!(
+                Author::<Test>::get(),
+                Some((3, Sr25519Keyring::Bob.public().into()))
+            );
         });
     }
 }
@@ -0,0 +1,907 @@
+pub use pallet::*;
+
+use crate::aura_session;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use frame_support::{
+    traits::{FindAuthor, Get, OnRuntimeUpgrade},
+    weights::Weight,
+    BoundedVec,
+};
+use sp_runtime::{traits::Convert, ConsensusEngineId};
+
+/// Finds the account of the validator whose authority index `Inner` finds.
+pub struct FindAccountFromAuthorIndex<T, Inner>(PhantomData<(T, Inner)>);
+
+impl<T, Inner> FindAuthor<T::AccountId> for FindAccountFromAuthorIndex<T, Inner>
+where
+    T: Config,
+    Inner: FindAuthor<spin_primitives::AuthorityIndex>,
+{
+    fn find_author<'a, I>(digests: I) -> Option<T::AccountId>
+    where
+        I: 'a + IntoIterator<Item = (ConsensusEngineId, &'a [u8])>,
+    {
+        let index = Inner::find_author(digests)?;
+        Validators::<T>::get().get(index as usize).cloned()
+    }
+}
+
+/// Handler for the pruning of a GRANDPA set from the set history.
+pub trait OnSetPruned {
+    /// Called when the set `set_id` is forgotten, after which its key owner proofs are invalid.
+    fn on_set_pruned(set_id: sp_consensus_grandpa::SetId);
+}
+
+impl OnSetPruned for () {
+    fn on_set_pruned(_: sp_consensus_grandpa::SetId) {}
+}
+
+/// Seed the keys of chains launched before this pallet from the Aura and GRANDPA authorities,
+/// matched by position, with `AccountOf` giving the account of each Aura key.
+pub struct SeedAuthorityKeys<T, AccountOf>(PhantomData<(T, AccountOf)>);
+
+impl<T, AccountOf> OnRuntimeUpgrade for SeedAuthorityKeys<T, AccountOf>
+where
+    T: Config,
+    AccountOf: Convert<T::AuthorityId, T::AccountId>,
+{
+    fn on_runtime_upgrade() -> Weight {
+        if !Validators::<T>::get().is_empty() {
+            return T::DbWeight::get().reads(1);
+        }
+
+        let aura = pallet_aura::Authorities::<T>::get();
+        let grandpa = pallet_grandpa::Pallet::<T>::grandpa_authorities();
+        if aura.len() != grandpa.len() {
+            log::error!(
+                target: "runtime::authority_keys",
+                "Not seeding the authority keys: {} Aura authorities but {} GRANDPA ones",
+                aura.len(),
+                grandpa.len(),
+            );
+            return T::DbWeight::get().reads(3);
+        }
+
+        let mut validators = Vec::with_capacity(aura.len());
+        let mut historical = Vec::with_capacity(aura.len());
+        for (aura, (grandpa, _)) in aura.into_iter().zip(grandpa) {
+            let who = AccountOf::convert(aura.clone());
+            CurrentKeys::<T>::insert(
+                &who,
+                Keys {
+                    aura,
+                    grandpa: grandpa.clone(),
+                },
+            );
+            validators.push(who.clone());
+            historical.push((who, grandpa));
+        }
+
+        let count = validators.len() as u64;
+        Validators::<T>::put(
+            BoundedVec::try_from(validators).expect("as many as the Aura authorities; qed"),
+        );
+        // the set has been active since at least the current session.
+        let set_id = pallet_grandpa::CurrentSetId::<T>::get();
+        HistoricalSets::<T>::insert(
+            set_id,
+            HistoricalSetOf::<T>::try_from(historical)
+                .expect("as many as the Aura authorities; qed"),
+        );
+        pallet_grandpa::SetIdSession::<T>::insert(
+            set_id,
+            aura_session::Pallet::<T>::session_index_at(pallet_aura::CurrentSlot::<T>::get()),
+        );
+
+        T::DbWeight::get().reads_writes(6, 3 + count)
+    }
+}
+
+#[frame_support::pallet]
+pub mod pallet {
+    use super::OnSetPruned;
+    use crate::aura_session;
+    use alloc::vec::Vec;
+    use codec::Encode;
+    use frame_support::{pallet_prelude::*, traits::KeyOwnerProofSystem};
+    use frame_system::pallet_prelude::*;
+    use sp_consensus_grandpa::{
+        AuthorityId as GrandpaId, AuthoritySignature as GrandpaSignature, SetId, KEY_TYPE,
+    };
+    use sp_core::crypto::KeyTypeId;
+    use sp_runtime::RuntimeAppPublic;
+    use sp_session::{GetSessionNumber, GetValidatorCount, ValidatorCount};
+    use spin_primitives::SessionIndex;
+
+    /// Context of the payload signed by each key to prove its ownership.
+    pub const KEYS_PROOF_CONTEXT: &[u8] = b"spin_set_keys";
+
+    /// Keys of an authority.
+    ///
+    /// Encodes like the runtime's session keys, as returned by `author_rotateKeys`.
+    #[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct Keys<AuraId> {
+        pub aura: AuraId,
+        pub grandpa: GrandpaId,
+    }
+
+    /// Signatures of `(KEYS_PROOF_CONTEXT, account)` by each of the [`Keys`] registered for the
+    /// account.
+    #[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+    pub struct KeysProof<AuraSignature> {
+        pub aura: AuraSignature,
+        pub grandpa: GrandpaSignature,
+    }
+
+    /// Proof that a GRANDPA key belonged to an authority of the given set and session.
+    ///
+    /// Checked against the authority sets kept in [`HistoricalSets`].
+    #[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug, TypeInfo)]
+    pub struct KeyOwnerProof {
+        pub session: SessionIndex,
+        pub set_id: SetId,
+        pub validator_count: ValidatorCount,
+    }
+
+    impl GetSessionNumber for KeyOwnerProof {
+        fn session(&self) -> SessionIndex {
+            self.session
+        }
+    }
+
+    impl GetValidatorCount for KeyOwnerProof {
+        fn validator_count(&self) -> ValidatorCount {
+            self.validator_count
+        }
+    }
+
+    /// Keys of an authority of this runtime.
+    pub type KeysOf<T> = Keys<<T as pallet_aura::Config>::AuthorityId>;
+
+    /// Accounts of the authorities of this runtime.
+    pub type ValidatorsOf<T> = BoundedVec<
+        <T as frame_system::Config>::AccountId,
+        <T as pallet_aura::Config>::MaxAuthorities,
+    >;
+
+    /// Accounts of the authorities of a GRANDPA set, along with their GRANDPA keys.
+    pub type HistoricalSetOf<T> = BoundedVec<
+        (<T as frame_system::Config>::AccountId, GrandpaId),
+        <T as pallet_aura::Config>::MaxAuthorities,
+    >;
+
+    /// Ownership proof of the keys of an authority of this runtime.
+    pub type KeysProofOf<T> =
+        KeysProof<<<T as pallet_aura::Config>::AuthorityId as RuntimeAppPublic>::Signature>;
+
+    #[pallet::pallet]
+    pub struct Pallet<T>(_);
+
+    #[pallet::config]
+    pub trait Config:
+        frame_system::Config + pallet_aura::Config + pallet_grandpa::Config + aura_session::Config
+    {
+        type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
+
+        /// Handler for the GRANDPA sets pruned from [`HistoricalSets`].
+        type OnSetPruned: OnSetPruned;
+    }
+
+    /// Accounts of the authorities, in the order of the Aura and GRANDPA authority sets.
+    #[pallet::storage]
+    pub type Validators<T: Config> = StorageValue<_, ValidatorsOf<T>, ValueQuery>;
+
+    /// Keys currently in use by each authority.
+    #[pallet::storage]
+    pub type CurrentKeys<T: Config> =
+        StorageMap<_, Twox64Concat, T::AccountId, KeysOf<T>, OptionQuery>;
+
+    /// Keys to be used from the next session on.
+    #[pallet::storage]
+    pub type QueuedKeys<T: Config> =
+        StorageMap<_, Twox64Concat, T::AccountId, KeysOf<T>, OptionQuery>;
+
+    /// Authorities of the recent GRANDPA sets, to check key ownership proofs against.
+    ///
+    /// Sets are pruned along with `pallet_grandpa::SetIdSession`, keeping the last
+    /// `MaxSetIdSessionEntries` of them.
+    #[pallet::storage]
+    pub type HistoricalSets<T: Config> =
+        StorageMap<_, Twox64Concat, SetId, HistoricalSetOf<T>, OptionQuery>;
+
+    #[pallet::genesis_config]
+    #[derive(frame_support::DefaultNoBound)]
+    pub struct GenesisConfig<T: Config> {
+        /// Accounts of the genesis authorities along with their Aura and GRANDPA keys, in the
+        /// order of the Aura and GRANDPA genesis authorities.
+        pub keys: Vec<(T::AccountId, T::AuthorityId, GrandpaId)>,
+    }
+
+    #[pallet::genesis_build]
+    impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
+        fn build(&self) {
+            let authorities = pallet_aura::Authorities::<T>::get();
+            assert_eq!(
+                authorities.len(),
+                self.keys.len(),
+                "Keys must be given for every Aura authority"
+            );
+
+            let mut validators = Vec::with_capacity(self.keys.len());
+            let mut historical = Vec::with_capacity(self.keys.len());
+            for ((who, aura, grandpa), authority) in self.keys.iter().zip(authorities.iter()) {
+                assert!(
+                    aura == authority,
+                    "Keys must be given in the order of the Aura authorities"
+                );
+                assert!(
+                    !CurrentKeys::<T>::contains_key(who),
+                    "Accounts of the authorities must be unique"
+                );
+                let keys = Keys {
+                    aura: aura.clone(),
+                    grandpa: grandpa.clone(),
+                };
+                CurrentKeys::<T>::insert(who, keys);
+                validators.push(who.clone());
+                historical.push((who.clone(), grandpa.clone()));
+            }
+
+            Validators::<T>::put(
+                BoundedVec::try_from(validators).expect("as many as the Aura authorities; qed"),
+            );
+            HistoricalSets::<T>::insert(
+                pallet_grandpa::CurrentSetId::<T>::get(),
+                HistoricalSetOf::<T>::try_from(historical)
+                    .expect("as many as the Aura authorities; qed"),
+            );
+        }
+    }
+
+    #[pallet::event]
+    #[pallet::generate_deposit(pub(super) fn deposit_event)]
+    pub enum Event<T: Config> {
+        /// New keys were queued for the next session.
+        KeysQueued { who: T::AccountId },
+        /// Queued keys are now in use.
+        KeysChanged {
+            who: T::AccountId,
+            session_index: SessionIndex,
+        },
+    }
+
+    #[pallet::error]
+    pub enum Error<T> {
+        /// The account is not an authority.
+        NotValidator,
+        /// The ownership proof doesn't match the keys.
+        InvalidProof,
+        /// One of the keys is used by another authority.
+        DuplicatedKey,
+        /// The account has no keys registered.
+        NoKeys,
+    }
+
+    #[pallet::call]
+    impl<T: Config> Pallet<T> {
+        /// Register new keys for the authority of the origin, taking effect at the next session.
+        ///
+        /// `proof` holds the signatures of `(KEYS_PROOF_CONTEXT, origin)` by every key. Queuing
+        /// keys again before the session ends replaces the keys queued earlier.
+        #[pallet::call_index(0)]
+        #[pallet::weight(T::DbWeight::get().reads_writes(
+            1 + 2 * u64::from(<T as pallet_aura::Config>::MaxAuthorities::get()),
+            1,
+        ))]
+        pub fn set_keys(
+            origin: OriginFor<T>,
+            keys: KeysOf<T>,
+            proof: KeysProofOf<T>,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            ensure!(
+                Validators::<T>::get().contains(&who),
+                Error::<T>::NotValidator
+            );
+
+            Self::queue_keys(who, keys, proof)
+        }
+    }
+
+    impl<T: Config> Pallet<T> {
+        /// Queue keys for the given account, to be used once it is an authority of a session.
+        ///
+        /// See [`Pallet::set_keys`] for the proof.
+        pub fn queue_keys(
+            who: T::AccountId,
+            keys: KeysOf<T>,
+            proof: KeysProofOf<T>,
+        ) -> DispatchResult {
+            let payload = (KEYS_PROOF_CONTEXT, &who).encode();
+            ensure!(
+                keys.aura.verify(&payload, &proof.aura)
+                    && keys.grandpa.verify(&payload, &proof.grandpa),
+                Error::<T>::InvalidProof
+            );
+
+            // the keys of the account are replaced, so they don't conflict.
+            let in_use =
+                |other: &KeysOf<T>| other.aura == keys.aura || other.grandpa == keys.grandpa;
+            ensure!(
+                !CurrentKeys::<T>::iter().any(|(owner, other)| owner != who && in_use(&other))
+                    && !QueuedKeys::<T>::iter()
+                        .any(|(owner, other)| owner != who && in_use(&other)),
+                Error::<T>::DuplicatedKey
+            );
+
+            QueuedKeys::<T>::insert(&who, keys);
+            Self::deposit_event(Event::KeysQueued { who });
+
+            Ok(())
+        }
+
+        /// Change the Aura and GRANDPA authorities to `validators`, or the current validators,
+        /// using the keys queued for them.
+        ///
+        /// Returns `Ok(false)` if the authorities stay the same, in which case no GRANDPA change
+        /// is scheduled. Fails without changing anything if GRANDPA can't take a new set yet.
+        /// Either way, the current GRANDPA set is recorded as active in `session_index`.
+        pub fn change_authorities(
+            session_index: SessionIndex,
+            validators: Option<ValidatorsOf<T>>,
+        ) -> Result<bool, DispatchError> {
+            let changed = Self::do_change_authorities(session_index, validators);
+            pallet_grandpa::SetIdSession::<T>::insert(
+                pallet_grandpa::CurrentSetId::<T>::get(),
+                session_index,
+            );
+
+            changed
+        }
+
+        fn do_change_authorities(
+            session_index: SessionIndex,
+            validators: Option<ValidatorsOf<T>>,
+        ) -> Result<bool, DispatchError> {
+            let validators_changed = validators.is_some();
+            let current = Validators::<T>::get();
+            let validators = validators.unwrap_or_else(|| current.clone());
+            let queued = validators
+                .iter()
+                .filter(|who| QueuedKeys::<T>::contains_key(who))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !validators_changed && queued.is_empty() {
+                return Ok(false);
+            }
+
+            let keys = validators
+                .iter()
+                .map(|who| {
+                    QueuedKeys::<T>::get(who)
+                        .or_else(|| CurrentKeys::<T>::get(who))
+                        .ok_or(Error::<T>::NoKeys)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // e.g. keys queued again, or the same validators queued.
+            let unchanged = validators == current
+                && validators
+                    .iter()
+                    .zip(&keys)
+                    .all(|(who, keys)| CurrentKeys::<T>::get(who).as_ref() == Some(keys));
+            if !unchanged {
+                let grandpa_authorities = keys
+                    .iter()
+                    .map(|keys| (keys.grandpa.clone(), 1))
+                    .collect::<Vec<_>>();
+                pallet_grandpa::Pallet::<T>::schedule_change(
+                    grandpa_authorities,
+                    Zero::zero(),
+                    None,
+                )?;
+                let set_id = pallet_grandpa::CurrentSetId::<T>::mutate(|id| {
+                    *id += 1;
+                    *id
+                });
+                Self::note_set(
+                    set_id,
+                    validators
+                        .iter()
+                        .cloned()
+                        .zip(keys.iter().map(|keys| keys.grandpa.clone()))
+                        .collect(),
+                );
+
+                let aura_authorities = keys.into_iter().map(|keys| keys.aura).collect::<Vec<_>>();
+                pallet_aura::Pallet::<T>::change_authorities(
+                    BoundedVec::try_from(aura_authorities)
+                        .expect("as many as the validators, bounded alike; qed"),
+                );
+            }
+
+            if validators_changed {
+                for who in current {
+                    if !validators.contains(&who) {
+                        CurrentKeys::<T>::remove(&who);
+                    }
+                }
+                // keys queued for accounts which didn't make it into the set.
+                let stale = QueuedKeys::<T>::iter_keys()
+                    .filter(|who| !validators.contains(who))
+                    .collect::<Vec<_>>();
+                for who in stale {
+                    QueuedKeys::<T>::remove(&who);
+                }
+                Validators::<T>::put(validators);
+            }
+
+            for who in queued {
+                if let Some(keys) = QueuedKeys::<T>::take(&who) {
+                    CurrentKeys::<T>::insert(&who, keys);
+                    Self::deposit_event(Event::KeysChanged { who, session_index });
+                }
+            }
+
+            Ok(!unchanged)
+        }
+
+        /// Upper bound of the weight of [`Self::change_authorities`].
+        pub fn change_authorities_weight() -> Weight {
+            let max_authorities = u64::from(<T as pallet_aura::Config>::MaxAuthorities::get());
+            // along with the reports of about one offence per authority of the pruned set.
+            T::DbWeight::get().reads_writes(5 + 4 * max_authorities, 8 + 4 * max_authorities)
+        }
+
+        /// Upper bound of the weight of [`Self::change_authorities`] for the current validators
+        /// when the authorities stay the same.
+        pub fn keep_authorities_weight() -> Weight {
+            let max_authorities = u64::from(<T as pallet_aura::Config>::MaxAuthorities::get());
+            T::DbWeight::get().reads_writes(2 + 4 * max_authorities, 1 + 2 * max_authorities)
+        }
+
+        /// Record the authorities of a new GRANDPA set, forgetting about the oldest one.
+        fn note_set(set_id: SetId, authorities: Vec<(T::AccountId, GrandpaId)>) {
+            HistoricalSets::<T>::insert(
+                set_id,
+                HistoricalSetOf::<T>::try_from(authorities)
+                    .expect("as many as the validators, bounded alike; qed"),
+            );
+
+            let max_entries = <T as pallet_grandpa::Config>::MaxSetIdSessionEntries::get().max(1);
+            if let Some(pruned) = set_id.checked_sub(max_entries) {
+                HistoricalSets::<T>::remove(pruned);
+                pallet_grandpa::SetIdSession::<T>::remove(pruned);
+                T::OnSetPruned::on_set_pruned(pruned);
+            }
+        }
+
+        /// Proof that `key` belonged to an authority of the GRANDPA set `set_id`.
+        ///
+        /// Returns `None` if the set is unknown or pruned, or the key isn't part of it.
+        pub fn key_owner_proof(set_id: SetId, key: &GrandpaId) -> Option<KeyOwnerProof> {
+            let authorities = HistoricalSets::<T>::get(set_id)?;
+            if !authorities.iter().any(|(_, k)| k == key) {
+                return None;
+            }
+
+            Some(KeyOwnerProof {
+                // the last session of the set, which is the current one for the current set.
+                session: pallet_grandpa::SetIdSession::<T>::get(set_id)?,
+                set_id,
+                validator_count: authorities.len() as ValidatorCount,
+            })
+        }
+
+        /// The account owning `key` in the set and session given by `proof`.
+        pub fn check_key_owner_proof(
+            key: &GrandpaId,
+            proof: &KeyOwnerProof,
+        ) -> Option<T::AccountId> {
+            let authorities = HistoricalSets::<T>::get(proof.set_id)?;
+            if authorities.len() != proof.validator_count as usize {
+                return None;
+            }
+
+            // the session must fall within the set.
+            let last_session = pallet_grandpa::SetIdSession::<T>::get(proof.set_id)?;
+            let previous_last_session = proof
+                .set_id
+                .checked_sub(1)
+                .and_then(pallet_grandpa::SetIdSession::<T>::get);
+            if proof.session > last_session
+                || previous_last_session.is_some_and(|previous| proof.session <= previous)
+            {
+                return None;
+            }
+
+            authorities
+                .into_iter()
+                .find(|(_, k)| k == key)
+                .map(|(who, _)| who)
+        }
+    }
+
+    impl<T: Config> KeyOwnerProofSystem<(KeyTypeId, GrandpaId)> for Pallet<T> {
+        type Proof = KeyOwnerProof;
+        type IdentificationTuple = T::AccountId;
+
+        fn prove((key_type, key): (KeyTypeId, GrandpaId)) -> Option<Self::Proof> {
+            if key_type != KEY_TYPE {
+                return None;
+            }
+            Self::key_owner_proof(pallet_grandpa::CurrentSetId::<T>::get(), &key)
+        }
+
+        fn check_proof(
+            (key_type, key): (KeyTypeId, GrandpaId),
+            proof: Self::Proof,
+        ) -> Option<Self::IdentificationTuple> {
+            if key_type != KEY_TYPE {
+                return None;
+            }
+            Self::check_key_owner_proof(&key, &proof)
+        }
+    }
+
+    impl<T: Config> aura_session::OnNewSession for Pallet<T> {
+        fn on_new_session(session_index: SessionIndex) -> Weight {
+            match Self::change_authorities(session_index, None) {
+                Ok(false) => Self::keep_authorities_weight(),
+                Ok(true) => Self::change_authorities_weight(),
+                Err(e) => {
+                    log::warn!(
+                        target: "runtime::authority_keys",
+                        "Postponing key changes to the next session: {:?}",
+                        e,
+                    );
+                    Self::change_authorities_weight()
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aura_session;
+    use codec::Encode;
+    use frame_support::{assert_noop, assert_ok, derive_impl, parameter_types, traits::Hooks};
+    use sp_consensus_grandpa::AuthorityPair as GrandpaPair;
+    use sp_core::{ConstBool, ConstU32, ConstU64, Pair};
+    use sp_keyring::{Ed25519Keyring, Sr25519Keyring};
+    use sp_runtime::{BuildStorage, DispatchError};
+    use spin_primitives::sr25519::AuthorityPair as AuraPair;
+
+    type Block = frame_system::mocking::MockBlock<Test>;
+
+    frame_support::construct_runtime!(
+        pub struct Test {
+            System: frame_system,
+            Timestamp: pallet_timestamp,
+            Aura: pallet_aura,
+            Grandpa: pallet_grandpa,
+            AuraSession: aura_session,
+            AuthorityKeys: pallet,
+        }
+    );
+
+    #[derive_impl(frame_system::config_preludes::TestDefaultConfig)]
+    impl frame_system::Config for Test {
+        type Block = Block;
+    }
+
+    impl pallet_timestamp::Config for Test {
+        type Moment = u64;
+        type OnTimestampSet = Aura;
+        type MinimumPeriod = ConstU64<1000>;
+        type WeightInfo = ();
+    }
+
+    impl pallet_aura::Config for Test {
+        type AuthorityId = spin_primitives::sr25519::AuthorityId;
+        type DisabledValidators = ();
+        type MaxAuthorities = ConstU32<10>;
+        type AllowMultipleBlocksPerSlot = ConstBool<false>;
+        type SlotDuration = pallet_aura::MinimumPeriodTimesTwo<Test>;
+    }
+
+    impl pallet_grandpa::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type WeightInfo = ();
+        type MaxAuthorities = ConstU32<10>;
+        type MaxNominators = ConstU32<0>;
+        type MaxSetIdSessionEntries = ConstU64<2>;
+        type KeyOwnerProof = sp_core::Void;
+        type EquivocationReportSystem = ();
+    }
+
+    parameter_types! {
+        pub const FallbackTimeout: Option<u64> = None;
+    }
+
+    impl aura_session::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type FallbackTimeout = FallbackTimeout;
+        type SessionMode = ();
+        type DisableOrigin = frame_system::EnsureRoot<u64>;
+        type SessionLengthOrigin = frame_system::EnsureRoot<u64>;
+        type DisabledValidators = ();
+        type OnNewSession = AuthorityKeys;
+    }
+
+    impl pallet::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type OnSetPruned = ();
+    }
+
+    const AUTHORITIES: [(u64, Sr25519Keyring, Ed25519Keyring); 3] = [
+        (1, Sr25519Keyring::Alice, Ed25519Keyring::Alice),
+        (2, Sr25519Keyring::Bob, Ed25519Keyring::Bob),
+        (3, Sr25519Keyring::Charlie, Ed25519Keyring::Charlie),
+    ];
+
+    fn build_ext() -> sp_io::TestExternalities {
+        build_ext_with(true)
+    }
+
+    fn build_ext_with(keys: bool) -> sp_io::TestExternalities {
+        let mut storage = frame_system::GenesisConfig::<Test>::default()
+            .build_storage()
+            .unwrap();
+        pallet_aura::GenesisConfig::<Test> {
+            authorities: AUTHORITIES
+                .iter()
+                .map(|(_, k, _)| k.public().into())
+                .collect(),
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        pallet_grandpa::GenesisConfig::<Test> {
+            authorities: AUTHORITIES
+                .iter()
+                .map(|(_, _, k)| (k.public().into(), 1))
+                .collect(),
+            ..Default::default()
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        aura_session::GenesisConfig::<Test> { session_length: 3 }
+            .assimilate_storage(&mut storage)
+            .unwrap();
+        if keys {
+            pallet::GenesisConfig::<Test> {
+                keys: AUTHORITIES
+                    .iter()
+                    .map(|(who, aura, grandpa)| {
+                        (*who, aura.public().into(), grandpa.public().into())
+                    })
+                    .collect(),
+            }
+            .assimilate_storage(&mut storage)
+            .unwrap();
+        }
+
+        let mut ext: sp_io::TestExternalities = storage.into();
+        ext.execute_with(|| System::set_block_number(1));
+        ext
+    }
+
+    fn keys_of(
+        who: u64,
+        aura: &AuraPair,
+        grandpa: &GrandpaPair,
+    ) -> (KeysOf<Test>, KeysProofOf<Test>) {
+        let payload = (KEYS_PROOF_CONTEXT, who).encode();
+        (
+            Keys {
+                aura: aura.public(),
+                grandpa: grandpa.public(),
+            },
+            KeysProof {
+                aura: aura.sign(&payload),
+                grandpa: grandpa.sign(&payload),
+            },
+        )
+    }
+
+    #[test]
+    fn queued_keys_apply_at_the_next_session() {
+        build_ext().execute_with(|| {
+            let aura = AuraPair::generate().0;
+            let (keys, proof) = keys_of(2, &aura, &GrandpaPair::generate().0);
+
+            assert_noop!(
+                AuthorityKeys::set_keys(RuntimeOrigin::signed(4), keys.clone(), proof.clone()),
+                Error::<Test>::NotValidator,
+            );
+            assert_noop!(
+                AuthorityKeys::set_keys(RuntimeOrigin::signed(1), keys.clone(), proof.clone()),
+                Error::<Test>::InvalidProof,
+            );
+            assert_noop!(
+                AuthorityKeys::set_keys(RuntimeOrigin::root(), keys.clone(), proof.clone()),
+                DispatchError::BadOrigin,
+            );
+
+            assert_ok!(AuthorityKeys::set_keys(
+                RuntimeOrigin::signed(2),
+                keys.clone(),
+                proof
+            ));
+            System::assert_last_event(Event::KeysQueued { who: 2 }.into());
+
+            // the keys are taken, even while queued.
+            let (other, other_proof) = keys_of(3, &aura, &GrandpaPair::generate().0);
+            assert_noop!(
+                AuthorityKeys::set_keys(RuntimeOrigin::signed(3), other, other_proof),
+                Error::<Test>::DuplicatedKey,
+            );
+
+            // nothing changes until the session ends.
+            AuraSession::on_initialize(2);
+            assert_eq!(
+                pallet_aura::Authorities::<Test>::get()[1],
+                Sr25519Keyring::Bob.public().into()
+            );
+
+            AuraSession::on_initialize(3);
+            System::assert_last_event(
+                Event::KeysChanged {
+                    who: 2,
+                    session_index: 1,
+                }
+                .into(),
+            );
+            assert_eq!(pallet_aura::Authorities::<Test>::get()[1], keys.aura);
+            assert_eq!(pallet_grandpa::CurrentSetId::<Test>::get(), 1);
+            assert_eq!(CurrentKeys::<Test>::get(2), Some(keys.clone()));
+            assert!(QueuedKeys::<Test>::get(2).is_none());
+
+            Grandpa::on_finalize(3);
+            assert_eq!(
+                Grandpa::grandpa_authorities(),
+                vec![
+                    (Ed25519Keyring::Alice.public().into(), 1),
+                    (keys.grandpa, 1),
+                    (Ed25519Keyring::Charlie.public().into(), 1),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn key_owner_proofs_follow_the_set_history() {
+        build_ext().execute_with(|| {
+            let bob = Ed25519Keyring::Bob.public().into();
+            let proof = AuthorityKeys::key_owner_proof(0, &bob).unwrap();
+            assert_eq!(
+                proof,
+                KeyOwnerProof {
+                    session: 0,
+                    set_id: 0,
+                    validator_count: 3,
+                }
+            );
+            assert_eq!(AuthorityKeys::check_key_owner_proof(&bob, &proof), Some(2));
+            assert_eq!(
+                AuthorityKeys::check_key_owner_proof(&Ed25519Keyring::Dave.public().into(), &proof),
+                None
+            );
+
+            // Bob rotates his GRANDPA key, starting set 1 in session 1.
+            let grandpa = GrandpaPair::generate().0;
+            let (keys, keys_proof) = keys_of(2, &AuraPair::generate().0, &grandpa);
+            assert_ok!(AuthorityKeys::set_keys(
+                RuntimeOrigin::signed(2),
+                keys,
+                keys_proof
+            ));
+            AuraSession::on_initialize(3);
+            Grandpa::on_finalize(3);
+
+            // the old key remains provably Bob's in set 0 only.
+            assert_eq!(AuthorityKeys::check_key_owner_proof(&bob, &proof), Some(2));
+            assert!(AuthorityKeys::key_owner_proof(1, &bob).is_none());
+            let new_proof = AuthorityKeys::key_owner_proof(1, &grandpa.public()).unwrap();
+            assert_eq!(new_proof.session, 1);
+
+            // the session of a proof must fall within its set.
+            let stale_session = KeyOwnerProof {
+                session: 0,
+                ..new_proof.clone()
+            };
+            assert_eq!(
+                AuthorityKeys::check_key_owner_proof(&grandpa.public(), &stale_session),
+                None
+            );
+
+            // sessions without changes extend the current set.
+            AuraSession::on_initialize(6);
+            assert_eq!(
+                AuthorityKeys::key_owner_proof(1, &grandpa.public())
+                    .unwrap()
+                    .session,
+                2
+            );
+            assert_eq!(pallet_grandpa::SetIdSession::<Test>::get(0), Some(0));
+
+            // set 0 is pruned once set 2 starts.
+            let (keys, keys_proof) =
+                keys_of(3, &AuraPair::generate().0, &GrandpaPair::generate().0);
+            assert_ok!(AuthorityKeys::set_keys(
+                RuntimeOrigin::signed(3),
+                keys,
+                keys_proof
+            ));
+            AuraSession::on_initialize(9);
+            assert_eq!(pallet_grandpa::CurrentSetId::<Test>::get(), 2);
+            assert!(HistoricalSets::<Test>::get(0).is_none());
+            assert!(pallet_grandpa::SetIdSession::<Test>::get(0).is_none());
+            assert_eq!(AuthorityKeys::check_key_owner_proof(&bob, &proof), None);
+            assert_eq!(
+                AuthorityKeys::check_key_owner_proof(&grandpa.public(), &new_proof),
+                Some(2)
+            );
+        });
+    }
+
+    #[test]
+    fn unchanged_keys_keep_the_authorities() {
+        build_ext().execute_with(|| {
+            let current = CurrentKeys::<Test>::get(2).unwrap();
+            QueuedKeys::<Test>::insert(2, current.clone());
+
+            assert_eq!(AuthorityKeys::change_authorities(1, None), Ok(false));
+            assert_eq!(pallet_grandpa::CurrentSetId::<Test>::get(), 0);
+            assert!(QueuedKeys::<Test>::get(2).is_none());
+            assert_eq!(CurrentKeys::<Test>::get(2), Some(current));
+            assert_eq!(pallet_grandpa::SetIdSession::<Test>::get(0), Some(1));
+
+            // the same validators, queued again, change nothing either.
+            assert_eq!(
+                AuthorityKeys::change_authorities(2, Some(Validators::<Test>::get())),
+                Ok(false)
+            );
+            assert_eq!(pallet_grandpa::CurrentSetId::<Test>::get(), 0);
+        });
+    }
+
+    #[test]
+    fn keys_are_seeded_from_the_authorities() {
+        struct AccountOf;
+        impl Convert<spin_primitives::sr25519::AuthorityId, u64> for AccountOf {
+            fn convert(aura: spin_primitives::sr25519::AuthorityId) -> u64 {
+                AUTHORITIES
+                    .iter()
+                    .find(|(_, k, _)| aura == k.public().into())
+                    .map(|(who, _, _)| *who)
+                    .unwrap()
+            }
+        }
+
+        build_ext_with(false).execute_with(|| {
+            assert!(Validators::<Test>::get().is_empty());
+            SeedAuthorityKeys::<Test, AccountOf>::on_runtime_upgrade();
+
+            assert_eq!(Validators::<Test>::get().into_inner(), vec![1, 2, 3]);
+            assert_eq!(
+                CurrentKeys::<Test>::get(2),
+                Some(Keys {
+                    aura: Sr25519Keyring::Bob.public().into(),
+                    grandpa: Ed25519Keyring::Bob.public().into(),
+                })
+            );
+            let bob = Ed25519Keyring::Bob.public().into();
+            let proof = AuthorityKeys::key_owner_proof(0, &bob).unwrap();
+            assert_eq!(AuthorityKeys::check_key_owner_proof(&bob, &proof), Some(2));
+
+            // seeded chains keep their keys.
+            CurrentKeys::<Test>::remove(2);
+            SeedAuthorityKeys::<Test, AccountOf>::on_runtime_upgrade();
+            assert!(CurrentKeys::<Test>::get(2).is_none());
+        });
+    }
+}
@@ -0,0 +1,357 @@
+pub use pallet::*;
+
+use core::marker::PhantomData;
+use frame_support::traits::Get;
+use sp_runtime::{
+    traits::{AtLeast32BitUnsigned, Zero},
+    SaturatedConversion,
+};
+
+/// Amount minted for the author of a block.
+pub trait IssuanceCurve<BlockNumber, Balance> {
+    /// Reward for the author of the block with the given number.
+    fn block_reward(block_number: BlockNumber) -> Balance;
+}
+
+/// No rewards, authors only get their share of the fees.
+impl<BlockNumber, Balance: Zero> IssuanceCurve<BlockNumber, Balance> for () {
+    fn block_reward(_: BlockNumber) -> Balance {
+        Zero::zero()
+    }
+}
+
+/// A reward of `Initial` per block, halved every `Period` blocks. A zero period never halves.
+pub struct Halving<Initial, Period>(PhantomData<(Initial, Period)>);
+
+impl<BlockNumber, Balance, Initial, Period> IssuanceCurve<BlockNumber, Balance>
+    for Halving<Initial, Period>
+where
+    BlockNumber: AtLeast32BitUnsigned,
+    Balance: AtLeast32BitUnsigned,
+    Initial: Get<Balance>,
+    Period: Get<BlockNumber>,
+{
+    fn block_reward(block_number: BlockNumber) -> Balance {
+        let period = Period::get();
+        if period.is_zero() {
+            return Initial::get();
+        }
+
+        let halvings = (block_number / period).saturated_into::<u32>();
+        Initial::get()
+            .checked_shr(halvings)
+            .unwrap_or_else(Zero::zero)
+    }
+}
+
+#[frame_support::pallet]
+pub mod pallet {
+    use super::IssuanceCurve;
+    use frame_support::{
+        pallet_prelude::*,
+        traits::{
+            fungible::{Balanced, Credit, Inspect, Mutate},
+            Imbalance, OnUnbalanced,
+        },
+    };
+    use frame_system::pallet_prelude::*;
+    use sp_runtime::{traits::Zero, Perbill};
+
+    /// Balance of the currency rewards are paid in.
+    pub type BalanceOf<T> =
+        <<T as Config>::Currency as Inspect<<T as frame_system::Config>::AccountId>>::Balance;
+
+    #[pallet::pallet]
+    pub struct Pallet<T>(_);
+
+    #[pallet::config]
+    pub trait Config: frame_system::Config {
+        type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
+
+        /// Currency the rewards are minted in and the fees are paid in.
+        type Currency: Mutate<Self::AccountId> + Balanced<Self::AccountId>;
+
+        /// Amount minted for the author of each block.
+        type IssuanceCurve: IssuanceCurve<BlockNumberFor<Self>, BalanceOf<Self>>;
+
+        /// Share of the transaction fees and tips going to the block author, the rest is
+        /// burned.
+        #[pallet::constant]
+        type AuthorFeeShare: Get<Perbill>;
+    }
+
+    /// Account of the author of the current block, as noted by `pallet_authorship`.
+    #[pallet::storage]
+    pub type Author<T: Config> = StorageValue<_, T::AccountId, OptionQuery>;
+
+    #[pallet::event]
+    #[pallet::generate_deposit(pub(super) fn deposit_event)]
+    pub enum Event<T: Config> {
+        /// The author of a block was rewarded.
+        BlockRewarded {
+            author: T::AccountId,
+            amount: BalanceOf<T>,
+        },
+    }
+
+    #[pallet::hooks]
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+        fn on_initialize(_: BlockNumberFor<T>) -> Weight {
+            // the reward minted in `on_finalize`.
+            T::DbWeight::get().reads_writes(3, 3)
+        }
+
+        fn on_finalize(n: BlockNumberFor<T>) {
+            let Some(author) = Author::<T>::take() else {
+                return;
+            };
+            let reward = T::IssuanceCurve::block_reward(n);
+            if reward.is_zero() {
+                return;
+            }
+
+            match T::Currency::mint_into(&author, reward) {
+                Ok(amount) => Self::deposit_event(Event::BlockRewarded { author, amount }),
+                Err(e) => log::warn!(
+                    target: "runtime::block_rewards",
+                    "Unable to reward the author of block {:?}: {:?}",
+                    n,
+                    e,
+                ),
+            }
+        }
+    }
+
+    /// Remembers the author to pay, before the session rotates the validators.
+    impl<T: Config> pallet_authorship::EventHandler<T::AccountId, BlockNumberFor<T>> for Pallet<T> {
+        fn note_author(author: T::AccountId) {
+            Author::<T>::put(author);
+        }
+    }
+
+    /// Pays the share of the block author, burning the rest.
+    impl<T: Config> OnUnbalanced<Credit<T::AccountId, T::Currency>> for Pallet<T> {
+        fn on_nonzero_unbalanced(fees: Credit<T::AccountId, T::Currency>) {
+            let share = T::AuthorFeeShare::get().mul_floor(fees.peek());
+            let (to_author, _burned) = fees.split(share);
+
+            if let Some(author) = Author::<T>::get() {
+                // credit the author can't take, e.g. below the existential deposit, is burned.
+                let _ = T::Currency::resolve(&author, to_author);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aura_session, authority_keys};
+    use codec::Encode;
+    use frame_support::{
+        derive_impl, parameter_types,
+        traits::{fungible::Balanced, Hooks, OnUnbalanced},
+    };
+    use sp_core::{ConstBool, ConstU32, ConstU64};
+    use sp_keyring::{Ed25519Keyring, Sr25519Keyring};
+    use sp_runtime::{BuildStorage, Digest, DigestItem, Perbill};
+    use spin_primitives::{Slot, AURA_ENGINE_ID};
+
+    type Block = frame_system::mocking::MockBlock<Test>;
+
+    frame_support::construct_runtime!(
+        pub struct Test {
+            System: frame_system,
+            Timestamp: pallet_timestamp,
+            Aura: pallet_aura,
+            Grandpa: pallet_grandpa,
+            Balances: pallet_balances,
+            Authorship: pallet_authorship,
+            BlockRewards: pallet,
+            AuraSession: aura_session,
+            AuthorityKeys: authority_keys,
+        }
+    );
+
+    #[derive_impl(frame_system::config_preludes::TestDefaultConfig)]
+    impl frame_system::Config for Test {
+        type Block = Block;
+        type AccountData = pallet_balances::AccountData<u64>;
+    }
+
+    #[derive_impl(pallet_balances::config_preludes::TestDefaultConfig)]
+    impl pallet_balances::Config for Test {
+        type AccountStore = System;
+    }
+
+    impl pallet_timestamp::Config for Test {
+        type Moment = u64;
+        type OnTimestampSet = Aura;
+        type MinimumPeriod = ConstU64<1000>;
+        type WeightInfo = ();
+    }
+
+    impl pallet_aura::Config for Test {
+        type AuthorityId = spin_primitives::sr25519::AuthorityId;
+        type DisabledValidators = ();
+        type MaxAuthorities = ConstU32<10>;
+        type AllowMultipleBlocksPerSlot = ConstBool<false>;
+        type SlotDuration = pallet_aura::MinimumPeriodTimesTwo<Test>;
+    }
+
+    impl pallet_grandpa::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type WeightInfo = ();
+        type MaxAuthorities = ConstU32<10>;
+        type MaxNominators = ConstU32<0>;
+        type MaxSetIdSessionEntries = ConstU64<2>;
+        type KeyOwnerProof = sp_core::Void;
+        type EquivocationReportSystem = ();
+    }
+
+    parameter_types! {
+        pub const FallbackTimeout: Option<u64> = None;
+        pub const AuthorFeeShare: Perbill = Perbill::from_percent(50);
+    }
+
+    impl aura_session::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type FallbackTimeout = FallbackTimeout;
+        type SessionMode = ();
+        type DisableOrigin = frame_system::EnsureRoot<u64>;
+        type SessionLengthOrigin = frame_system::EnsureRoot<u64>;
+        type DisabledValidators = ();
+        type OnNewSession = AuthorityKeys;
+    }
+
+    impl authority_keys::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type OnSetPruned = ();
+    }
+
+    impl pallet_authorship::Config for Test {
+        type FindAuthor = authority_keys::FindAccountFromAuthorIndex<Test, AuraSession>;
+        type EventHandler = BlockRewards;
+    }
+
+    impl pallet::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type Currency = Balances;
+        type IssuanceCurve = Halving<ConstU64<100>, ConstU64<4>>;
+        type AuthorFeeShare = AuthorFeeShare;
+    }
+
+    const AUTHORITIES: [(u64, Sr25519Keyring, Ed25519Keyring); 3] = [
+        (1, Sr25519Keyring::Alice, Ed25519Keyring::Alice),
+        (2, Sr25519Keyring::Bob, Ed25519Keyring::Bob),
+        (3, Sr25519Keyring::Charlie, Ed25519Keyring::Charlie),
+    ];
+
+    fn build_ext() -> sp_io::TestExternalities {
+        let mut storage = frame_system::GenesisConfig::<Test>::default()
+            .build_storage()
+            .unwrap();
+        pallet_aura::GenesisConfig::<Test> {
+            authorities: AUTHORITIES
+                .iter()
+                .map(|(_, k, _)| k.public().into())
+                .collect(),
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        pallet_grandpa::GenesisConfig::<Test> {
+            authorities: AUTHORITIES
+                .iter()
+                .map(|(_, _, k)| (k.public().into(), 1))
+                .collect(),
+            ..Default::default()
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        aura_session::GenesisConfig::<Test> { session_length: 3 }
+            .assimilate_storage(&mut storage)
+            .unwrap();
+        authority_keys::GenesisConfig::<Test> {
+            keys: AUTHORITIES
+                .iter()
+                .map(|(who, aura, grandpa)| (*who, aura.public().into(), grandpa.public().into()))
+                .collect(),
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+
+        storage.into()
+    }
+
+    fn initialize_block(n: u64, slot: u64) {
+        let digest = Digest {
+            logs: vec![DigestItem::PreRuntime(
+                AURA_ENGINE_ID,
+                Slot::from(slot).encode(),
+            )],
+        };
+        System::initialize(&n, &Default::default(), &digest);
+        Aura::on_initialize(n);
+        Authorship::on_initialize(n);
+        AuraSession::on_initialize(n);
+    }
+
+    fn finalize_block(n: u64) {
+        Authorship::on_finalize(n);
+        BlockRewards::on_finalize(n);
+        AuraSession::on_finalize(n);
+    }
+
+    #[test]
+    fn halving_curve() {
+        type Curve = Halving<ConstU64<100>, ConstU64<4>>;
+
+        assert_eq!(<Curve as IssuanceCurve<u64, u64>>::block_reward(3), 100);
+        assert_eq!(<Curve as IssuanceCurve<u64, u64>>::block_reward(4), 50);
+        assert_eq!(<Curve as IssuanceCurve<u64, u64>>::block_reward(9), 25);
+        assert_eq!(<Curve as IssuanceCurve<u64, u64>>::block_reward(400), 0);
+        assert_eq!(
+            <Halving<ConstU64<100>, ConstU64<0>> as IssuanceCurve<u64, u64>>::block_reward(400),
+            100
+        );
+    }
+
+    #[test]
+    fn session_author_is_rewarded() {
+        build_ext().execute_with(|| {
+            for n in 1..=4 {
+                initialize_block(n, n);
+                finalize_block(n);
+            }
+
+            // the block starting session 1 is still authored in session 0.
+            assert_eq!(Balances::free_balance(1), 300);
+            assert_eq!(Balances::free_balance(2), 50);
+            System::assert_last_event(
+                Event::BlockRewarded {
+                    author: 2,
+                    amount: 50,
+                }
+                .into(),
+            );
+            assert_eq!(Balances::total_issuance(), 350);
+            assert!(Author::<Test>::get().is_none());
+        });
+    }
+
+    #[test]
+    fn author_takes_a_share_of_the_fees() {
+        build_ext().execute_with(|| {
+            initialize_block(1, 1);
+            BlockRewards::on_unbalanced(Balances::issue(40));
+            assert_eq!(Balances::free_balance(1), 20);
+            assert_eq!(Balances::total_issuance(), 20);
+            finalize_block(1);
+
+            // without an author, all the fees are burned.
+            BlockRewards::on_unbalanced(Balances::issue(40));
+            assert_eq!(Balances::free_balance(1), 120);
+            assert_eq!(Balances::total_issuance(), 120);
+        });
+    }
+}
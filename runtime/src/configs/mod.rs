@@ -33,18 +33,29 @@ use frame_support::{
     },
     PalletId,
 };
-use frame_system::limits::{BlockLength, BlockWeights};
+use frame_system::{
+    limits::{BlockLength, BlockWeights},
+    EnsureRoot,
+};
 use pallet_transaction_payment::{ConstFeeMultiplier, FungibleAdapter, Multiplier};
-use sp_runtime::{traits::One, Perbill};
+use sp_core::crypto::ByteArray;
+use sp_runtime::{
+    traits::{Convert, One},
+    Perbill,
+};
 use sp_version::RuntimeVersion;
 
-use crate::{aura_session, MINUTES, UNIT};
+use crate::{
+    aura_equivocation, aura_session, authority_keys, block_rewards, grandpa_equivocation,
+    session_performance, validator_set, UncheckedExtrinsic, UNIT,
+};
 
 // Local module imports
 use super::{
-    AccountId, Aura, AuraId, Balance, Balances, Block, BlockNumber, Hash, Nonce, PalletInfo,
-    Runtime, RuntimeCall, RuntimeEvent, RuntimeFreezeReason, RuntimeHoldReason, RuntimeOrigin,
-    RuntimeTask, System, EXISTENTIAL_DEPOSIT, SLOT_DURATION, VERSION,
+    AccountId, Aura, AuraEquivocation, AuraId, AuraSession, Balance, Balances, Block, BlockNumber,
+    BlockRewards, GrandpaEquivocation, Hash, Nonce, PalletInfo, Runtime, RuntimeCall, RuntimeEvent,
+    RuntimeFreezeReason, RuntimeHoldReason, RuntimeOrigin, RuntimeTask, SessionPerformance, System,
+    ValidatorSet, DAYS, EXISTENTIAL_DEPOSIT, SLOT_DURATION, VERSION,
 };
 
 const NORMAL_DISPATCH_RATIO: Perbill = Perbill::from_percent(75);
@@ -100,16 +111,23 @@ impl pallet_aura::Config for Runtime {
     type SlotDuration = pallet_aura::MinimumPeriodTimesTwo<Runtime>;
 }
 
+parameter_types! {
+    /// Number of GRANDPA sets whose authorities can still be reported for equivocating.
+    pub const MaxSetIdSessionEntries: u64 = 168;
+    /// Keep GRANDPA equivocation reports in the pool for an hour.
+    pub const ReportLongevity: u64 = 600;
+}
+
 impl pallet_grandpa::Config for Runtime {
     type RuntimeEvent = RuntimeEvent;
 
     type WeightInfo = ();
     type MaxAuthorities = ConstU32<32>;
     type MaxNominators = ConstU32<0>;
-    type MaxSetIdSessionEntries = ConstU64<0>;
+    type MaxSetIdSessionEntries = MaxSetIdSessionEntries;
 
-    type KeyOwnerProof = sp_core::Void;
-    type EquivocationReportSystem = ();
+    type KeyOwnerProof = authority_keys::KeyOwnerProof;
+    type EquivocationReportSystem = GrandpaEquivocation;
 }
 
 impl pallet_timestamp::Config for Runtime {
@@ -145,7 +163,7 @@ parameter_types! {
 
 impl pallet_transaction_payment::Config for Runtime {
     type RuntimeEvent = RuntimeEvent;
-    type OnChargeTransaction = FungibleAdapter<Balances, ()>;
+    type OnChargeTransaction = FungibleAdapter<Balances, BlockRewards>;
     type OperationalFeeMultiplier = ConstU8<5>;
     type WeightToFee = IdentityFee<Balance>;
     type LengthToFee = IdentityFee<Balance>;
@@ -170,6 +188,106 @@ parameter_types! {
     pub const StringLimit: u32 = 128;
 }
 
+parameter_types! {
+    /// Hand the session over to the next authority after a minute without blocks.
+    pub const SessionFallbackTimeout: Option<u64> = Some(10);
+    /// Move sessions on every `SessionLength` blocks.
+    pub const SpinSessionMode: spin_primitives::SessionMode = spin_primitives::SessionMode::Blocks;
+}
+
 impl aura_session::Config for Runtime {
-    type SessionLength = ConstU32<MINUTES>;
+    type RuntimeEvent = RuntimeEvent;
+    type FallbackTimeout = SessionFallbackTimeout;
+    type SessionMode = SpinSessionMode;
+    type DisableOrigin = EnsureRoot<AccountId>;
+    type SessionLengthOrigin = EnsureRoot<AccountId>;
+    type DisabledValidators = AuraEquivocation;
+    // sessions end before the authorities change.
+    type OnNewSession = (SessionPerformance, ValidatorSet);
+}
+
+impl aura_equivocation::Config for Runtime {
+    type RuntimeEvent = RuntimeEvent;
+}
+
+impl authority_keys::Config for Runtime {
+    type RuntimeEvent = RuntimeEvent;
+    type OnSetPruned = GrandpaEquivocation;
+}
+
+/// Account of an Aura key, made of the raw bytes of the key.
+///
+/// This is how the accounts of the development authorities relate to their Aura keys.
+pub struct AccountOfAuraKey;
+
+impl Convert<AuraId, AccountId> for AccountOfAuraKey {
+    fn convert(aura: AuraId) -> AccountId {
+        let raw: [u8; 32] = aura
+            .as_slice()
+            .try_into()
+            .expect("Aura keys are 32 bytes; qed");
+        raw.into()
+    }
+}
+
+impl validator_set::Config for Runtime {
+    type RuntimeEvent = RuntimeEvent;
+    type ValidatorOrigin = EnsureRoot<AccountId>;
+}
+
+impl grandpa_equivocation::Config for Runtime {
+    type RuntimeEvent = RuntimeEvent;
+    type ReportLongevity = ReportLongevity;
+}
+
+parameter_types! {
+    /// Keep the performance of about a day of the default one-minute sessions.
+    pub const SessionHistoryDepth: u32 = 1440;
+    /// Take an authority out of the rotation after it missed three of its sessions in a row.
+    pub const MissedSessionsThreshold: Option<u32> = Some(3);
+}
+
+impl session_performance::Config for Runtime {
+    type RuntimeEvent = RuntimeEvent;
+    type HistoryDepth = SessionHistoryDepth;
+    type MissedSessionsThreshold = MissedSessionsThreshold;
+    type OnMissedSessions = AuraSession;
+}
+
+parameter_types! {
+    /// Reward for the author of each block before the first halving.
+    pub const InitialBlockReward: Balance = UNIT;
+    /// Halve the block reward about every four years.
+    pub const BlockRewardHalvingPeriod: BlockNumber = 4 * 365 * DAYS;
+    /// Half of the fees go to the block author, the other half is burned.
+    pub const AuthorFeeShare: Perbill = Perbill::from_percent(50);
+}
+
+impl pallet_authorship::Config for Runtime {
+    type FindAuthor = authority_keys::FindAccountFromAuthorIndex<Runtime, AuraSession>;
+    type EventHandler = BlockRewards;
+}
+
+impl block_rewards::Config for Runtime {
+    type RuntimeEvent = RuntimeEvent;
+    type Currency = Balances;
+    type IssuanceCurve = block_rewards::Halving<InitialBlockReward, BlockRewardHalvingPeriod>;
+    type AuthorFeeShare = AuthorFeeShare;
+}
+
+impl<LocalCall> frame_system::offchain::CreateTransactionBase<LocalCall> for Runtime
+where
+    RuntimeCall: From<LocalCall>,
+{
+    type Extrinsic = UncheckedExtrinsic;
+    type RuntimeCall = RuntimeCall;
+}
+
+impl<LocalCall> frame_system::offchain::CreateInherent<LocalCall> for Runtime
+where
+    RuntimeCall: From<LocalCall>,
+{
+    fn create_inherent(call: RuntimeCall) -> UncheckedExtrinsic {
+        UncheckedExtrinsic::new_bare(call)
+    }
 }
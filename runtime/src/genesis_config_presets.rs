@@ -15,18 +15,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{AccountId, BalancesConfig, RuntimeGenesisConfig, SudoConfig};
+use crate::{
+    AccountId, AuraId, AuraSessionConfig, AuthorityKeysConfig, BalancesConfig,
+    RuntimeGenesisConfig, SudoConfig, MINUTES,
+};
 use alloc::{vec, vec::Vec};
 use frame_support::build_struct_json_patch;
 use serde_json::Value;
-use spin_primitives::sr25519::AuthorityId as AuraId;
 use sp_consensus_grandpa::AuthorityId as GrandpaId;
 use sp_genesis_builder::{self, PresetId};
 use sp_keyring::Sr25519Keyring;
 
+// Keys of the development authorities, matching the crypto of `AuraId`.
+#[cfg(feature = "ed25519-authorities")]
+use sp_keyring::Ed25519Keyring as AuraKeyring;
+#[cfg(not(feature = "ed25519-authorities"))]
+use sp_keyring::Sr25519Keyring as AuraKeyring;
+
 // Returns the genesis config presets populated with given parameters.
 fn testnet_genesis(
-    initial_authorities: Vec<(AuraId, GrandpaId)>,
+    initial_authorities: Vec<(AccountId, AuraId, GrandpaId)>,
     endowed_accounts: Vec<AccountId>,
     root: AccountId,
 ) -> Value {
@@ -41,15 +49,21 @@ fn testnet_genesis(
         aura: pallet_aura::GenesisConfig {
             authorities: initial_authorities
                 .iter()
-                .map(|x| (x.0.clone()))
+                .map(|x| (x.1.clone()))
                 .collect::<Vec<_>>(),
         },
         grandpa: pallet_grandpa::GenesisConfig {
             authorities: initial_authorities
                 .iter()
-                .map(|x| (x.1.clone(), 1))
+                .map(|x| (x.2.clone(), 1))
                 .collect::<Vec<_>>(),
         },
+        aura_session: AuraSessionConfig {
+            session_length: MINUTES,
+        },
+        authority_keys: AuthorityKeysConfig {
+            keys: initial_authorities,
+        },
         sudo: SudoConfig { key: Some(root) },
     })
 }
@@ -58,7 +72,8 @@ fn testnet_genesis(
 pub fn development_config_genesis() -> Value {
     testnet_genesis(
         vec![(
-            sp_keyring::Sr25519Keyring::Alice.public().into(),
+            Sr25519Keyring::Alice.to_account_id(),
+            AuraKeyring::Alice.public().into(),
             sp_keyring::Ed25519Keyring::Alice.public().into(),
         )],
         vec![
@@ -76,11 +91,13 @@ pub fn local_config_genesis() -> Value {
     testnet_genesis(
         vec![
             (
-                sp_keyring::Sr25519Keyring::Alice.public().into(),
+                Sr25519Keyring::Alice.to_account_id(),
+                AuraKeyring::Alice.public().into(),
                 sp_keyring::Ed25519Keyring::Alice.public().into(),
             ),
             (
-                sp_keyring::Sr25519Keyring::Bob.public().into(),
+                Sr25519Keyring::Bob.to_account_id(),
+                AuraKeyring::Bob.public().into(),
                 sp_keyring::Ed25519Keyring::Bob.public().into(),
             ),
         ],
@@ -0,0 +1,473 @@
+pub use pallet::*;
+
+#[frame_support::pallet]
+pub mod pallet {
+    use crate::{authority_keys, validator_set};
+    use alloc::boxed::Box;
+    use frame_support::pallet_prelude::*;
+    use frame_system::{
+        offchain::{CreateInherent, SubmitTransaction},
+        pallet_prelude::*,
+    };
+    use sp_consensus_grandpa::{EquivocationProof, RoundNumber, SetId};
+    use sp_staking::offence::OffenceReportSystem;
+
+    /// Equivocation proof against a GRANDPA voter of this runtime.
+    pub type EquivocationProofOf<T> =
+        EquivocationProof<<T as frame_system::Config>::Hash, BlockNumberFor<T>>;
+
+    #[pallet::pallet]
+    pub struct Pallet<T>(_);
+
+    #[pallet::config]
+    pub trait Config:
+        frame_system::Config
+        + validator_set::Config
+        + pallet_grandpa::Config<KeyOwnerProof = authority_keys::KeyOwnerProof>
+        + CreateInherent<pallet_grandpa::Call<Self>>
+    {
+        type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
+
+        /// Number of blocks an equivocation report stays valid in the transaction pool.
+        #[pallet::constant]
+        type ReportLongevity: Get<u64>;
+    }
+
+    /// Offenders already reported, by GRANDPA set and round.
+    ///
+    /// Pruned along with the sets of `authority_keys::HistoricalSets`.
+    #[pallet::storage]
+    pub type Reports<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        SetId,
+        Twox64Concat,
+        (RoundNumber, T::AccountId),
+        (),
+        OptionQuery,
+    >;
+
+    #[pallet::event]
+    #[pallet::generate_deposit(pub(super) fn deposit_event)]
+    pub enum Event<T: Config> {
+        /// A GRANDPA voter equivocated. It leaves the validator set at the next session,
+        /// unless it is the last validator.
+        Equivocation {
+            offender: T::AccountId,
+            set_id: SetId,
+            round: RoundNumber,
+        },
+    }
+
+    impl<T: Config> Pallet<T> {
+        /// The offender proven by `key_owner_proof`, if it owned the equivocating key in the
+        /// set of the equivocation.
+        fn offender(
+            equivocation_proof: &EquivocationProofOf<T>,
+            key_owner_proof: &authority_keys::KeyOwnerProof,
+        ) -> Option<T::AccountId> {
+            if key_owner_proof.set_id != equivocation_proof.set_id() {
+                return None;
+            }
+
+            authority_keys::Pallet::<T>::check_key_owner_proof(
+                equivocation_proof.offender(),
+                key_owner_proof,
+            )
+        }
+
+        fn is_reported(
+            equivocation_proof: &EquivocationProofOf<T>,
+            offender: &T::AccountId,
+        ) -> bool {
+            Reports::<T>::contains_key(
+                equivocation_proof.set_id(),
+                (equivocation_proof.round(), offender),
+            )
+        }
+
+        /// Remove the offender from the next validator set.
+        fn punish(offender: T::AccountId, set_id: SetId, round: RoundNumber) {
+            Reports::<T>::insert(set_id, (round, &offender), ());
+
+            if let Err(e) = validator_set::Pallet::<T>::remove(offender.clone()) {
+                log::warn!(
+                    target: "runtime::grandpa_equivocation",
+                    "Keeping equivocating validator {:?}: {:?}",
+                    offender,
+                    e,
+                );
+            }
+
+            Self::deposit_event(Event::Equivocation {
+                offender,
+                set_id,
+                round,
+            });
+        }
+    }
+
+    impl<T: Config>
+        OffenceReportSystem<
+            Option<T::AccountId>,
+            (EquivocationProofOf<T>, authority_keys::KeyOwnerProof),
+        > for Pallet<T>
+    {
+        type Longevity = T::ReportLongevity;
+
+        fn publish_evidence(
+            (equivocation_proof, key_owner_proof): (
+                EquivocationProofOf<T>,
+                authority_keys::KeyOwnerProof,
+            ),
+        ) -> Result<(), ()> {
+            let call = pallet_grandpa::Call::report_equivocation_unsigned {
+                equivocation_proof: Box::new(equivocation_proof),
+                key_owner_proof,
+            };
+            let xt = T::create_inherent(call.into());
+
+            SubmitTransaction::<T, pallet_grandpa::Call<T>>::submit_transaction(xt)
+        }
+
+        fn check_evidence(
+            (equivocation_proof, key_owner_proof): (
+                EquivocationProofOf<T>,
+                authority_keys::KeyOwnerProof,
+            ),
+        ) -> Result<(), TransactionValidityError> {
+            let offender = Self::offender(&equivocation_proof, &key_owner_proof)
+                .ok_or(InvalidTransaction::BadProof)?;
+
+            if Self::is_reported(&equivocation_proof, &offender) {
+                return Err(InvalidTransaction::Stale.into());
+            }
+
+            Ok(())
+        }
+
+        fn process_evidence(
+            _reporter: Option<T::AccountId>,
+            (equivocation_proof, key_owner_proof): (
+                EquivocationProofOf<T>,
+                authority_keys::KeyOwnerProof,
+            ),
+        ) -> Result<(), DispatchError> {
+            let offender = Self::offender(&equivocation_proof, &key_owner_proof)
+                .ok_or(pallet_grandpa::Error::<T>::InvalidKeyOwnershipProof)?;
+
+            ensure!(
+                !Self::is_reported(&equivocation_proof, &offender),
+                pallet_grandpa::Error::<T>::DuplicateOffenceReport
+            );
+
+            let set_id = equivocation_proof.set_id();
+            let round = equivocation_proof.round();
+            ensure!(
+                sp_consensus_grandpa::check_equivocation_proof(equivocation_proof),
+                pallet_grandpa::Error::<T>::InvalidEquivocationProof
+            );
+
+            Self::punish(offender, set_id, round);
+
+            Ok(())
+        }
+    }
+
+    impl<T: Config> authority_keys::OnSetPruned for Pallet<T> {
+        fn on_set_pruned(set_id: SetId) {
+            // offences in the set can't be proven anymore, nor reported twice.
+            let _ = Reports::<T>::clear_prefix(set_id, u32::MAX, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aura_session, authority_keys, validator_set};
+    use frame_support::{assert_noop, assert_ok, derive_impl, parameter_types, traits::Hooks};
+    use sp_consensus_grandpa::{Equivocation, EquivocationProof, RoundNumber, SetId};
+    use sp_core::{ConstBool, ConstU32, ConstU64, H256};
+    use sp_keyring::{Ed25519Keyring, Sr25519Keyring};
+    use sp_runtime::{
+        traits::ValidateUnsigned, transaction_validity::TransactionSource, BuildStorage,
+    };
+
+    type Block = frame_system::mocking::MockBlock<Test>;
+
+    frame_support::construct_runtime!(
+        pub struct Test {
+            System: frame_system,
+            Timestamp: pallet_timestamp,
+            Aura: pallet_aura,
+            Grandpa: pallet_grandpa,
+            AuraSession: aura_session,
+            AuthorityKeys: authority_keys,
+            ValidatorSet: validator_set,
+            GrandpaEquivocation: pallet,
+        }
+    );
+
+    #[derive_impl(frame_system::config_preludes::TestDefaultConfig)]
+    impl frame_system::Config for Test {
+        type Block = Block;
+    }
+
+    impl pallet_timestamp::Config for Test {
+        type Moment = u64;
+        type OnTimestampSet = Aura;
+        type MinimumPeriod = ConstU64<1000>;
+        type WeightInfo = ();
+    }
+
+    impl pallet_aura::Config for Test {
+        type AuthorityId = spin_primitives::sr25519::AuthorityId;
+        type DisabledValidators = ();
+        type MaxAuthorities = ConstU32<3>;
+        type AllowMultipleBlocksPerSlot = ConstBool<false>;
+        type SlotDuration = pallet_aura::MinimumPeriodTimesTwo<Test>;
+    }
+
+    impl pallet_grandpa::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type WeightInfo = ();
+        type MaxAuthorities = ConstU32<3>;
+        type MaxNominators = ConstU32<0>;
+        type MaxSetIdSessionEntries = ConstU64<2>;
+        type KeyOwnerProof = authority_keys::KeyOwnerProof;
+        type EquivocationReportSystem = GrandpaEquivocation;
+    }
+
+    parameter_types! {
+        pub const FallbackTimeout: Option<u64> = None;
+    }
+
+    impl aura_session::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type FallbackTimeout = FallbackTimeout;
+        type SessionMode = ();
+        type DisableOrigin = frame_system::EnsureRoot<u64>;
+        type SessionLengthOrigin = frame_system::EnsureRoot<u64>;
+        type DisabledValidators = ();
+        type OnNewSession = ValidatorSet;
+    }
+
+    impl authority_keys::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type OnSetPruned = GrandpaEquivocation;
+    }
+
+    impl validator_set::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type ValidatorOrigin = frame_system::EnsureRoot<u64>;
+    }
+
+    impl pallet::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type ReportLongevity = ConstU64<10>;
+    }
+
+    impl<C> frame_system::offchain::CreateTransactionBase<C> for Test
+    where
+        RuntimeCall: From<C>,
+    {
+        type Extrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+        type RuntimeCall = RuntimeCall;
+    }
+
+    impl<C> frame_system::offchain::CreateInherent<C> for Test
+    where
+        RuntimeCall: From<C>,
+    {
+        fn create_inherent(call: RuntimeCall) -> Self::Extrinsic {
+            frame_system::mocking::MockUncheckedExtrinsic::<Test>::new_bare(call)
+        }
+    }
+
+    const AUTHORITIES: [(u64, Sr25519Keyring, Ed25519Keyring); 3] = [
+        (1, Sr25519Keyring::Alice, Ed25519Keyring::Alice),
+        (2, Sr25519Keyring::Bob, Ed25519Keyring::Bob),
+        (3, Sr25519Keyring::Charlie, Ed25519Keyring::Charlie),
+    ];
+
+    fn build_ext() -> sp_io::TestExternalities {
+        let mut storage = frame_system::GenesisConfig::<Test>::default()
+            .build_storage()
+            .unwrap();
+        pallet_aura::GenesisConfig::<Test> {
+            authorities: AUTHORITIES
+                .iter()
+                .map(|(_, k, _)| k.public().into())
+                .collect(),
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        pallet_grandpa::GenesisConfig::<Test> {
+            authorities: AUTHORITIES
+                .iter()
+                .map(|(_, _, k)| (k.public().into(), 1))
+                .collect(),
+            ..Default::default()
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        aura_session::GenesisConfig::<Test> { session_length: 3 }
+            .assimilate_storage(&mut storage)
+            .unwrap();
+        authority_keys::GenesisConfig::<Test> {
+            keys: AUTHORITIES
+                .iter()
+                .map(|(who, aura, grandpa)| (*who, aura.public().into(), grandpa.public().into()))
+                .collect(),
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+
+        let mut ext: sp_io::TestExternalities = storage.into();
+        ext.execute_with(|| System::set_block_number(1));
+        ext
+    }
+
+    /// Two conflicting prevotes of `voter`, signed by `signer`.
+    fn equivocation_proof(
+        voter: Ed25519Keyring,
+        signer: Ed25519Keyring,
+        set_id: SetId,
+        round: RoundNumber,
+    ) -> EquivocationProofOf<Test> {
+        let prevote = |byte| finality_grandpa::Prevote {
+            target_hash: H256::repeat_byte(byte),
+            target_number: 1,
+        };
+        let signed = |prevote: finality_grandpa::Prevote<H256, u64>| {
+            let message = finality_grandpa::Message::Prevote(prevote.clone());
+            let payload = sp_consensus_grandpa::localized_payload(round, set_id, &message);
+            (prevote, signer.sign(&payload).into())
+        };
+
+        EquivocationProof::new(
+            set_id,
+            Equivocation::Prevote(finality_grandpa::Equivocation {
+                round_number: round,
+                identity: voter.public().into(),
+                first: signed(prevote(1)),
+                second: signed(prevote(2)),
+            }),
+        )
+    }
+
+    fn key_owner_proof(voter: Ed25519Keyring, set_id: SetId) -> authority_keys::KeyOwnerProof {
+        AuthorityKeys::key_owner_proof(set_id, &voter.public().into()).unwrap()
+    }
+
+    #[test]
+    fn equivocating_voter_leaves_at_the_next_session() {
+        build_ext().execute_with(|| {
+            let proof = equivocation_proof(Ed25519Keyring::Bob, Ed25519Keyring::Bob, 0, 1);
+            let key_owner_proof = key_owner_proof(Ed25519Keyring::Bob, 0);
+
+            assert!(Grandpa::validate_unsigned(
+                TransactionSource::Local,
+                &pallet_grandpa::Call::report_equivocation_unsigned {
+                    equivocation_proof: Box::new(proof.clone()),
+                    key_owner_proof: key_owner_proof.clone(),
+                },
+            )
+            .is_ok());
+            assert_ok!(Grandpa::report_equivocation_unsigned(
+                RuntimeOrigin::none(),
+                Box::new(proof.clone()),
+                key_owner_proof.clone(),
+            ));
+            System::assert_last_event(
+                Event::Equivocation {
+                    offender: 2,
+                    set_id: 0,
+                    round: 1,
+                }
+                .into(),
+            );
+            assert_eq!(ValidatorSet::next_validators().into_inner(), vec![1, 3]);
+
+            // the same offence can't be reported twice.
+            assert_noop!(
+                Grandpa::report_equivocation_unsigned(
+                    RuntimeOrigin::none(),
+                    Box::new(proof),
+                    key_owner_proof,
+                ),
+                pallet_grandpa::Error::<Test>::DuplicateOffenceReport,
+            );
+
+            AuraSession::on_initialize(3);
+            assert_eq!(
+                authority_keys::Validators::<Test>::get().into_inner(),
+                vec![1, 3]
+            );
+
+            // the report is forgotten along with its set, once set 2 starts.
+            Grandpa::on_finalize(3);
+            assert_ok!(ValidatorSet::remove(3));
+            AuraSession::on_initialize(6);
+            assert_eq!(pallet_grandpa::CurrentSetId::<Test>::get(), 2);
+            assert!(authority_keys::HistoricalSets::<Test>::get(0).is_none());
+            assert_eq!(Reports::<Test>::iter_prefix(0).count(), 0);
+        });
+    }
+
+    #[test]
+    fn invalid_reports_are_rejected() {
+        build_ext().execute_with(|| {
+            let report = |proof, key_owner_proof| {
+                Grandpa::report_equivocation_unsigned(
+                    RuntimeOrigin::none(),
+                    Box::new(proof),
+                    key_owner_proof,
+                )
+            };
+
+            // the key owner proof must be for the offender.
+            assert_noop!(
+                report(
+                    equivocation_proof(Ed25519Keyring::Bob, Ed25519Keyring::Bob, 0, 1),
+                    key_owner_proof(Ed25519Keyring::Alice, 0),
+                ),
+                pallet_grandpa::Error::<Test>::InvalidKeyOwnershipProof,
+            );
+
+            // and for the set of the equivocation.
+            assert_noop!(
+                report(
+                    equivocation_proof(Ed25519Keyring::Bob, Ed25519Keyring::Bob, 1, 1),
+                    key_owner_proof(Ed25519Keyring::Bob, 0),
+                ),
+                pallet_grandpa::Error::<Test>::InvalidKeyOwnershipProof,
+            );
+
+            // both votes must be signed by the offender.
+            assert_noop!(
+                report(
+                    equivocation_proof(Ed25519Keyring::Bob, Ed25519Keyring::Charlie, 0, 1),
+                    key_owner_proof(Ed25519Keyring::Bob, 0),
+                ),
+                pallet_grandpa::Error::<Test>::InvalidEquivocationProof,
+            );
+
+            // reports from the network are not accepted into the pool.
+            assert!(Grandpa::validate_unsigned(
+                TransactionSource::External,
+                &pallet_grandpa::Call::report_equivocation_unsigned {
+                    equivocation_proof: Box::new(equivocation_proof(
+                        Ed25519Keyring::Bob,
+                        Ed25519Keyring::Bob,
+                        0,
+                        1
+                    )),
+                    key_owner_proof: key_owner_proof(Ed25519Keyring::Bob, 0),
+                },
+            )
+            .is_err());
+        });
+    }
+}
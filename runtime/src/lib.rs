@@ -4,13 +4,20 @@
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
 pub mod apis;
+pub mod aura_equivocation;
 pub mod aura_session;
+pub mod authority_keys;
 #[cfg(feature = "runtime-benchmarks")]
 mod benchmarks;
+pub mod block_rewards;
 pub mod configs;
+pub mod grandpa_equivocation;
+pub mod session_performance;
+pub mod validator_set;
 
 extern crate alloc;
 use alloc::vec::Vec;
+pub use aura_crypto::AuthorityId as AuraId;
 use sp_runtime::{
     generic, impl_opaque_keys,
     traits::{BlakeTwo256, IdentifyAccount, Verify},
@@ -19,7 +26,12 @@ use sp_runtime::{
 #[cfg(feature = "std")]
 use sp_version::NativeVersion;
 use sp_version::RuntimeVersion;
-pub use spin_primitives::sr25519::AuthorityId as AuraId;
+/// Crypto of the block authorities, Ed25519 as enabled by the `ed25519-authorities` feature.
+#[cfg(feature = "ed25519-authorities")]
+pub use spin_primitives::ed25519 as aura_crypto;
+/// Crypto of the block authorities, Sr25519 unless the `ed25519-authorities` feature is enabled.
+#[cfg(not(feature = "ed25519-authorities"))]
+pub use spin_primitives::sr25519 as aura_crypto;
 
 pub use frame_system::Call as SystemCall;
 pub use pallet_balances::Call as BalancesCall;
@@ -71,7 +83,7 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
     //   `spec_version`, and `authoring_version` are the same between Wasm and native.
     // This value is set to 100 to notify Polkadot-JS App (https://polkadot.js.org/apps) to use
     //   the compatible custom types.
-    spec_version: 100,
+    spec_version: 101,
     impl_version: 1,
     apis: apis::RUNTIME_API_VERSIONS,
     transaction_version: 1,
@@ -174,8 +186,11 @@ pub type SignedPayload = generic::SignedPayload<RuntimeCall, TxExtension>;
 /// All migrations of the runtime, aside from the ones declared in the pallets.
 ///
 /// This can be a tuple of types, each implementing `OnRuntimeUpgrade`.
-#[allow(unused_parens)]
-type Migrations = ();
+type Migrations = (
+    aura_session::SeedSessionLength<Runtime, frame_support::traits::ConstU32<MINUTES>>,
+    authority_keys::SeedAuthorityKeys<Runtime, configs::AccountOfAuraKey>,
+    aura_session::SeedParentSlot<Runtime>,
+);
 
 /// Executive: handles dispatch to the various modules.
 pub type Executive = frame_executive::Executive<
@@ -190,7 +205,9 @@ pub type Executive = frame_executive::Executive<
 // Create the runtime by composing the FRAME pallets that were previously configured.
 #[frame_support::runtime]
 mod runtime {
-    use crate::aura_session;
+    use crate::{
+        aura_equivocation, aura_session, authority_keys, grandpa_equivocation, validator_set,
+    };
 
     #[runtime::runtime]
     #[runtime::derive(
@@ -227,6 +244,60 @@ mod runtime {
     #[runtime::pallet_index(6)]
     pub type Sudo = pallet_sudo;
 
+    // Declared ahead of the session pallet, so the author of the block starting a session is
+    // found before the validators rotate.
+    #[runtime::pallet_index(14)]
+    pub type Authorship = pallet_authorship;
+
+    #[runtime::pallet_index(13)]
+    pub type BlockRewards = block_rewards;
+
     #[runtime::pallet_index(7)]
     pub type AuraSession = aura_session;
+
+    #[runtime::pallet_index(8)]
+    pub type AuraEquivocation = aura_equivocation;
+
+    #[runtime::pallet_index(9)]
+    pub type AuthorityKeys = authority_keys;
+
+    #[runtime::pallet_index(10)]
+    pub type ValidatorSet = validator_set;
+
+    #[runtime::pallet_index(11)]
+    pub type GrandpaEquivocation = grandpa_equivocation;
+
+    #[runtime::pallet_index(12)]
+    pub type SessionPerformance = session_performance;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frame_support::traits::PalletsInfoAccess;
+
+    #[test]
+    fn authorship_runs_before_the_session_rotates() {
+        let position = |name| {
+            AllPalletsWithSystem::infos()
+                .iter()
+                .position(|info| info.name == name)
+                .unwrap()
+        };
+
+        // hooks run in declaration order, whatever the pallet indices.
+        assert!(position("Authorship") < position("AuraSession"));
+    }
+
+    #[test]
+    fn authority_crypto_follows_the_feature() {
+        use sp_runtime::app_crypto::AppCrypto;
+
+        let expected = if cfg!(feature = "ed25519-authorities") {
+            sp_core::ed25519::CRYPTO_ID
+        } else {
+            sp_core::sr25519::CRYPTO_ID
+        };
+        assert_eq!(<AuraId as AppCrypto>::CRYPTO_ID, expected);
+    }
 }
@@ -0,0 +1,603 @@
+pub use pallet::*;
+
+use frame_support::{traits::Get, weights::Weight};
+
+/// Consequence for an authority that built no block in too many of its sessions in a row.
+pub trait OnMissedSessions<AuthorityId> {
+    /// Called with the authority and the number of sessions it missed in a row.
+    fn on_missed_sessions(authority: &AuthorityId, missed: u32) -> Weight;
+}
+
+impl<AuthorityId> OnMissedSessions<AuthorityId> for () {
+    fn on_missed_sessions(_: &AuthorityId, _: u32) -> Weight {
+        Weight::zero()
+    }
+}
+
+/// Takes the authority out of the rotation until the operator enables it again.
+impl<T: crate::aura_session::Config> OnMissedSessions<T::AuthorityId>
+    for crate::aura_session::Pallet<T>
+{
+    fn on_missed_sessions(authority: &T::AuthorityId, missed: u32) -> Weight {
+        let index = pallet_aura::Authorities::<T>::get()
+            .iter()
+            .position(|a| a == authority);
+
+        // the authority may have left the set at the session boundary.
+        if let Some(index) = index {
+            if let Err(e) = Self::disable(index as spin_primitives::AuthorityIndex) {
+                log::warn!(
+                    target: "runtime::session_performance",
+                    "Unable to disable authority {} after {} missed sessions: {:?}",
+                    index,
+                    missed,
+                    e,
+                );
+            }
+        }
+
+        T::DbWeight::get().reads_writes(4, 1)
+    }
+}
+
+#[frame_support::pallet]
+pub mod pallet {
+    use super::OnMissedSessions;
+    use crate::aura_session;
+    use alloc::vec::Vec;
+    use core::ops::Range;
+    use frame_support::pallet_prelude::*;
+    use frame_system::pallet_prelude::*;
+    use spin_primitives::{SessionIndex, SessionMode};
+
+    /// Performance record of a session of this runtime.
+    pub type SessionPerformanceOf<T> =
+        spin_primitives::SessionPerformance<<T as pallet_aura::Config>::AuthorityId>;
+
+    #[pallet::pallet]
+    pub struct Pallet<T>(_);
+
+    #[pallet::config]
+    pub trait Config: frame_system::Config + aura_session::Config {
+        type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
+
+        /// Number of completed sessions to keep the performance of.
+        #[pallet::constant]
+        type HistoryDepth: Get<SessionIndex>;
+
+        /// Number of consecutive missed sessions after which [`Config::OnMissedSessions`] is
+        /// called. `None` disables the consequence.
+        type MissedSessionsThreshold: Get<Option<u32>>;
+
+        /// Consequence for an authority reaching the threshold, e.g. disabling it.
+        type OnMissedSessions: OnMissedSessions<Self::AuthorityId>;
+    }
+
+    /// Performance of the current and the latest completed sessions.
+    #[pallet::storage]
+    pub type Sessions<T: Config> =
+        StorageMap<_, Twox64Concat, SessionIndex, SessionPerformanceOf<T>, OptionQuery>;
+
+    /// Session of the latest block, the one currently recorded.
+    #[pallet::storage]
+    pub type RecordedSession<T: Config> = StorageValue<_, SessionIndex, OptionQuery>;
+
+    /// Number of consecutive sessions an authority was scheduled for and built no block in.
+    #[pallet::storage]
+    pub type MissedSessions<T: Config> =
+        StorageMap<_, Twox64Concat, T::AuthorityId, u32, ValueQuery>;
+
+    #[pallet::event]
+    #[pallet::generate_deposit(pub(super) fn deposit_event)]
+    pub enum Event<T: Config> {
+        /// The scheduled author built no block in its session.
+        SessionMissed {
+            session_index: SessionIndex,
+            author: T::AuthorityId,
+            missed: u32,
+        },
+    }
+
+    #[pallet::hooks]
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+        fn on_initialize(n: BlockNumberFor<T>) -> Weight {
+            let mut weight = T::DbWeight::get().reads(1);
+            let Some((session_index, author)) = aura_session::Author::<T>::get() else {
+                return weight;
+            };
+
+            weight.saturating_accrue(T::DbWeight::get().reads(2));
+            if aura_session::CurrentSessionStart::<T>::get() == n {
+                weight.saturating_accrue(Self::forget_departed_authorities());
+                // noted in `on_new_session`, along with the session it ends.
+                if session_index < aura_session::SessionIndex::<T>::get() {
+                    return weight;
+                }
+            }
+
+            weight.saturating_add(Self::note_block(session_index, &author))
+        }
+    }
+
+    /// Ends the sessions before the authorities change, so that their scheduled authors and
+    /// the authorities to disable are found in the set they were scheduled with.
+    impl<T: Config> aura_session::OnNewSession for Pallet<T> {
+        fn on_new_session(new_session_index: SessionIndex) -> Weight {
+            let mut weight = T::DbWeight::get().reads(2);
+            let Some((session_index, author)) = aura_session::Author::<T>::get() else {
+                return weight;
+            };
+
+            let previous = RecordedSession::<T>::get();
+            if session_index < new_session_index {
+                weight.saturating_accrue(Self::note_block(session_index, &author));
+            }
+
+            let mode = aura_session::Pallet::<T>::session_mode();
+            let (ended, missed) = match (previous, mode) {
+                // following the slots, the block starting a session is authored in it, and the
+                // sessions since the previous block had none.
+                (Some(previous), _) if previous < session_index => {
+                    (Some(previous), previous.saturating_add(1)..session_index)
+                }
+                // the block starting a session is the last one authored in the previous session.
+                (Some(_), _) | (None, SessionMode::Blocks) => (Some(session_index), 0..0),
+                // the previous session is unknown right after the upgrade.
+                (None, SessionMode::Slots(_)) => (None, 0..0),
+            };
+            if let Some(ended) = ended {
+                weight.saturating_accrue(Self::end_session(ended));
+            }
+            weight.saturating_add(Self::end_missed_sessions(missed))
+        }
+    }
+
+    impl<T: Config> Pallet<T> {
+        /// Performance of the given session, if it is still in the history.
+        pub fn session_performance(session_index: SessionIndex) -> Option<SessionPerformanceOf<T>> {
+            Sessions::<T>::get(session_index)
+        }
+
+        /// Number of consecutive sessions the authority was scheduled for and missed.
+        pub fn missed_sessions(authority: &T::AuthorityId) -> u32 {
+            MissedSessions::<T>::get(authority)
+        }
+
+        fn note_block(session_index: SessionIndex, author: &T::AuthorityId) -> Weight {
+            if RecordedSession::<T>::get() != Some(session_index) {
+                RecordedSession::<T>::put(session_index);
+            }
+
+            let slot = pallet_aura::CurrentSlot::<T>::get();
+            // children of the genesis block only take their own slot.
+            let slots = aura_session::ParentSlot::<T>::get()
+                .map_or(1, |parent_slot| (*slot).saturating_sub(*parent_slot));
+
+            Sessions::<T>::mutate(session_index, |performance| {
+                if performance.is_none() {
+                    // opened by the first block authored in the session, so the scheduled
+                    // author follows the authority set of the session.
+                    *performance =
+                        aura_session::Pallet::<T>::author_of(session_index).map(|author| {
+                            SessionPerformanceOf::<T> {
+                                author,
+                                blocks_produced: 0,
+                                blocks: 0,
+                                slots: 0,
+                            }
+                        });
+                }
+
+                if let Some(performance) = performance {
+                    performance.blocks.saturating_inc();
+                    performance.slots.saturating_accrue(slots);
+                    if performance.author == *author {
+                        performance.blocks_produced.saturating_inc();
+                    }
+                }
+            });
+
+            T::DbWeight::get().reads_writes(10, 2)
+        }
+
+        /// Forget the missed sessions of the authorities that left the set.
+        ///
+        /// Done at every session start, so only the authorities of the last two sets have
+        /// entries.
+        fn forget_departed_authorities() -> Weight {
+            let authorities = pallet_aura::Authorities::<T>::get();
+            let mut entries = 0;
+            let mut departed = Vec::new();
+            for authority in MissedSessions::<T>::iter_keys() {
+                entries += 1;
+                if !authorities.contains(&authority) {
+                    departed.push(authority);
+                }
+            }
+
+            let weight = T::DbWeight::get().reads_writes(1 + entries, departed.len() as u64);
+            for authority in departed {
+                MissedSessions::<T>::remove(&authority);
+            }
+
+            weight
+        }
+
+        /// Record the sessions without blocks as missed by their scheduled authors.
+        ///
+        /// Only the sessions still in the history are recorded, the records the older ones
+        /// would have pruned on ending are pruned here.
+        fn end_missed_sessions(missed: Range<SessionIndex>) -> Weight {
+            let depth = T::HistoryDepth::get();
+            let recorded = missed.start.max(missed.end.saturating_sub(depth))..missed.end;
+            // records only exist up to the start of the missed sessions.
+            let pruned = missed.start.saturating_sub(depth)
+                ..recorded.start.saturating_sub(depth).min(missed.start);
+
+            let mut weight = T::DbWeight::get().writes(pruned.len() as u64);
+            for session_index in pruned {
+                Sessions::<T>::remove(session_index);
+            }
+
+            for session_index in recorded {
+                if let Some(author) = aura_session::Pallet::<T>::author_of(session_index) {
+                    Sessions::<T>::insert(
+                        session_index,
+                        SessionPerformanceOf::<T> {
+                            author,
+                            blocks_produced: 0,
+                            blocks: 0,
+                            slots: 0,
+                        },
+                    );
+                }
+                weight.saturating_accrue(T::DbWeight::get().reads_writes(4, 1));
+                weight.saturating_accrue(Self::end_session(session_index));
+            }
+
+            weight
+        }
+
+        fn end_session(session_index: SessionIndex) -> Weight {
+            let mut weight = T::DbWeight::get().reads_writes(1, 1);
+            if let Some(performance) = Sessions::<T>::get(session_index) {
+                weight.saturating_accrue(Self::note_performance(session_index, performance));
+            }
+
+            if let Some(pruned) = session_index.checked_sub(T::HistoryDepth::get()) {
+                Sessions::<T>::remove(pruned);
+            }
+
+            weight
+        }
+
+        fn note_performance(
+            session_index: SessionIndex,
+            performance: SessionPerformanceOf<T>,
+        ) -> Weight {
+            let author = performance.author;
+            if performance.blocks_produced > 0 {
+                MissedSessions::<T>::remove(&author);
+                return T::DbWeight::get().writes(1);
+            }
+
+            let missed = MissedSessions::<T>::mutate(&author, |missed| {
+                missed.saturating_inc();
+                *missed
+            });
+            Self::deposit_event(Event::SessionMissed {
+                session_index,
+                author: author.clone(),
+                missed,
+            });
+
+            let mut weight = T::DbWeight::get().reads_writes(1, 1);
+            if T::MissedSessionsThreshold::get().is_some_and(|threshold| missed >= threshold) {
+                // a fresh count once the consequence is applied, e.g. after being enabled again.
+                MissedSessions::<T>::remove(&author);
+                weight.saturating_accrue(T::OnMissedSessions::on_missed_sessions(&author, missed));
+            }
+
+            weight
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aura_session;
+    use codec::Encode;
+    use frame_support::{derive_impl, parameter_types, traits::Hooks};
+    use sp_core::{ConstBool, ConstU32, ConstU64};
+    use sp_keyring::Sr25519Keyring;
+    use sp_runtime::{BuildStorage, Digest, DigestItem};
+    use spin_primitives::{
+        sr25519::AuthorityId, SessionMode, SessionPerformance, Slot, AURA_ENGINE_ID,
+    };
+
+    type Block = frame_system::mocking::MockBlock<Test>;
+
+    frame_support::construct_runtime!(
+        pub struct Test {
+            System: frame_system,
+            Timestamp: pallet_timestamp,
+            Aura: pallet_aura,
+            AuraSession: aura_session,
+            SessionPerformance: pallet,
+        }
+    );
+
+    #[derive_impl(frame_system::config_preludes::TestDefaultConfig)]
+    impl frame_system::Config for Test {
+        type Block = Block;
+    }
+
+    impl pallet_timestamp::Config for Test {
+        type Moment = u64;
+        type OnTimestampSet = Aura;
+        type MinimumPeriod = ConstU64<1000>;
+        type WeightInfo = ();
+    }
+
+    impl pallet_aura::Config for Test {
+        type AuthorityId = AuthorityId;
+        type DisabledValidators = ();
+        type MaxAuthorities = ConstU32<10>;
+        type AllowMultipleBlocksPerSlot = ConstBool<false>;
+        type SlotDuration = pallet_aura::MinimumPeriodTimesTwo<Test>;
+    }
+
+    const FALLBACK_TIMEOUT: u64 = 2;
+
+    parameter_types! {
+        pub const FallbackTimeout: Option<u64> = Some(FALLBACK_TIMEOUT);
+        pub const MissedSessionsThreshold: Option<u32> = Some(2);
+        pub static Mode: SessionMode = SessionMode::Blocks;
+    }
+
+    impl aura_session::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type FallbackTimeout = FallbackTimeout;
+        type SessionMode = Mode;
+        type DisableOrigin = frame_system::EnsureRoot<u64>;
+        type SessionLengthOrigin = frame_system::EnsureRoot<u64>;
+        type DisabledValidators = ();
+        type OnNewSession = SessionPerformance;
+    }
+
+    impl pallet::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type HistoryDepth = ConstU32<2>;
+        type MissedSessionsThreshold = MissedSessionsThreshold;
+        type OnMissedSessions = AuraSession;
+    }
+
+    fn build_ext() -> sp_io::TestExternalities {
+        let mut storage = frame_system::GenesisConfig::<Test>::default()
+            .build_storage()
+            .unwrap();
+        pallet_aura::GenesisConfig::<Test> {
+            authorities: vec![
+                Sr25519Keyring::Alice.public().into(),
+                Sr25519Keyring::Bob.public().into(),
+                Sr25519Keyring::Charlie.public().into(),
+            ],
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        aura_session::GenesisConfig::<Test> { session_length: 3 }
+            .assimilate_storage(&mut storage)
+            .unwrap();
+
+        storage.into()
+    }
+
+    fn produce_block(slot: u64) {
+        let n = System::block_number() + 1;
+        let digest = Digest {
+            logs: vec![DigestItem::PreRuntime(
+                AURA_ENGINE_ID,
+                Slot::from(slot).encode(),
+            )],
+        };
+        System::initialize(&n, &Default::default(), &digest);
+        pallet_aura::CurrentSlot::<Test>::put(Slot::from(slot));
+        AuraSession::on_initialize(n);
+        SessionPerformance::on_initialize(n);
+        AuraSession::on_finalize(n);
+    }
+
+    /// Produce the blocks authored in the current session, up to the one starting the next.
+    ///
+    /// Blocks are either on time or late enough for the next authority to take over.
+    fn run_session(on_time: bool) {
+        loop {
+            let gap = if on_time { 1 } else { FALLBACK_TIMEOUT + 1 };
+            let parent_slot = aura_session::ParentSlot::<Test>::get().map_or(0, |slot| *slot);
+            produce_block(parent_slot + gap);
+
+            if aura_session::CurrentSessionStart::<Test>::get() == System::block_number() {
+                break;
+            }
+        }
+    }
+
+    fn performance(
+        author: Sr25519Keyring,
+        blocks_produced: u32,
+        slots: u64,
+    ) -> SessionPerformance<AuthorityId> {
+        SessionPerformance {
+            author: author.public().into(),
+            blocks_produced,
+            blocks: 3,
+            slots,
+        }
+    }
+
+    #[test]
+    fn records_the_scheduled_author_of_each_session() {
+        build_ext().execute_with(|| {
+            run_session(true);
+            assert_eq!(
+                Pallet::<Test>::session_performance(0),
+                Some(performance(Sr25519Keyring::Alice, 3, 3)),
+            );
+
+            // Bob times out once and Charlie steps in.
+            produce_block(4);
+            produce_block(8);
+            assert_eq!(
+                aura_session::Author::<Test>::get(),
+                Some((1, Sr25519Keyring::Charlie.public().into())),
+            );
+            produce_block(9);
+            assert_eq!(
+                Pallet::<Test>::session_performance(1),
+                Some(performance(Sr25519Keyring::Bob, 2, 6)),
+            );
+            assert_eq!(
+                Pallet::<Test>::missed_sessions(&Sr25519Keyring::Bob.public().into()),
+                0
+            );
+        });
+    }
+
+    #[test]
+    fn missing_sessions_disables_the_author() {
+        let bob: AuthorityId = Sr25519Keyring::Bob.public().into();
+
+        build_ext().execute_with(|| {
+            run_session(true);
+            run_session(false);
+            assert_eq!(
+                Pallet::<Test>::session_performance(1),
+                Some(performance(Sr25519Keyring::Bob, 0, 9)),
+            );
+            System::assert_last_event(
+                Event::SessionMissed {
+                    session_index: 1,
+                    author: bob.clone(),
+                    missed: 1,
+                }
+                .into(),
+            );
+            assert_eq!(Pallet::<Test>::missed_sessions(&bob), 1);
+
+            run_session(true);
+            run_session(true);
+            // only the latest two completed sessions are kept.
+            assert_eq!(Sessions::<Test>::iter_keys().count(), 2);
+            assert!(Pallet::<Test>::session_performance(1).is_none());
+            assert!(aura_session::DisabledAuthorities::<Test>::get().is_empty());
+
+            run_session(false);
+            System::assert_has_event(
+                Event::SessionMissed {
+                    session_index: 4,
+                    author: bob.clone(),
+                    missed: 2,
+                }
+                .into(),
+            );
+            System::assert_last_event(
+                aura_session::Event::AuthorityDisabled { authority_index: 1 }.into(),
+            );
+            assert_eq!(
+                aura_session::DisabledAuthorities::<Test>::get().into_inner(),
+                vec![1]
+            );
+            assert_eq!(Pallet::<Test>::missed_sessions(&bob), 0);
+            assert_eq!(
+                aura_session::Pallet::<Test>::author_of(7),
+                Some(Sr25519Keyring::Charlie.public().into()),
+            );
+        });
+    }
+
+    #[test]
+    fn departed_authorities_are_forgotten() {
+        let bob: AuthorityId = Sr25519Keyring::Bob.public().into();
+
+        build_ext().execute_with(|| {
+            run_session(true);
+            run_session(false);
+            assert_eq!(Pallet::<Test>::missed_sessions(&bob), 1);
+
+            // Dave takes over from Bob.
+            pallet_aura::Pallet::<Test>::change_authorities(
+                vec![
+                    Sr25519Keyring::Alice.public().into(),
+                    Sr25519Keyring::Dave.public().into(),
+                    Sr25519Keyring::Charlie.public().into(),
+                ]
+                .try_into()
+                .unwrap(),
+            );
+            run_session(true);
+            assert_eq!(MissedSessions::<Test>::iter_keys().count(), 0);
+        });
+    }
+
+    #[test]
+    fn slot_based_sessions_end_at_the_next_session_with_blocks() {
+        Mode::set(SessionMode::Slots(3));
+        build_ext().execute_with(|| {
+            produce_block(1);
+            produce_block(2);
+            // session 1 has no blocks, and Bob steps in for Charlie in session 2.
+            produce_block(7);
+            assert_eq!(
+                aura_session::Author::<Test>::get(),
+                Some((2, Sr25519Keyring::Bob.public().into())),
+            );
+            assert_eq!(
+                Pallet::<Test>::session_performance(0),
+                Some(SessionPerformance {
+                    author: Sr25519Keyring::Alice.public().into(),
+                    blocks_produced: 2,
+                    blocks: 2,
+                    slots: 2,
+                }),
+            );
+            // and Bob missed session 1.
+            assert_eq!(
+                Pallet::<Test>::session_performance(1),
+                Some(SessionPerformance {
+                    author: Sr25519Keyring::Bob.public().into(),
+                    blocks_produced: 0,
+                    blocks: 0,
+                    slots: 0,
+                }),
+            );
+            System::assert_has_event(
+                Event::SessionMissed {
+                    session_index: 1,
+                    author: Sr25519Keyring::Bob.public().into(),
+                    missed: 1,
+                }
+                .into(),
+            );
+
+            produce_block(9);
+            System::assert_last_event(
+                Event::SessionMissed {
+                    session_index: 2,
+                    author: Sr25519Keyring::Charlie.public().into(),
+                    missed: 1,
+                }
+                .into(),
+            );
+
+            // only the skipped sessions still in the history are recorded, and the older
+            // records are pruned.
+            produce_block(18);
+            produce_block(30);
+            assert_eq!(
+                Sessions::<Test>::iter_keys().collect::<std::collections::BTreeSet<_>>(),
+                [8, 9, 10].into(),
+            );
+        });
+    }
+}
@@ -0,0 +1,527 @@
+pub use pallet::*;
+
+#[frame_support::pallet]
+pub mod pallet {
+    use crate::{
+        aura_session,
+        authority_keys::{self, KeysOf, KeysProofOf, ValidatorsOf},
+    };
+    use alloc::vec::Vec;
+    use frame_support::pallet_prelude::*;
+    use frame_system::pallet_prelude::*;
+    use spin_primitives::{AuthorityIndex, SessionIndex};
+
+    #[pallet::pallet]
+    pub struct Pallet<T>(_);
+
+    #[pallet::config]
+    pub trait Config: frame_system::Config + authority_keys::Config {
+        type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
+
+        /// Origin allowed to change the validator set.
+        type ValidatorOrigin: EnsureOrigin<Self::RuntimeOrigin>;
+    }
+
+    /// Validators taking over at the next session boundary.
+    #[pallet::storage]
+    pub type QueuedValidators<T: Config> = StorageValue<_, ValidatorsOf<T>, OptionQuery>;
+
+    #[pallet::event]
+    #[pallet::generate_deposit(pub(super) fn deposit_event)]
+    pub enum Event<T: Config> {
+        /// A validator was queued to join at the next session.
+        ValidatorAdded { who: T::AccountId },
+        /// A validator was queued to leave at the next session.
+        ValidatorRemoved { who: T::AccountId },
+        /// A new validator set was queued for the next session.
+        ValidatorsQueued { validators: Vec<T::AccountId> },
+        /// The queued validator set took over.
+        ValidatorsChanged {
+            session_index: SessionIndex,
+            validators: Vec<T::AccountId>,
+        },
+    }
+
+    #[pallet::error]
+    pub enum Error<T> {
+        /// The account is already in the next validator set.
+        AlreadyValidator,
+        /// The account is not in the next validator set.
+        NotValidator,
+        /// The validator set can't hold more authorities.
+        TooManyValidators,
+        /// The validator set can't be empty.
+        NoValidators,
+        /// The account appears more than once in the validator set.
+        DuplicateValidator,
+        /// The account has no keys registered.
+        NoKeys,
+        /// Every validator of the next set would be disabled.
+        NoEnabledValidator,
+    }
+
+    #[pallet::call]
+    impl<T: Config> Pallet<T> {
+        /// Add a validator with the given keys from the next session on.
+        ///
+        /// See `authority_keys::Pallet::set_keys` for the ownership proof of the keys.
+        #[pallet::call_index(0)]
+        #[pallet::weight(T::DbWeight::get().reads_writes(
+            3 + 2 * u64::from(<T as pallet_aura::Config>::MaxAuthorities::get()),
+            2,
+        ))]
+        pub fn add_validator(
+            origin: OriginFor<T>,
+            who: T::AccountId,
+            keys: KeysOf<T>,
+            proof: KeysProofOf<T>,
+        ) -> DispatchResult {
+            T::ValidatorOrigin::ensure_origin(origin)?;
+
+            let mut validators = Self::next_validators();
+            ensure!(!validators.contains(&who), Error::<T>::AlreadyValidator);
+            validators
+                .try_push(who.clone())
+                .map_err(|_| Error::<T>::TooManyValidators)?;
+
+            authority_keys::Pallet::<T>::queue_keys(who.clone(), keys, proof)?;
+            QueuedValidators::<T>::put(validators);
+            Self::deposit_event(Event::ValidatorAdded { who });
+
+            Ok(())
+        }
+
+        /// Remove a validator from the next session on.
+        #[pallet::call_index(1)]
+        #[pallet::weight(T::DbWeight::get().reads_writes(3, 1))]
+        pub fn remove_validator(origin: OriginFor<T>, who: T::AccountId) -> DispatchResult {
+            T::ValidatorOrigin::ensure_origin(origin)?;
+
+            Self::remove(who)
+        }
+
+        /// Replace the validator set from the next session on.
+        ///
+        /// Every validator must either be one already or have keys queued, e.g. through
+        /// [`Pallet::add_validator`] earlier in the session.
+        #[pallet::call_index(2)]
+        #[pallet::weight(T::DbWeight::get().reads_writes(
+            2 * u64::from(<T as pallet_aura::Config>::MaxAuthorities::get()),
+            1,
+        ))]
+        pub fn queue_validators(
+            origin: OriginFor<T>,
+            validators: ValidatorsOf<T>,
+        ) -> DispatchResult {
+            T::ValidatorOrigin::ensure_origin(origin)?;
+
+            ensure!(!validators.is_empty(), Error::<T>::NoValidators);
+            for (i, who) in validators.iter().enumerate() {
+                ensure!(
+                    !validators[..i].contains(who),
+                    Error::<T>::DuplicateValidator
+                );
+                ensure!(
+                    authority_keys::CurrentKeys::<T>::contains_key(who)
+                        || authority_keys::QueuedKeys::<T>::contains_key(who),
+                    Error::<T>::NoKeys
+                );
+            }
+
+            QueuedValidators::<T>::put(&validators);
+            Self::deposit_event(Event::ValidatorsQueued {
+                validators: validators.into_inner(),
+            });
+
+            Ok(())
+        }
+    }
+
+    impl<T: Config> Pallet<T> {
+        /// The validator set of the next session.
+        pub fn next_validators() -> ValidatorsOf<T> {
+            QueuedValidators::<T>::get().unwrap_or_else(authority_keys::Validators::<T>::get)
+        }
+
+        /// Queue the removal of a validator from the next session on.
+        ///
+        /// The last validator, or the last one not disabled by the operator, can't be removed.
+        pub fn remove(who: T::AccountId) -> DispatchResult {
+            let mut validators = Self::next_validators();
+            let position = validators
+                .iter()
+                .position(|validator| *validator == who)
+                .ok_or(Error::<T>::NotValidator)?;
+            validators.remove(position);
+            ensure!(!validators.is_empty(), Error::<T>::NoValidators);
+
+            let current = authority_keys::Validators::<T>::get();
+            let disabled = Self::disabled_validators(&current);
+            ensure!(
+                validators
+                    .iter()
+                    .any(|validator| !disabled.contains(&validator)),
+                Error::<T>::NoEnabledValidator
+            );
+
+            QueuedValidators::<T>::put(validators);
+            Self::deposit_event(Event::ValidatorRemoved { who });
+
+            Ok(())
+        }
+
+        /// The validators of the `current` set disabled by the operator.
+        ///
+        /// They stay disabled in the next set, see [`Self::remap_disabled`].
+        fn disabled_validators(current: &[T::AccountId]) -> Vec<&T::AccountId> {
+            aura_session::DisabledAuthorities::<T>::get()
+                .iter()
+                .filter_map(|index| current.get(*index as usize))
+                .collect()
+        }
+
+        /// Move the indices of the authorities disabled by the operator to the new set.
+        ///
+        /// Authorities leaving the set are no longer disabled.
+        fn remap_disabled(previous: &[T::AccountId], next: &[T::AccountId]) {
+            let mut disabled = Self::disabled_validators(previous)
+                .into_iter()
+                .filter_map(|who| next.iter().position(|validator| validator == who))
+                .map(|index| index as AuthorityIndex)
+                .collect::<Vec<_>>();
+            disabled.sort();
+
+            aura_session::DisabledAuthorities::<T>::put(
+                BoundedVec::try_from(disabled).expect("at most as many as before; qed"),
+            );
+        }
+    }
+
+    impl<T: Config> aura_session::OnNewSession for Pallet<T> {
+        fn on_new_session(session_index: SessionIndex) -> Weight {
+            let queued = QueuedValidators::<T>::get();
+            let previous = authority_keys::Validators::<T>::get();
+
+            let changed = queued.is_some();
+            let weight = match authority_keys::Pallet::<T>::change_authorities(
+                session_index,
+                queued.clone(),
+            ) {
+                Ok(keys_changed) => {
+                    if let Some(validators) = queued {
+                        QueuedValidators::<T>::kill();
+                        Self::remap_disabled(&previous, &validators);
+                        Self::deposit_event(Event::ValidatorsChanged {
+                            session_index,
+                            validators: validators.into_inner(),
+                        });
+                    }
+
+                    if keys_changed || changed {
+                        authority_keys::Pallet::<T>::change_authorities_weight()
+                    } else {
+                        authority_keys::Pallet::<T>::keep_authorities_weight()
+                    }
+                }
+                Err(e) => {
+                    log::warn!(
+                        target: "runtime::validator_set",
+                        "Postponing authority changes to the next session: {:?}",
+                        e,
+                    );
+                    authority_keys::Pallet::<T>::change_authorities_weight()
+                }
+            };
+
+            weight.saturating_add(T::DbWeight::get().reads_writes(3, 2))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aura_session,
+        authority_keys::{self, Keys, KeysProof, KEYS_PROOF_CONTEXT},
+    };
+    use codec::Encode;
+    use frame_support::{
+        assert_noop, assert_ok, derive_impl, parameter_types, traits::Hooks, BoundedVec,
+    };
+    use sp_consensus_grandpa::AuthorityPair as GrandpaPair;
+    use sp_core::{ConstBool, ConstU32, ConstU64, Pair};
+    use sp_keyring::{Ed25519Keyring, Sr25519Keyring};
+    use sp_runtime::{BuildStorage, DispatchError};
+    use spin_primitives::sr25519::AuthorityPair as AuraPair;
+
+    type Block = frame_system::mocking::MockBlock<Test>;
+
+    frame_support::construct_runtime!(
+        pub struct Test {
+            System: frame_system,
+            Timestamp: pallet_timestamp,
+            Aura: pallet_aura,
+            Grandpa: pallet_grandpa,
+            AuraSession: aura_session,
+            AuthorityKeys: authority_keys,
+            ValidatorSet: pallet,
+        }
+    );
+
+    #[derive_impl(frame_system::config_preludes::TestDefaultConfig)]
+    impl frame_system::Config for Test {
+        type Block = Block;
+    }
+
+    impl pallet_timestamp::Config for Test {
+        type Moment = u64;
+        type OnTimestampSet = Aura;
+        type MinimumPeriod = ConstU64<1000>;
+        type WeightInfo = ();
+    }
+
+    impl pallet_aura::Config for Test {
+        type AuthorityId = spin_primitives::sr25519::AuthorityId;
+        type DisabledValidators = ();
+        type MaxAuthorities = ConstU32<3>;
+        type AllowMultipleBlocksPerSlot = ConstBool<false>;
+        type SlotDuration = pallet_aura::MinimumPeriodTimesTwo<Test>;
+    }
+
+    impl pallet_grandpa::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type WeightInfo = ();
+        type MaxAuthorities = ConstU32<3>;
+        type MaxNominators = ConstU32<0>;
+        type MaxSetIdSessionEntries = ConstU64<0>;
+        type KeyOwnerProof = sp_core::Void;
+        type EquivocationReportSystem = ();
+    }
+
+    parameter_types! {
+        pub const FallbackTimeout: Option<u64> = None;
+    }
+
+    impl aura_session::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type FallbackTimeout = FallbackTimeout;
+        type SessionMode = ();
+        type DisableOrigin = frame_system::EnsureRoot<u64>;
+        type SessionLengthOrigin = frame_system::EnsureRoot<u64>;
+        type DisabledValidators = ();
+        type OnNewSession = ValidatorSet;
+    }
+
+    impl authority_keys::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type OnSetPruned = ();
+    }
+
+    impl pallet::Config for Test {
+        type RuntimeEvent = RuntimeEvent;
+        type ValidatorOrigin = frame_system::EnsureRoot<u64>;
+    }
+
+    fn build_ext() -> sp_io::TestExternalities {
+        let authorities = [
+            (1, Sr25519Keyring::Alice, Ed25519Keyring::Alice),
+            (2, Sr25519Keyring::Bob, Ed25519Keyring::Bob),
+        ];
+
+        let mut storage = frame_system::GenesisConfig::<Test>::default()
+            .build_storage()
+            .unwrap();
+        pallet_aura::GenesisConfig::<Test> {
+            authorities: authorities
+                .iter()
+                .map(|(_, k, _)| k.public().into())
+                .collect(),
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        pallet_grandpa::GenesisConfig::<Test> {
+            authorities: authorities
+                .iter()
+                .map(|(_, _, k)| (k.public().into(), 1))
+                .collect(),
+            ..Default::default()
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+        aura_session::GenesisConfig::<Test> { session_length: 3 }
+            .assimilate_storage(&mut storage)
+            .unwrap();
+        authority_keys::GenesisConfig::<Test> {
+            keys: authorities
+                .iter()
+                .map(|(who, aura, grandpa)| (*who, aura.public().into(), grandpa.public().into()))
+                .collect(),
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+
+        let mut ext: sp_io::TestExternalities = storage.into();
+        ext.execute_with(|| System::set_block_number(1));
+        ext
+    }
+
+    fn keys_of(who: u64) -> (KeysOf<Test>, KeysProofOf<Test>) {
+        let aura = AuraPair::generate().0;
+        let grandpa = GrandpaPair::generate().0;
+        let payload = (KEYS_PROOF_CONTEXT, who).encode();
+        (
+            Keys {
+                aura: aura.public(),
+                grandpa: grandpa.public(),
+            },
+            KeysProof {
+                aura: aura.sign(&payload),
+                grandpa: grandpa.sign(&payload),
+            },
+        )
+    }
+
+    #[test]
+    fn validators_change_at_the_next_session() {
+        build_ext().execute_with(|| {
+            let (keys, proof) = keys_of(3);
+
+            assert_noop!(
+                ValidatorSet::add_validator(
+                    RuntimeOrigin::signed(3),
+                    3,
+                    keys.clone(),
+                    proof.clone()
+                ),
+                DispatchError::BadOrigin,
+            );
+            assert_noop!(
+                ValidatorSet::add_validator(RuntimeOrigin::root(), 2, keys.clone(), proof.clone()),
+                Error::<Test>::AlreadyValidator,
+            );
+
+            assert_ok!(ValidatorSet::add_validator(
+                RuntimeOrigin::root(),
+                3,
+                keys.clone(),
+                proof
+            ));
+            System::assert_last_event(Event::ValidatorAdded { who: 3 }.into());
+
+            // bounded by the maximum number of authorities.
+            let (other, other_proof) = keys_of(4);
+            assert_noop!(
+                ValidatorSet::add_validator(RuntimeOrigin::root(), 4, other, other_proof),
+                Error::<Test>::TooManyValidators,
+            );
+
+            // Bob leaves the set, and with it the disabled authorities.
+            assert_ok!(ValidatorSet::remove_validator(RuntimeOrigin::root(), 2));
+            assert_eq!(ValidatorSet::next_validators().into_inner(), vec![1, 3]);
+            aura_session::DisabledAuthorities::<Test>::put(BoundedVec::try_from(vec![1]).unwrap());
+
+            AuraSession::on_initialize(2);
+            assert_eq!(pallet_aura::Authorities::<Test>::get().len(), 2);
+            assert_eq!(
+                authority_keys::Validators::<Test>::get().into_inner(),
+                vec![1, 2]
+            );
+
+            AuraSession::on_initialize(3);
+            assert_eq!(
+                authority_keys::Validators::<Test>::get().into_inner(),
+                vec![1, 3]
+            );
+            assert_eq!(
+                pallet_aura::Authorities::<Test>::get().into_inner(),
+                vec![Sr25519Keyring::Alice.public().into(), keys.aura.clone()]
+            );
+            assert!(authority_keys::CurrentKeys::<Test>::get(2).is_none());
+            assert!(QueuedValidators::<Test>::get().is_none());
+            assert!(aura_session::DisabledAuthorities::<Test>::get().is_empty());
+
+            Grandpa::on_finalize(3);
+            assert_eq!(
+                Grandpa::grandpa_authorities(),
+                vec![
+                    (Ed25519Keyring::Alice.public().into(), 1),
+                    (keys.grandpa, 1)
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn queued_validators_need_keys() {
+        build_ext().execute_with(|| {
+            assert_noop!(
+                ValidatorSet::queue_validators(RuntimeOrigin::root(), Default::default()),
+                Error::<Test>::NoValidators,
+            );
+            assert_noop!(
+                ValidatorSet::queue_validators(
+                    RuntimeOrigin::root(),
+                    BoundedVec::try_from(vec![1, 1]).unwrap()
+                ),
+                Error::<Test>::DuplicateValidator,
+            );
+            assert_noop!(
+                ValidatorSet::queue_validators(
+                    RuntimeOrigin::root(),
+                    BoundedVec::try_from(vec![1, 3]).unwrap()
+                ),
+                Error::<Test>::NoKeys,
+            );
+
+            assert_ok!(ValidatorSet::queue_validators(
+                RuntimeOrigin::root(),
+                BoundedVec::try_from(vec![2, 1]).unwrap()
+            ));
+            System::assert_last_event(
+                Event::ValidatorsQueued {
+                    validators: vec![2, 1],
+                }
+                .into(),
+            );
+
+            AuraSession::on_initialize(3);
+            System::assert_last_event(
+                Event::ValidatorsChanged {
+                    session_index: 1,
+                    validators: vec![2, 1],
+                }
+                .into(),
+            );
+            assert_eq!(
+                pallet_aura::Authorities::<Test>::get().into_inner(),
+                vec![
+                    Sr25519Keyring::Bob.public().into(),
+                    Sr25519Keyring::Alice.public().into()
+                ]
+            );
+        });
+    }
+    #[test]
+    fn an_enabled_validator_must_remain() {
+        build_ext().execute_with(|| {
+            assert_noop!(
+                ValidatorSet::remove_validator(RuntimeOrigin::root(), 3),
+                Error::<Test>::NotValidator,
+            );
+
+            // Alice is disabled, so Bob is the last enabled validator.
+            assert_ok!(AuraSession::disable_authority(RuntimeOrigin::root(), 0));
+            assert_noop!(
+                ValidatorSet::remove_validator(RuntimeOrigin::root(), 2),
+                Error::<Test>::NoEnabledValidator,
+            );
+
+            assert_ok!(ValidatorSet::remove_validator(RuntimeOrigin::root(), 1));
+            assert_noop!(
+                ValidatorSet::remove_validator(RuntimeOrigin::root(), 2),
+                Error::<Test>::NoValidators,
+            );
+        });
+    }
+}